@@ -31,19 +31,30 @@ fn main() {
     let proto_path = Path::new("./protos");
     fs::create_dir_all(&dest_path).unwrap();
 
+    let mut protos = vec![
+        "batch",
+        "transaction",
+        "events",
+        "transaction_receipt",
+        "merkle",
+        "subprocess",
+    ];
+    // The Sawtooth validator messages are only used by the sawtooth-compat adapter
+    if env::var("CARGO_FEATURE_SAWTOOTH_COMPAT").is_ok() {
+        protos.extend(&["validator", "processor", "state_context"]);
+    }
+    let proto_files: Vec<_> = protos
+        .iter()
+        .map(|proto| proto_path.join(format!("{}.proto", proto)))
+        .collect();
+
     // Run protoc
     protoc_rust::run(protoc_rust::Args {
         out_dir: &dest_path.to_str().unwrap(),
-        input: &[
-            proto_path.join("batch.proto").to_str().unwrap(),
-            proto_path.join("transaction.proto").to_str().unwrap(),
-            proto_path.join("events.proto").to_str().unwrap(),
-            proto_path
-                .join("transaction_receipt.proto")
-                .to_str()
-                .unwrap(),
-            proto_path.join("merkle.proto").to_str().unwrap(),
-        ],
+        input: &proto_files
+            .iter()
+            .map(|file| file.to_str().unwrap())
+            .collect::<Vec<_>>(),
         includes: &[proto_path.to_str().unwrap()],
         customize: Customize::default(),
     })
@@ -51,7 +62,7 @@ fn main() {
 
     // Create mod.rs accordingly
     let mut mod_file = File::create(dest_path.join("mod.rs")).unwrap();
-    mod_file
-        .write_all(b"pub mod batch;\npub mod events;\npub mod transaction;\npub mod transaction_receipt;\npub mod merkle;\n")
-        .unwrap();
+    for proto in protos {
+        writeln!(mod_file, "pub mod {};", proto).unwrap();
+    }
 }
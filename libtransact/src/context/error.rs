@@ -17,29 +17,40 @@
 use std::error::Error;
 
 use crate::protocol::receipt::TransactionReceiptBuilderError;
-use crate::state::error::StateReadError;
+use crate::state::error::{StateReadError, StateWriteError};
 
 #[derive(Debug)]
 pub enum ContextManagerError {
     MissingContextError(String),
+    /// Returned when a context is used to access an address it is not permitted to.
+    AuthorizationError(String),
+    /// Returned when a context is rolled back to a savepoint which is not valid for it.
+    InvalidSavepoint(String),
     TransactionReceiptBuilderError(TransactionReceiptBuilderError),
     StateReadError(StateReadError),
+    StateWriteError(StateWriteError),
 }
 
 impl Error for ContextManagerError {
     fn description(&self) -> &str {
         match *self {
             ContextManagerError::MissingContextError(ref msg) => msg,
+            ContextManagerError::AuthorizationError(ref msg) => msg,
+            ContextManagerError::InvalidSavepoint(ref msg) => msg,
             ContextManagerError::TransactionReceiptBuilderError(ref err) => err.description(),
             ContextManagerError::StateReadError(ref err) => err.description(),
+            ContextManagerError::StateWriteError(ref err) => err.description(),
         }
     }
 
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             ContextManagerError::MissingContextError(_) => Some(self),
+            ContextManagerError::AuthorizationError(_) => None,
+            ContextManagerError::InvalidSavepoint(_) => None,
             ContextManagerError::TransactionReceiptBuilderError(ref err) => Some(err),
             ContextManagerError::StateReadError(ref err) => Some(err),
+            ContextManagerError::StateWriteError(ref err) => Some(err),
         }
     }
 }
@@ -50,12 +61,19 @@ impl std::fmt::Display for ContextManagerError {
             ContextManagerError::MissingContextError(ref s) => {
                 write!(f, "Unable to find specified Context: {:?}", s)
             }
+            ContextManagerError::AuthorizationError(ref s) => {
+                write!(f, "Address access not permitted: {}", s)
+            }
+            ContextManagerError::InvalidSavepoint(ref s) => write!(f, "Invalid savepoint: {}", s),
             ContextManagerError::TransactionReceiptBuilderError(ref err) => {
                 write!(f, "A TransactionReceiptBuilder error occured: {}", err)
             }
             ContextManagerError::StateReadError(ref err) => {
                 write!(f, "A State Read error occured: {}", err)
             }
+            ContextManagerError::StateWriteError(ref err) => {
+                write!(f, "A State Write error occured: {}", err)
+            }
         }
     }
 }
@@ -71,3 +89,9 @@ impl From<StateReadError> for ContextManagerError {
         ContextManagerError::StateReadError(err)
     }
 }
+
+impl From<StateWriteError> for ContextManagerError {
+    fn from(err: StateWriteError) -> Self {
+        ContextManagerError::StateWriteError(err)
+    }
+}
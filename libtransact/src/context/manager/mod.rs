@@ -17,17 +17,24 @@
  */
 pub mod sync;
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::str;
 
 pub use crate::context::error::ContextManagerError;
-use crate::context::{Context, ContextId, ContextLifecycle};
+use crate::context::{Context, ContextId, ContextLifecycle, Savepoint};
 use crate::protocol::receipt::{Event, StateChange, TransactionReceipt, TransactionReceiptBuilder};
-use crate::state::Read;
+use crate::state;
+use crate::state::{Read, Write};
 
 pub struct ContextManager {
     contexts: HashMap<ContextId, Context>,
+    /// The number of contexts which list each context as one of their base contexts.
+    context_refs: HashMap<ContextId, usize>,
+    /// Contexts which have been dropped, but are still the base context of another context.
+    dropped_contexts: HashSet<ContextId>,
     database: Box<dyn Read<StateId = String, Key = String, Value = Vec<u8>>>,
 }
 
@@ -35,12 +42,48 @@ impl ContextLifecycle for ContextManager {
     /// Creates a Context, and returns the resulting ContextId.
     fn create_context(&mut self, dependent_contexts: &[ContextId], state_id: &str) -> ContextId {
         let new_context = Context::new(state_id, dependent_contexts.to_vec());
+        for base_context_id in dependent_contexts {
+            *self.context_refs.entry(*base_context_id).or_insert(0) += 1;
+        }
         self.contexts.insert(*new_context.id(), new_context.clone());
         *new_context.id()
     }
 
-    fn drop_context(&mut self, _context_id: ContextId) {
-        unimplemented!();
+    /// Drops a Context, removing it once no other Context lists it as a base context.
+    ///
+    /// When a Context is removed, any of its base contexts which have also been dropped and are
+    /// no longer referenced are removed as well.
+    fn drop_context(&mut self, context_id: ContextId) {
+        if !self.contexts.contains_key(&context_id) {
+            return;
+        }
+
+        if self.context_refs.contains_key(&context_id) {
+            self.dropped_contexts.insert(context_id);
+            return;
+        }
+
+        let mut unreferenced = vec![context_id];
+        while let Some(context_id) = unreferenced.pop() {
+            self.dropped_contexts.remove(&context_id);
+            if let Some(context) = self.contexts.remove(&context_id) {
+                for base_context_id in context.base_contexts() {
+                    let remaining = match self.context_refs.get_mut(base_context_id) {
+                        Some(count) => {
+                            *count -= 1;
+                            *count
+                        }
+                        None => continue,
+                    };
+                    if remaining == 0 {
+                        self.context_refs.remove(base_context_id);
+                        if self.dropped_contexts.contains(base_context_id) {
+                            unreferenced.push(*base_context_id);
+                        }
+                    }
+                }
+            }
+        }
     }
 
     /// Creates a TransactionReceipt based on the information available within the specified Context.
@@ -64,6 +107,8 @@ impl ContextManager {
     pub fn new(database: Box<dyn Read<StateId = String, Key = String, Value = Vec<u8>>>) -> Self {
         ContextManager {
             contexts: HashMap::new(),
+            context_refs: HashMap::new(),
+            dropped_contexts: HashSet::new(),
             database,
         }
     }
@@ -93,6 +138,21 @@ impl ContextManager {
         })
     }
 
+    /// Restricts the addresses which may be accessed through the specified Context.
+    ///
+    /// Reads are limited to addresses starting with one of the input prefixes, and writes and
+    /// deletes to addresses starting with one of the output prefixes.
+    pub fn set_address_permissions(
+        &mut self,
+        context_id: &ContextId,
+        inputs: Vec<String>,
+        outputs: Vec<String>,
+    ) -> Result<(), ContextManagerError> {
+        let context = self.get_context_mut(context_id)?;
+        context.set_address_permissions(inputs, outputs);
+        Ok(())
+    }
+
     /// Get the values associated with list of keys, from a specific Context.
     /// If a key is not found in the context, State is then checked for these keys.
     /// Keys are returned with the associated value, if found in Context or State.
@@ -101,6 +161,14 @@ impl ContextManager {
         context_id: &ContextId,
         keys: &[String],
     ) -> Result<Vec<(String, Vec<u8>)>, ContextManagerError> {
+        let current_context = self.get_context(context_id)?;
+        if let Some(key) = keys.iter().find(|key| !current_context.can_read(key)) {
+            return Err(ContextManagerError::AuthorizationError(format!(
+                "{} is not in the transaction's inputs",
+                key
+            )));
+        }
+
         let mut key_values = Vec::new();
         for key in keys.iter().rev() {
             let mut context = self.get_context(context_id)?;
@@ -141,6 +209,51 @@ impl ContextManager {
         Ok(key_values)
     }
 
+    /// Lists the keys and values starting with the given prefix, as seen from a specific Context,
+    /// ordered by key.
+    ///
+    /// The StateChanges of the Context and all of its base contexts are applied, as they are by
+    /// `squash`, to the entries under the prefix in the Context's state, so keys set in a Context
+    /// are listed and keys deleted in a Context are not. Listing a prefix which is not in the
+    /// Context's inputs returns an `AuthorizationError`.
+    pub fn list(
+        &self,
+        context_id: &ContextId,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextManagerError> {
+        let context = self.get_context(context_id)?;
+        if !context.can_read(prefix) {
+            return Err(ContextManagerError::AuthorizationError(format!(
+                "{} is not in the transaction's inputs",
+                prefix
+            )));
+        }
+
+        let mut entries = BTreeMap::new();
+        for entry in self
+            .database
+            .iter_prefix(context.state_id(), Some(&prefix.to_string()))?
+        {
+            let (key, value) = entry?;
+            entries.insert(key, value);
+        }
+
+        for state_change in self.squash(context_id)? {
+            match state_change {
+                state::StateChange::Set { key, value } => {
+                    if key.starts_with(prefix) {
+                        entries.insert(key, value);
+                    }
+                }
+                state::StateChange::Delete { key } => {
+                    entries.remove(&key);
+                }
+            }
+        }
+
+        Ok(entries.into_iter().collect())
+    }
+
     /// Adds a StateChange::Set to the specified Context
     pub fn set_state(
         &mut self,
@@ -149,6 +262,12 @@ impl ContextManager {
         value: Vec<u8>,
     ) -> Result<(), ContextManagerError> {
         let context = self.get_context_mut(context_id)?;
+        if !context.can_write(&key) {
+            return Err(ContextManagerError::AuthorizationError(format!(
+                "{} is not in the transaction's outputs",
+                key
+            )));
+        }
         context.set_state(key, value);
         Ok(())
     }
@@ -160,6 +279,13 @@ impl ContextManager {
         context_id: &ContextId,
         key: &str,
     ) -> Result<Option<Vec<u8>>, ContextManagerError> {
+        if !self.get_context(context_id)?.can_write(key) {
+            return Err(ContextManagerError::AuthorizationError(format!(
+                "{} is not in the transaction's outputs",
+                key
+            )));
+        }
+
         // Adding a StateChange::Delete to the specified Context, which will occur no matter which
         // Context or State the key and associated value is found in.
         let context_value = self.get_context_mut(context_id)?.delete_state(key);
@@ -201,6 +327,91 @@ impl ContextManager {
         Ok(None)
     }
 
+    /// Returns a savepoint for the current state changes, events and data of the specified Context.
+    pub fn savepoint(&self, context_id: &ContextId) -> Result<Savepoint, ContextManagerError> {
+        Ok(self.get_context(context_id)?.savepoint())
+    }
+
+    /// Rolls the specified Context back to the given savepoint, discarding the state changes,
+    /// events and data added since it was taken.
+    pub fn rollback_to(
+        &mut self,
+        context_id: &ContextId,
+        savepoint: &Savepoint,
+    ) -> Result<(), ContextManagerError> {
+        self.get_context_mut(context_id)?.rollback_to(savepoint)
+    }
+
+    /// Squashes the StateChanges of the specified Context and all of its base contexts into the
+    /// minimal list of StateChanges which produces the same resulting state.
+    ///
+    /// Each key takes its value from the Context which `get` would read it from: the Context
+    /// itself, or else the first of its base contexts, searched breadth-first, which changes the
+    /// key. The result holds a single StateChange per key, ordered by key.
+    pub fn squash(
+        &self,
+        context_id: &ContextId,
+    ) -> Result<Vec<state::StateChange>, ContextManagerError> {
+        let mut squashed = BTreeMap::new();
+        for context in self.context_chain(context_id)? {
+            for state_change in context.state_changes() {
+                match state_change {
+                    StateChange::Set { key, value } => squashed.insert(
+                        key.clone(),
+                        state::StateChange::Set {
+                            key: key.clone(),
+                            value: value.clone(),
+                        },
+                    ),
+                    StateChange::Delete { key } => squashed
+                        .insert(key.clone(), state::StateChange::Delete { key: key.clone() }),
+                };
+            }
+        }
+        Ok(squashed.into_iter().map(|(_, change)| change).collect())
+    }
+
+    /// Squashes the StateChanges of the specified Context, as `squash` does, and computes the
+    /// state ID that results from applying them to the Context's state ID with the given Write.
+    ///
+    /// The changes are not committed.
+    pub fn squash_and_compute_state_id<W>(
+        &self,
+        context_id: &ContextId,
+        state_write: &W,
+    ) -> Result<(String, Vec<state::StateChange>), ContextManagerError>
+    where
+        W: Write<StateId = String, Key = String, Value = Vec<u8>>,
+    {
+        let state_changes = self.squash(context_id)?;
+        let state_id = state_write
+            .compute_state_id(self.get_context(context_id)?.state_id(), &state_changes)?;
+        Ok((state_id, state_changes))
+    }
+
+    /// Returns the specified Context and all of its base contexts, ordered so that each Context
+    /// comes after every Context that `get` would consult after it.
+    ///
+    /// `get` searches the Context first and then its base contexts breadth-first, in the order
+    /// they are listed, and uses the first Context which contains the key. Applying the changes of
+    /// the returned contexts in order therefore leaves the same value for each key as `get`.
+    fn context_chain(&self, context_id: &ContextId) -> Result<Vec<&Context>, ContextManagerError> {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut contexts = VecDeque::new();
+        contexts.push_back(*context_id);
+        while let Some(context_id) = contexts.pop_front() {
+            if !visited.insert(context_id) {
+                continue;
+            }
+            let context = self.get_context(&context_id)?;
+            contexts.extend(context.base_contexts().iter().copied());
+            chain.push(context);
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
     /// Adds an Event to the specified Context.
     pub fn add_event(
         &mut self,
@@ -463,4 +674,306 @@ mod tests {
             (KEY2.to_string(), BYTES2.to_vec())
         );
     }
+
+    #[test]
+    fn address_permissions() {
+        let (mut manager, state_id) = make_manager(None);
+        let context_id = manager.create_context(&[], &state_id);
+        manager
+            .set_address_permissions(&context_id, vec!["11".into()], vec!["22".into()])
+            .unwrap();
+
+        assert!(manager.get(&context_id, &[KEY1.to_string()]).is_ok());
+        match manager.get(&context_id, &[KEY2.to_string()]) {
+            Err(ContextManagerError::AuthorizationError(_)) => (),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+
+        assert!(manager
+            .set_state(&context_id, KEY2.to_string(), BYTES2.to_vec())
+            .is_ok());
+        match manager.set_state(&context_id, KEY1.to_string(), BYTES1.to_vec()) {
+            Err(ContextManagerError::AuthorizationError(_)) => (),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+
+        assert!(manager.delete_state(&context_id, KEY2).is_ok());
+        match manager.delete_state(&context_id, KEY3) {
+            Err(ContextManagerError::AuthorizationError(_)) => (),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn list_context_chain() {
+        let state_changes = vec![
+            state::StateChange::Set {
+                key: KEY1.to_string(),
+                value: BYTES1.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY2.to_string(),
+                value: BYTES2.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY3.to_string(),
+                value: BYTES3.to_vec(),
+            },
+        ];
+        let (mut manager, state_id) = make_manager(Some(state_changes));
+
+        let base_context_id = manager.create_context(&[], &state_id);
+        manager.delete_state(&base_context_id, KEY1).unwrap();
+        manager
+            .set_state(&base_context_id, KEY4.to_string(), BYTES4.to_vec())
+            .unwrap();
+
+        let context_id = manager.create_context(&[base_context_id], &state_id);
+        manager
+            .set_state(&context_id, KEY2.to_string(), BYTES4.to_vec())
+            .unwrap();
+        manager
+            .set_state(&context_id, KEY1.to_string(), BYTES3.to_vec())
+            .unwrap();
+        manager.delete_state(&context_id, KEY3).unwrap();
+
+        assert_eq!(
+            manager.list(&context_id, "").unwrap(),
+            vec![
+                (KEY1.to_string(), BYTES3.to_vec()),
+                (KEY2.to_string(), BYTES4.to_vec()),
+                (KEY4.to_string(), BYTES4.to_vec()),
+            ]
+        );
+        assert_eq!(
+            manager.list(&base_context_id, "").unwrap(),
+            vec![
+                (KEY2.to_string(), BYTES2.to_vec()),
+                (KEY3.to_string(), BYTES3.to_vec()),
+                (KEY4.to_string(), BYTES4.to_vec()),
+            ]
+        );
+        assert_eq!(
+            manager.list(&context_id, "44").unwrap(),
+            vec![(KEY4.to_string(), BYTES4.to_vec())]
+        );
+        assert!(manager.list(&context_id, "33").unwrap().is_empty());
+
+        // Listing is limited to the Context's inputs
+        manager
+            .set_address_permissions(&context_id, vec!["22".into()], vec![])
+            .unwrap();
+        assert_eq!(manager.list(&context_id, "2222").unwrap().len(), 1);
+        match manager.list(&context_id, "") {
+            Err(ContextManagerError::AuthorizationError(_)) => (),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn squash_diamond_context_chain() {
+        let (mut manager, state_id) = make_manager(None);
+
+        let root_context_id = manager.create_context(&[], &state_id);
+        manager
+            .set_state(&root_context_id, KEY1.to_string(), BYTES1.to_vec())
+            .unwrap();
+        manager
+            .set_state(&root_context_id, KEY2.to_string(), BYTES1.to_vec())
+            .unwrap();
+
+        let middle_context_id = manager.create_context(&[root_context_id], &state_id);
+        manager
+            .set_state(&middle_context_id, KEY1.to_string(), BYTES4.to_vec())
+            .unwrap();
+
+        let left_context_id = manager.create_context(&[middle_context_id], &state_id);
+        manager
+            .set_state(&left_context_id, KEY3.to_string(), BYTES2.to_vec())
+            .unwrap();
+
+        let right_context_id = manager.create_context(&[root_context_id], &state_id);
+        manager
+            .set_state(&right_context_id, KEY1.to_string(), BYTES3.to_vec())
+            .unwrap();
+        manager
+            .set_state(&right_context_id, KEY3.to_string(), BYTES3.to_vec())
+            .unwrap();
+
+        let context_id = manager.create_context(&[left_context_id, right_context_id], &state_id);
+
+        // The right context is searched before the middle context, as it is closer, and before
+        // the root context; the left context is searched before the right one
+        let keys = [KEY1.to_string(), KEY2.to_string(), KEY3.to_string()];
+        let mut values = manager.get(&context_id, &keys).unwrap();
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                (KEY1.to_string(), BYTES3.to_vec()),
+                (KEY2.to_string(), BYTES1.to_vec()),
+                (KEY3.to_string(), BYTES2.to_vec()),
+            ]
+        );
+
+        let squashed = manager
+            .squash(&context_id)
+            .unwrap()
+            .into_iter()
+            .map(|change| match change {
+                state::StateChange::Set { key, value } => (key, value),
+                change => panic!("Unexpected state change {:?}", change),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(squashed, values);
+    }
+
+    #[test]
+    fn squash_context_chain() {
+        let state = HashMapState::new();
+        let state_changes = vec![state::StateChange::Set {
+            key: KEY1.to_string(),
+            value: BYTES1.to_vec(),
+        }];
+        let state_id = state
+            .commit(&HashMapState::state_id(&HashMap::new()), &state_changes)
+            .unwrap();
+        let mut manager = ContextManager::new(Box::new(state.clone()));
+
+        let first_context_id = manager.create_context(&[], &state_id);
+        manager
+            .set_state(&first_context_id, KEY2.to_string(), BYTES1.to_vec())
+            .unwrap();
+        manager
+            .set_state(&first_context_id, KEY3.to_string(), BYTES1.to_vec())
+            .unwrap();
+
+        let second_context_id = manager.create_context(&[], &state_id);
+        manager
+            .set_state(&second_context_id, KEY4.to_string(), BYTES1.to_vec())
+            .unwrap();
+
+        // The first base context takes precedence over the second
+        let third_context_id =
+            manager.create_context(&[first_context_id, second_context_id], &state_id);
+        manager
+            .set_state(&third_context_id, KEY4.to_string(), BYTES3.to_vec())
+            .unwrap();
+        manager.delete_state(&third_context_id, KEY1).unwrap();
+        manager
+            .set_state(&third_context_id, KEY2.to_string(), BYTES2.to_vec())
+            .unwrap();
+        manager
+            .set_state(&third_context_id, KEY2.to_string(), BYTES4.to_vec())
+            .unwrap();
+
+        let final_context_id = manager.create_context(&[third_context_id], &state_id);
+        manager
+            .set_state(&final_context_id, KEY5.to_string(), BYTES2.to_vec())
+            .unwrap();
+
+        let expected = vec![
+            state::StateChange::Delete {
+                key: KEY1.to_string(),
+            },
+            state::StateChange::Set {
+                key: KEY2.to_string(),
+                value: BYTES4.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY3.to_string(),
+                value: BYTES1.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY4.to_string(),
+                value: BYTES3.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY5.to_string(),
+                value: BYTES2.to_vec(),
+            },
+        ];
+        assert_eq!(manager.squash(&final_context_id).unwrap(), expected);
+
+        let (computed_state_id, state_changes) = manager
+            .squash_and_compute_state_id(&final_context_id, &state)
+            .unwrap();
+        assert_eq!(state_changes, expected);
+
+        // The state ID is computed from the final context's state ID, and matches the result of
+        // committing the squashed changes
+        let committed_state_id = state.commit(&state_id, &state_changes).unwrap();
+        assert_eq!(computed_state_id, committed_state_id);
+        let values = state
+            .get(&committed_state_id, &[KEY1.to_string(), KEY2.to_string()])
+            .unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values.get(KEY2), Some(&BYTES4.to_vec()));
+    }
+
+    #[test]
+    fn squash_conflicting_base_contexts() {
+        let (mut manager, state_id) = make_manager(None);
+
+        let first_context_id = manager.create_context(&[], &state_id);
+        manager
+            .set_state(&first_context_id, KEY1.to_string(), BYTES1.to_vec())
+            .unwrap();
+        let second_context_id = manager.create_context(&[], &state_id);
+        manager
+            .set_state(&second_context_id, KEY1.to_string(), BYTES2.to_vec())
+            .unwrap();
+
+        let context_id = manager.create_context(&[second_context_id, first_context_id], &state_id);
+        assert_eq!(
+            manager.get(&context_id, &[KEY1.to_string()]).unwrap(),
+            vec![(KEY1.to_string(), BYTES2.to_vec())]
+        );
+        assert_eq!(
+            manager.squash(&context_id).unwrap(),
+            vec![state::StateChange::Set {
+                key: KEY1.to_string(),
+                value: BYTES2.to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn drop_unreferenced_context() {
+        let (mut manager, state_id) = make_manager(None);
+        let context_id = manager.create_context(&[], &state_id);
+
+        manager.drop_context(context_id);
+        assert!(manager.contexts.is_empty());
+
+        // Dropping an unknown context is ignored
+        manager.drop_context(context_id);
+        assert!(manager.contexts.is_empty());
+    }
+
+    #[test]
+    fn drop_referenced_contexts() {
+        let (mut manager, state_id) = make_manager(None);
+        let first_context_id = manager.create_context(&[], &state_id);
+        let second_context_id = manager.create_context(&[first_context_id], &state_id);
+        let third_context_id = manager.create_context(&[second_context_id], &state_id);
+        let other_context_id = manager.create_context(&[first_context_id], &state_id);
+
+        // The first and second contexts are still referenced, so they are kept
+        manager.drop_context(first_context_id);
+        manager.drop_context(second_context_id);
+        assert_eq!(manager.contexts.len(), 4);
+
+        // Dropping the end of the chain removes the second context, but the first is still
+        // referenced by the other context
+        manager.drop_context(third_context_id);
+        assert_eq!(manager.contexts.len(), 2);
+        assert!(manager.contexts.get(&first_context_id).is_some());
+        assert!(manager.contexts.get(&other_context_id).is_some());
+
+        manager.drop_context(other_context_id);
+        assert!(manager.contexts.is_empty());
+        assert!(manager.context_refs.is_empty());
+        assert!(manager.dropped_contexts.is_empty());
+    }
 }
@@ -23,9 +23,10 @@
 use std::sync::{Arc, Mutex};
 
 use crate::context::error::ContextManagerError;
-use crate::context::{manager, ContextId, ContextLifecycle};
+use crate::context::{manager, ContextId, ContextLifecycle, Savepoint};
 use crate::protocol::receipt::{Event, TransactionReceipt};
-use crate::state::Read;
+use crate::state;
+use crate::state::{Read, Write};
 
 /// A thread-safe ContextManager.
 #[derive(Clone)]
@@ -43,6 +44,24 @@ impl ContextManager {
         }
     }
 
+    /// Restricts the addresses which may be accessed through a context to the given input and
+    /// output prefixes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the context id does not exist.
+    pub fn set_address_permissions(
+        &self,
+        context_id: &ContextId,
+        inputs: Vec<String>,
+        outputs: Vec<String>,
+    ) -> Result<(), ContextManagerError> {
+        self.internal_manager
+            .lock()
+            .expect("Lock in set_address_permissions was poisoned")
+            .set_address_permissions(context_id, inputs, outputs)
+    }
+
     /// Return a set of values from a context.
     ///
     /// The values are returned as key-value tuples
@@ -50,8 +69,8 @@ impl ContextManager {
     ///
     /// # Errors
     ///
-    /// Returns an error if the context id does not exist, or an error occurs while reading
-    /// from the underlying state.
+    /// Returns an error if the context id does not exist, if a key is not in the context's
+    /// inputs, or an error occurs while reading from the underlying state.
     pub fn get(
         &self,
         context_id: &ContextId,
@@ -63,10 +82,27 @@ impl ContextManager {
             .get(context_id, keys)
     }
 
+    /// Return the keys and values starting with the given prefix in a context, ordered by key.
+    ///
     /// # Errors
     ///
-    /// Returns an error if the context id does not exist, or an error occurs while reading
-    /// from the underlying state.
+    /// Returns an error if the context id does not exist, if the prefix is not in the context's
+    /// inputs, or an error occurs while reading from the underlying state.
+    pub fn list(
+        &self,
+        context_id: &ContextId,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextManagerError> {
+        self.internal_manager
+            .lock()
+            .expect("Lock in the list method was poisoned")
+            .list(context_id, prefix)
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if the context id does not exist, or if the key is not in the context's
+    /// outputs.
     pub fn set_state(
         &self,
         context_id: &ContextId,
@@ -79,6 +115,10 @@ impl ContextManager {
             .set_state(context_id, key, value)
     }
 
+    /// # Errors
+    ///
+    /// Returns an error if the context id does not exist, if the key is not in the context's
+    /// outputs, or an error occurs while reading from the underlying state.
     pub fn delete_state(
         &self,
         context_id: &ContextId,
@@ -111,6 +151,71 @@ impl ContextManager {
             .expect("Lock in add_data was poisoned")
             .add_data(context_id, data)
     }
+
+    /// Returns a savepoint for the current state changes, events and data of a context.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the context id does not exist.
+    pub fn savepoint(&self, context_id: &ContextId) -> Result<Savepoint, ContextManagerError> {
+        self.internal_manager
+            .lock()
+            .expect("Lock in savepoint was poisoned")
+            .savepoint(context_id)
+    }
+
+    /// Rolls a context back to the given savepoint.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the context id does not exist, or if the savepoint is not valid for
+    /// the context.
+    pub fn rollback_to(
+        &self,
+        context_id: &ContextId,
+        savepoint: &Savepoint,
+    ) -> Result<(), ContextManagerError> {
+        self.internal_manager
+            .lock()
+            .expect("Lock in rollback_to was poisoned")
+            .rollback_to(context_id, savepoint)
+    }
+
+    /// Squashes the StateChanges of the specified Context and all of its base contexts into the
+    /// minimal list of StateChanges, ordered by key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the context id, or one of its base contexts, does not exist.
+    pub fn squash(
+        &self,
+        context_id: &ContextId,
+    ) -> Result<Vec<state::StateChange>, ContextManagerError> {
+        self.internal_manager
+            .lock()
+            .expect("Lock in squash was poisoned")
+            .squash(context_id)
+    }
+
+    /// Squashes the StateChanges of the specified Context and computes the state ID that results
+    /// from applying them with the given Write, without committing them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a context does not exist, or if the state ID cannot be computed.
+    pub fn squash_and_compute_state_id<W>(
+        &self,
+        context_id: &ContextId,
+        state_write: &W,
+    ) -> Result<(String, Vec<state::StateChange>), ContextManagerError>
+    where
+        W: Write<StateId = String, Key = String, Value = Vec<u8>>,
+    {
+        self.internal_manager
+            .lock()
+            .expect("Lock in squash_and_compute_state_id was poisoned")
+            .squash_and_compute_state_id(context_id, state_write)
+    }
 }
 
 impl ContextLifecycle for ContextManager {
@@ -47,14 +47,41 @@ pub trait ContextLifecycle: Send {
     ) -> Result<TransactionReceipt, ContextManagerError>;
 }
 
+/// A point in a Context's changes, to which the Context may be rolled back.
+///
+/// Rolling back to a savepoint discards every state change, event and data added to the Context
+/// since the savepoint was taken.  A savepoint may only be used with the Context it was taken
+/// from, and only until the Context is rolled back to an earlier savepoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Savepoint {
+    context_id: ContextId,
+    /// The number of rollbacks made on the Context before the savepoint was taken
+    generation: u64,
+    state_changes: usize,
+    replaced_state_changes: usize,
+    events: usize,
+    data: usize,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Context {
     base_contexts: Vec<ContextId>,
     state_changes: Vec<StateChange>,
+    /// The state changes replaced by deletes, with their positions, so they can be restored on
+    /// rollback.
+    replaced_state_changes: Vec<(usize, StateChange)>,
+    /// The number of rollbacks made on this Context.
+    generation: u64,
+    /// The savepoint rolled back to by each rollback, which began the next generation.
+    rollbacks: Vec<Savepoint>,
     id: ContextId,
     data: Vec<Vec<u8>>,
     events: Vec<Event>,
     state_id: String,
+    /// The address prefixes which may be read, if restricted.
+    inputs: Option<Vec<String>>,
+    /// The address prefixes which may be written or deleted, if restricted.
+    outputs: Option<Vec<String>>,
 }
 
 impl Context {
@@ -63,9 +90,14 @@ impl Context {
             base_contexts,
             state_id: state_id.to_string(),
             state_changes: Vec::new(),
+            replaced_state_changes: Vec::new(),
+            generation: 0,
+            rollbacks: Vec::new(),
             id: *Uuid::new_v4().as_bytes(),
             data: Vec::new(),
             events: Vec::new(),
+            inputs: None,
+            outputs: None,
         }
     }
 
@@ -92,6 +124,24 @@ impl Context {
         &self.state_id
     }
 
+    /// Restricts reads from this Context to addresses starting with one of the
+    /// given input prefixes, and writes and deletes to addresses starting with
+    /// one of the given output prefixes.
+    pub fn set_address_permissions(&mut self, inputs: Vec<String>, outputs: Vec<String>) {
+        self.inputs = Some(inputs);
+        self.outputs = Some(outputs);
+    }
+
+    /// Returns true if the address may be read from this Context.
+    pub fn can_read(&self, address: &str) -> bool {
+        is_permitted(&self.inputs, address)
+    }
+
+    /// Returns true if the address may be written to or deleted from this Context.
+    pub fn can_write(&self, address: &str) -> bool {
+        is_permitted(&self.outputs, address)
+    }
+
     pub fn add_event(&mut self, event: Event) {
         if !self.events().contains(&event) {
             self.events.push(event);
@@ -124,21 +174,24 @@ impl Context {
 
     /// Adds StateChange::Delete and returns the value associated to the key being deleted
     pub fn delete_state(&mut self, key: &str) -> Option<Vec<u8>> {
-        let found_state_change = self
+        let found_index = self
             .state_changes
-            .iter_mut()
-            .rev()
-            .find(|state_change| state_change.has_key(&key));
-        if let Some(StateChange::Set { .. }) = found_state_change {
+            .iter()
+            .rposition(|state_change| state_change.has_key(key));
+        if let Some(index) = found_index {
             // If a StateChange::Set is found associated with the key, the value set is returned.
-            let mut new_state_change: StateChange = StateChange::Delete {
-                key: key.to_string(),
-            };
-            mem::swap(found_state_change.unwrap(), &mut new_state_change);
-            if let StateChange::Set { value: v, .. } = new_state_change {
-                return Some(v);
+            if let StateChange::Set { value: v, .. } = &self.state_changes[index] {
+                let value = v.clone();
+                let replaced = mem::replace(
+                    &mut self.state_changes[index],
+                    StateChange::Delete {
+                        key: key.to_string(),
+                    },
+                );
+                self.replaced_state_changes.push((index, replaced));
+                return Some(value);
             }
-        } else if found_state_change.is_none() {
+        } else {
             // If no StateChange, Set or Delete, is found associated with the key, a new Delete
             // is added to the list of StateChanges with the value returned as None.
             self.state_changes.push(StateChange::Delete {
@@ -148,6 +201,81 @@ impl Context {
         None
     }
 
+    /// Returns a savepoint for the current state changes, events and data of this Context.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            context_id: self.id,
+            generation: self.generation,
+            state_changes: self.state_changes.len(),
+            replaced_state_changes: self.replaced_state_changes.len(),
+            events: self.events.len(),
+            data: self.data.len(),
+        }
+    }
+
+    /// Discards the state changes, events and data added to this Context since the given
+    /// savepoint was taken.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the savepoint was not taken from this Context, or if the Context has
+    /// already been rolled back past it.
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) -> Result<(), ContextManagerError> {
+        if !self.is_valid_savepoint(savepoint) {
+            return Err(ContextManagerError::InvalidSavepoint(
+                "savepoint is not valid for this context".into(),
+            ));
+        }
+
+        self.state_changes.truncate(savepoint.state_changes);
+        // Restore the state changes which were replaced since the savepoint, newest first, so
+        // that each position ends up with the change it held at the savepoint
+        while self.replaced_state_changes.len() > savepoint.replaced_state_changes {
+            let (index, state_change) = self
+                .replaced_state_changes
+                .pop()
+                .expect("Replaced state changes unexpectedly empty");
+            if index < self.state_changes.len() {
+                self.state_changes[index] = state_change;
+            }
+        }
+        self.events.truncate(savepoint.events);
+        self.data.truncate(savepoint.data);
+
+        self.rollbacks.push(savepoint.clone());
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Returns true if the savepoint was taken from this Context, and the Context has not since
+    /// been rolled back to a savepoint taken before it.
+    ///
+    /// The changes made in each generation only grow, so a savepoint which was taken in the current
+    /// generation is valid.  Otherwise, the generations are followed back through the savepoints
+    /// which began them; the savepoint is valid if it was taken, in its own generation, no later
+    /// than the savepoint which began the next generation that is still in use.
+    fn is_valid_savepoint(&self, savepoint: &Savepoint) -> bool {
+        if savepoint.context_id != self.id || savepoint.generation > self.generation {
+            return false;
+        }
+
+        let mut generation = self.generation;
+        let mut limit = None;
+        while generation > savepoint.generation {
+            let rollback = &self.rollbacks[(generation - 1) as usize];
+            generation = rollback.generation;
+            limit = Some(rollback);
+        }
+
+        generation == savepoint.generation
+            && limit.map_or(true, |limit| {
+                savepoint.state_changes <= limit.state_changes
+                    && savepoint.replaced_state_changes <= limit.replaced_state_changes
+                    && savepoint.events <= limit.events
+                    && savepoint.data <= limit.data
+            })
+    }
+
     /// Checks to see if the Key is referenced by any StateChanges within the Context
     pub fn contains(&self, key: &str) -> bool {
         for state_change in self.state_changes().iter().rev() {
@@ -168,6 +296,13 @@ impl Context {
     }
 }
 
+fn is_permitted(prefixes: &Option<Vec<String>>, address: &str) -> bool {
+    match prefixes {
+        Some(prefixes) => prefixes.iter().any(|prefix| address.starts_with(prefix)),
+        None => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -259,4 +394,66 @@ mod tests {
             assert_eq!(Some(v.clone()), deleted_value);
         }
     }
+
+    #[test]
+    fn rollback_to_savepoint() {
+        let mut context = Context::new(KEY3, Vec::new());
+        context.set_state(KEY1.to_string(), BYTES1.to_vec());
+        context.add_data(BYTES1.to_vec());
+        let savepoint = context.savepoint();
+
+        context.set_state(KEY2.to_string(), BYTES2.to_vec());
+        // Deleting a key set before the savepoint replaces its state change
+        assert_eq!(context.delete_state(KEY1), Some(BYTES1.to_vec()));
+        context.add_data(BYTES2.to_vec());
+        context.add_event(Event {
+            event_type: "test".into(),
+            attributes: vec![],
+            data: BYTES3.to_vec(),
+        });
+
+        let nested_savepoint = context.savepoint();
+        context.set_state(KEY3.to_string(), BYTES3.to_vec());
+        context.rollback_to(&nested_savepoint).unwrap();
+        assert!(!context.contains(KEY3));
+        assert!(context.contains(KEY2));
+
+        context.rollback_to(&savepoint).unwrap();
+        assert_eq!(
+            context.state_changes(),
+            &vec![StateChange::Set {
+                key: KEY1.to_string(),
+                value: BYTES1.to_vec(),
+            }]
+        );
+        assert_eq!(context.get_state(KEY1), Some(&BYTES1[..]));
+        assert_eq!(context.data(), &vec![BYTES1.to_vec()]);
+        assert!(context.events().is_empty());
+
+        // The nested savepoint was discarded by rolling back past it, even once the context has
+        // as many changes as it did when the nested savepoint was taken
+        assert!(context.rollback_to(&nested_savepoint).is_err());
+        context.set_state(KEY3.to_string(), BYTES3.to_vec());
+        assert_eq!(context.delete_state(KEY3), Some(BYTES3.to_vec()));
+        context.add_data(BYTES3.to_vec());
+        context.add_event(Event {
+            event_type: "test".into(),
+            attributes: vec![],
+            data: BYTES1.to_vec(),
+        });
+        assert!(context.rollback_to(&nested_savepoint).is_err());
+
+        // The savepoint which was rolled back to, and those taken since, remain valid
+        let later_savepoint = context.savepoint();
+        context.set_state(KEY2.to_string(), BYTES2.to_vec());
+        context.rollback_to(&later_savepoint).unwrap();
+        assert!(!context.contains(KEY2));
+        context.rollback_to(&savepoint).unwrap();
+        assert_eq!(context.data(), &vec![BYTES1.to_vec()]);
+        assert!(context.rollback_to(&later_savepoint).is_err());
+
+        // A savepoint can't be used with another context
+        let other_context = Context::new(KEY3, Vec::new());
+        assert!(context.rollback_to(&other_context.savepoint()).is_err());
+    }
 }
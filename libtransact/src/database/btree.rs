@@ -98,7 +98,7 @@ impl<'a> DatabaseReader for BTreeReader<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?
             .get(key)
         {
             Some(value) => Ok(Some(value.to_vec())),
@@ -119,7 +119,7 @@ impl<'a> DatabaseReader for BTreeReader<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
 
         Ok(Box::new(BTreeDatabaseCursor::new(index.clone())))
     }
@@ -135,7 +135,7 @@ impl<'a> DatabaseReader for BTreeReader<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?
             .len())
     }
 }
@@ -199,10 +199,7 @@ impl<'a> DatabaseWriter for BTreeWriter<'a> {
 
     fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
         if !self.db.indexes.contains_key(index) {
-            return Err(DatabaseError::WriterError(format!(
-                "Not an index: {}",
-                index
-            )));
+            return Err(DatabaseError::MissingIndex(index.to_string()));
         }
 
         self.transactions.push(WriterTransaction::IndexPut {
@@ -227,7 +224,7 @@ impl<'a> DatabaseWriter for BTreeWriter<'a> {
             .db
             .indexes
             .get_mut(index)
-            .ok_or_else(|| DatabaseError::WriterError(format!("Not an index: {}", index)))?
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?
             .contains_key(key)
         {
             return Err(DatabaseError::WriterError("Key not found".to_string()));
@@ -329,7 +326,7 @@ impl<'a> DatabaseReader for BTreeWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?
             .get(key)
         {
             Some(value) => Ok(Some(value.to_vec())),
@@ -366,7 +363,7 @@ impl<'a> DatabaseReader for BTreeWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?
             .clone();
 
         for transaction in self.transactions.iter() {
@@ -442,7 +439,7 @@ impl<'a> DatabaseReader for BTreeWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?
             .len() as i32
             + count;
 
@@ -740,5 +737,4 @@ mod tests {
         assert_eq!(Some((vec!(2), vec!(22))), cursor.first());
         assert_eq!(Some((vec!(11), vec!(12))), cursor.last());
     }
-
 }
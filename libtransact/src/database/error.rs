@@ -26,6 +26,8 @@ pub enum DatabaseError {
     WriterError(String),
     CorruptionError(String),
     NotFoundError(String),
+    /// Returned when the database was not opened with the named index.
+    MissingIndex(String),
     DuplicateEntry,
 }
 
@@ -37,6 +39,7 @@ impl std::fmt::Display for DatabaseError {
             DatabaseError::WriterError(ref msg) => write!(f, "WriterError: {}", msg),
             DatabaseError::CorruptionError(ref msg) => write!(f, "CorruptionError: {}", msg),
             DatabaseError::NotFoundError(ref msg) => write!(f, "NotFoundError: {}", msg),
+            DatabaseError::MissingIndex(ref index) => write!(f, "MissingIndex: {}", index),
             DatabaseError::DuplicateEntry => write!(f, "DuplicateEntry"),
         }
     }
@@ -50,6 +53,7 @@ impl std::error::Error for DatabaseError {
             DatabaseError::WriterError(ref msg) => msg,
             DatabaseError::CorruptionError(ref msg) => msg,
             DatabaseError::NotFoundError(ref msg) => msg,
+            DatabaseError::MissingIndex(ref index) => index,
             DatabaseError::DuplicateEntry => "DuplicateEntry",
         }
     }
@@ -61,6 +65,7 @@ impl std::error::Error for DatabaseError {
             DatabaseError::WriterError(_) => None,
             DatabaseError::CorruptionError(_) => None,
             DatabaseError::NotFoundError(_) => None,
+            DatabaseError::MissingIndex(_) => None,
             DatabaseError::DuplicateEntry => None,
         }
     }
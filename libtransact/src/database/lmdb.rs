@@ -156,7 +156,7 @@ impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         let access = self.txn.access();
         let val: Result<&[u8], _> = access.get(index, key);
         Ok(val.ok().map(Vec::from))
@@ -176,7 +176,7 @@ impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         let cursor = self
             .txn
             .cursor(index)
@@ -199,7 +199,7 @@ impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         self.txn
             .db_stat(index)
             .map_err(|err| {
@@ -278,7 +278,7 @@ impl<'a> DatabaseWriter for LmdbDatabaseWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::WriterError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         self.txn
             .access()
             .put(index, key, value, lmdb::put::Flags::empty())
@@ -290,7 +290,7 @@ impl<'a> DatabaseWriter for LmdbDatabaseWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::WriterError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         self.txn
             .access()
             .del_key(index, key)
@@ -320,7 +320,7 @@ impl<'a> DatabaseReader for LmdbDatabaseWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         let access = self.txn.access();
         let val: Result<&[u8], _> = access.get(index, key);
         Ok(val.ok().map(Vec::from))
@@ -340,7 +340,7 @@ impl<'a> DatabaseReader for LmdbDatabaseWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         let cursor = self
             .txn
             .cursor(index)
@@ -363,7 +363,7 @@ impl<'a> DatabaseReader for LmdbDatabaseWriter<'a> {
             .db
             .indexes
             .get(index)
-            .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+            .ok_or_else(|| DatabaseError::MissingIndex(index.to_string()))?;
         self.txn
             .db_stat(index)
             .map_err(|err| {
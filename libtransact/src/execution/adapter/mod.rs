@@ -19,9 +19,16 @@
 //! and its associated state.
 
 mod error;
+#[cfg(feature = "sawtooth-compat")]
+pub mod sawtooth_tp_adapter;
 pub mod static_adapter;
+pub mod subprocess_adapter;
 #[cfg(test)]
 pub mod test_adapter;
+#[cfg(feature = "wasm")]
+pub mod wasm_adapter;
+#[cfg(feature = "sawtooth-compat")]
+mod zmtp;
 
 pub use crate::execution::adapter::error::{ExecutionAdapterError, ExecutionOperationError};
 
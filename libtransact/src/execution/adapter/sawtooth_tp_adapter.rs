@@ -0,0 +1,1680 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! The Sawtooth transaction processor adapter provides a way to execute transactions with
+//! standalone Sawtooth transaction processors.
+//!
+//! This module provides the `SawtoothTpExecutionAdapter`, an implementation of `ExecutionAdapter`
+//! which plays the part of the Sawtooth validator for the transaction processors which connect to
+//! it.  It listens on a TCP or Unix socket, given as a ZeroMQ endpoint such as
+//! `tcp://127.0.0.1:4004` or `ipc:///var/run/transact/tp.sock`, and speaks the transaction
+//! processor protocol over ZMTP, the wire protocol of ZeroMQ, so that processors built with any
+//! of the Sawtooth SDKs can connect to it unchanged.
+//!
+//! The families registered by the processors are registered with the `ExecutionRegistry`, and
+//! unregistered once no connected processor serves them.  Each transaction is sent to the least
+//! busy processor for its family, or is queued until one of them has room for it.  If a
+//! processor disconnects, or returns an internal error, while applying a transaction, the changes
+//! made to the transaction's context are rolled back, and the transaction is returned to the
+//! executor with a `RoutingError`, so that it is retried.  The same is done once the processor
+//! responds, if one of the transaction's state requests failed for any reason other than an
+//! address outside its inputs or outputs.
+//!
+//! ZMTP is implemented here, over the standard library's sockets, rather than with a ZeroMQ
+//! `ROUTER` socket: the adapter must know when a processor disconnects, in order to retry the
+//! transactions it was applying, and a `ROUTER` socket does not report which peer has gone.
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use protobuf::{Message as _, RepeatedField};
+use uuid::Uuid;
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::static_adapter::{restrict_context, StaticContext};
+use crate::execution::adapter::zmtp;
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::{ApplyError, ContextError, Savepoint, TransactionContext};
+use crate::protocol::receipt::Event;
+use crate::protocol::transaction::TransactionPair;
+use crate::protos::processor::{
+    TpProcessRequest, TpProcessResponse, TpProcessResponse_Status, TpRegisterRequest,
+    TpRegisterRequest_TpProcessRequestHeaderStyle, TpRegisterResponse, TpRegisterResponse_Status,
+    TpUnregisterResponse, TpUnregisterResponse_Status,
+};
+use crate::protos::state_context::{
+    TpEventAddRequest, TpEventAddResponse, TpEventAddResponse_Status, TpReceiptAddDataRequest,
+    TpReceiptAddDataResponse, TpReceiptAddDataResponse_Status, TpStateDeleteRequest,
+    TpStateDeleteResponse, TpStateDeleteResponse_Status, TpStateEntry, TpStateGetRequest,
+    TpStateGetResponse, TpStateGetResponse_Status, TpStateSetRequest, TpStateSetResponse,
+    TpStateSetResponse_Status,
+};
+use crate::protos::validator::{Message, Message_MessageType};
+use crate::protos::FromProto;
+use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+
+// A type declaration to make the use of this complicated type-bounded box easier to work with
+type OnDoneCallback =
+    Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
+
+/// A callback, and the result to call it with once the shared state is unlocked.
+type Completion = (
+    OnDoneCallback,
+    Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>,
+);
+
+/// A process request, with its correlation id, and the writer of the processor to send it to once
+/// the shared state is unlocked.
+type ProcessRequestSend = (Arc<Mutex<Stream>>, String, TpProcessRequest);
+
+/// The version of the transaction processor protocol which is supported.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How often the listener checks whether the adapter has been stopped.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The SawtoothTpExecutionAdapter to serve Sawtooth transaction processors
+///
+/// The adapter binds its socket when it is created, and accepts connections from transaction
+/// processors once it is started.  Each connection is served by its own background thread.
+pub struct SawtoothTpExecutionAdapter {
+    listener: Option<Listener>,
+    endpoint: String,
+    context_manager: ContextManager,
+    shared: Arc<Mutex<SharedState>>,
+    stop: Arc<AtomicBool>,
+    accept_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl SawtoothTpExecutionAdapter {
+    /// Creates a new adapter, if possible.
+    ///
+    /// Creates a `SawtoothTpExecutionAdapter` listening on the given endpoint, which is either
+    /// `tcp://<host>:<port>` or `ipc://<path>`, and applying the processors' state requests
+    /// through the given `ContextManager`.  A stale Unix socket at the given path is replaced.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the endpoint is invalid or cannot be bound.
+    pub fn new_adapter(
+        endpoint: &str,
+        context_manager: ContextManager,
+    ) -> Result<Self, ExecutionAdapterError> {
+        let listener = Listener::bind(endpoint)
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+        let endpoint = listener
+            .endpoint()
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+
+        Ok(SawtoothTpExecutionAdapter {
+            listener: Some(listener),
+            endpoint,
+            context_manager,
+            shared: Arc::new(Mutex::new(SharedState::default())),
+            stop: Arc::new(AtomicBool::new(false)),
+            accept_thread: None,
+        })
+    }
+
+    /// Returns the endpoint the adapter is listening on.
+    ///
+    /// For a TCP endpoint, this includes the port which was bound, if port 0 was requested.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+impl ExecutionAdapter for SawtoothTpExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        let listener = self.listener.take().ok_or_else(|| {
+            ExecutionOperationError::StartError(
+                "Sawtooth transaction processor adapter was already started".into(),
+            )
+        })?;
+        listener.set_nonblocking(true).map_err(|err| {
+            ExecutionOperationError::StartError(format!("Unable to configure listener: {}", err))
+        })?;
+
+        lock(&self.shared).registry = Some(execution_registry);
+
+        let shared = self.shared.clone();
+        let context_manager = self.context_manager.clone();
+        let stop = self.stop.clone();
+        let join_handle = thread::Builder::new()
+            .name("SawtoothTpExecutionAdapter".into())
+            .spawn(move || accept_connections(&listener, &shared, &context_manager, &stop))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start transaction processor listener thread: {}",
+                    err
+                ))
+            })?;
+        self.accept_thread = Some(join_handle);
+
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        let mut deferred = Deferred::default();
+        lock(&self.shared).dispatch(
+            &self.context_manager,
+            (transaction_pair, context_id, on_done),
+            &mut deferred,
+        );
+        deferred.run();
+        Ok(())
+    }
+
+    fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(join_handle) = self.accept_thread {
+            join_handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join listener thread.".into())
+            })?;
+        }
+
+        // Closing the connections ends their threads, which then clean up after themselves
+        let join_handles = {
+            let mut shared = lock(&self.shared);
+            for stream in shared.connections.values() {
+                let _ = stream.shutdown();
+            }
+            std::mem::replace(&mut shared.connection_threads, vec![])
+        };
+        for join_handle in join_handles {
+            join_handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join connection thread.".into())
+            })?;
+        }
+
+        if self.endpoint.starts_with("ipc://") {
+            if let Err(err) = fs::remove_file(&self.endpoint["ipc://".len()..]) {
+                warn!("Unable to remove socket {}: {}", self.endpoint, err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A transaction which has not yet been sent to a processor.
+type QueuedTransaction = (TransactionPair, ContextId, OnDoneCallback);
+
+/// A transaction which has been sent to a processor.
+struct PendingTransaction {
+    connection_id: usize,
+    transaction_pair: TransactionPair,
+    context_id: ContextId,
+    on_done: OnDoneCallback,
+    savepoint: Savepoint,
+    /// Whether one of the transaction's state requests failed, in which case the transaction is
+    /// retried once the processor responds, whatever its result
+    failed: bool,
+}
+
+/// A connected transaction processor which has registered at least once.
+struct Processor {
+    writer: Arc<Mutex<Stream>>,
+    families: Vec<TransactionFamily>,
+    raw_header: bool,
+    max_occupancy: usize,
+    in_flight: usize,
+}
+
+/// The state shared by the adapter and its connection threads.
+#[derive(Default)]
+struct SharedState {
+    registry: Option<Box<dyn ExecutionRegistry>>,
+    /// The open connections, by connection id, which are shut down when the adapter is stopped
+    connections: HashMap<usize, Stream>,
+    connection_threads: Vec<thread::JoinHandle<()>>,
+    next_connection_id: usize,
+    processors: HashMap<usize, Processor>,
+    /// The transactions sent to processors, by the correlation id of their process request
+    pending: HashMap<String, PendingTransaction>,
+    queued: VecDeque<QueuedTransaction>,
+}
+
+impl SharedState {
+    /// Sends a transaction to the least busy processor of its family which has room for it,
+    /// queues it if they are all full, or returns it with a `RoutingError` if there are none.
+    fn dispatch(
+        &mut self,
+        context_manager: &ContextManager,
+        transaction: QueuedTransaction,
+        deferred: &mut Deferred,
+    ) {
+        let (transaction_pair, context_id, on_done) = transaction;
+        let family = TransactionFamily::from_pair(&transaction_pair);
+
+        let mut serving = self
+            .processors
+            .iter()
+            .filter(|(_, processor)| processor.families.contains(&family))
+            .peekable();
+        if serving.peek().is_none() {
+            deferred.completions.push((
+                on_done,
+                Err(ExecutionAdapterError::RoutingError(Box::new(
+                    transaction_pair,
+                ))),
+            ));
+            return;
+        }
+        let connection_id = match serving
+            .filter(|(_, processor)| processor.in_flight < processor.max_occupancy)
+            .min_by_key(|(_, processor)| processor.in_flight)
+        {
+            Some((connection_id, _)) => *connection_id,
+            None => {
+                self.queued
+                    .push_back((transaction_pair, context_id, on_done));
+                return;
+            }
+        };
+
+        let correlation_id = Uuid::new_v4().to_string();
+        let processor = self
+            .processors
+            .get_mut(&connection_id)
+            .expect("Processor was removed while it was locked");
+        let request =
+            match process_request(&transaction_pair, &correlation_id, processor.raw_header) {
+                Ok(request) => request,
+                Err(err) => {
+                    warn!("Unable to build process request: {}", err);
+                    deferred.completions.push((
+                        on_done,
+                        Err(ExecutionAdapterError::RoutingError(Box::new(
+                            transaction_pair,
+                        ))),
+                    ));
+                    return;
+                }
+            };
+
+        if let Err(err) = restrict_context(context_manager, &context_id, &transaction_pair) {
+            deferred.completions.push((
+                on_done,
+                Err(ExecutionAdapterError::GeneralExecutionError(Box::new(err))),
+            ));
+            return;
+        }
+        let savepoint = match context_manager.savepoint(&context_id) {
+            Ok(savepoint) => savepoint,
+            Err(err) => {
+                deferred.completions.push((
+                    on_done,
+                    Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                        ApplyError::InternalError(format!("Unable to create savepoint: {}", err)),
+                    ))),
+                ));
+                return;
+            }
+        };
+
+        processor.in_flight += 1;
+        deferred
+            .requests
+            .push((processor.writer.clone(), correlation_id.clone(), request));
+        self.pending.insert(
+            correlation_id,
+            PendingTransaction {
+                connection_id,
+                transaction_pair,
+                context_id,
+                on_done,
+                savepoint,
+                failed: false,
+            },
+        );
+    }
+
+    /// Dispatches the queued transactions, in order, which processors now have room for.
+    fn dispatch_queued(&mut self, context_manager: &ContextManager, deferred: &mut Deferred) {
+        let queued = std::mem::replace(&mut self.queued, VecDeque::new());
+        for transaction in queued {
+            self.dispatch(context_manager, transaction, deferred);
+        }
+    }
+
+    /// Removes the given families from a processor, unregistering the ones no other processor
+    /// serves, and returning the queued transactions which can no longer be routed.
+    fn remove_families(
+        &mut self,
+        connection_id: usize,
+        families: &[TransactionFamily],
+        deferred: &mut Deferred,
+    ) {
+        if let Some(processor) = self.processors.get_mut(&connection_id) {
+            processor
+                .families
+                .retain(|family| !families.contains(family));
+        }
+
+        let unserved: Vec<&TransactionFamily> = families
+            .iter()
+            .filter(|family| {
+                !self
+                    .processors
+                    .values()
+                    .any(|processor| processor.families.contains(family))
+            })
+            .collect();
+        if let Some(registry) = self.registry.as_mut() {
+            for family in &unserved {
+                registry.unregister_transaction_family(family);
+            }
+        }
+
+        let queued = std::mem::replace(&mut self.queued, VecDeque::new());
+        for (transaction_pair, context_id, on_done) in queued {
+            if unserved.contains(&&TransactionFamily::from_pair(&transaction_pair)) {
+                deferred.completions.push((
+                    on_done,
+                    Err(ExecutionAdapterError::RoutingError(Box::new(
+                        transaction_pair,
+                    ))),
+                ));
+            } else {
+                self.queued
+                    .push_back((transaction_pair, context_id, on_done));
+            }
+        }
+    }
+
+    /// Removes a closed connection, returning the transactions its processor was applying.
+    fn remove_connection(
+        &mut self,
+        connection_id: usize,
+        context_manager: &ContextManager,
+        deferred: &mut Deferred,
+    ) {
+        self.connections.remove(&connection_id);
+        let families = match self.processors.get(&connection_id) {
+            Some(processor) => processor.families.clone(),
+            None => return,
+        };
+        self.remove_families(connection_id, &families, deferred);
+        self.processors.remove(&connection_id);
+
+        let correlation_ids: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.connection_id == connection_id)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect();
+        for correlation_id in correlation_ids {
+            if let Some(pending) = self.pending.remove(&correlation_id) {
+                deferred.completions.push(pending.retry(context_manager));
+            }
+        }
+    }
+}
+
+/// The work to do once the shared state is unlocked, so that no socket is written to, and no
+/// callback is called, while it is held.
+#[derive(Default)]
+struct Deferred {
+    requests: Vec<ProcessRequestSend>,
+    completions: Vec<Completion>,
+}
+
+impl Deferred {
+    /// Sends the process requests, and then calls the callbacks.
+    fn run(self) {
+        for (writer, correlation_id, request) in self.requests {
+            if let Err(err) = send(
+                &writer,
+                Message_MessageType::TP_PROCESS_REQUEST,
+                &correlation_id,
+                &request,
+            ) {
+                // The connection's thread removes the processor, and retries its transactions,
+                // once its stream is shut down
+                warn!("Unable to send transaction to processor: {}", err);
+                let _ = lock(&writer).shutdown();
+            }
+        }
+        for (on_done, result) in self.completions {
+            on_done(result);
+        }
+    }
+}
+
+impl PendingTransaction {
+    /// Rolls back the changes made by the processor, and returns the transaction so that it is
+    /// retried.
+    fn retry(self, context_manager: &ContextManager) -> Completion {
+        if let Err(err) = context_manager.rollback_to(&self.context_id, &self.savepoint) {
+            warn!("Unable to roll back context {:?}: {}", self.context_id, err);
+        }
+        (
+            self.on_done,
+            Err(ExecutionAdapterError::RoutingError(Box::new(
+                self.transaction_pair,
+            ))),
+        )
+    }
+}
+
+fn accept_connections(
+    listener: &Listener,
+    shared: &Arc<Mutex<SharedState>>,
+    context_manager: &ContextManager,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => {
+                warn!("Unable to accept transaction processor connection: {}", err);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        let result = stream
+            .set_nonblocking(false)
+            .and_then(|_| Ok((stream.try_clone()?, stream.try_clone()?)));
+        let (writer, shutdown_handle) = match result {
+            Ok(streams) => streams,
+            Err(err) => {
+                warn!("Unable to set up transaction processor connection: {}", err);
+                continue;
+            }
+        };
+
+        let mut state = lock(shared);
+        let connection_id = state.next_connection_id;
+        state.next_connection_id += 1;
+
+        let connection = Connection {
+            connection_id,
+            writer: Arc::new(Mutex::new(writer)),
+            shared: shared.clone(),
+            context_manager: context_manager.clone(),
+        };
+        match thread::Builder::new()
+            .name(format!("SawtoothTpExecutionAdapter-{}", connection_id))
+            .spawn(move || connection.serve(stream))
+        {
+            Ok(join_handle) => {
+                state.connections.insert(connection_id, shutdown_handle);
+                state.connection_threads.push(join_handle);
+            }
+            Err(err) => warn!("Unable to start connection thread: {}", err),
+        }
+    }
+}
+
+/// A connection to a transaction processor, served by its own thread.
+struct Connection {
+    connection_id: usize,
+    writer: Arc<Mutex<Stream>>,
+    shared: Arc<Mutex<SharedState>>,
+    context_manager: ContextManager,
+}
+
+impl Connection {
+    fn serve(self, stream: Stream) {
+        let mut reader = BufReader::new(stream);
+        let result = zmtp::handshake(&mut ReadWrite(&mut reader, &self.writer), b"ROUTER")
+            .and_then(|_| loop {
+                let frames = zmtp::read_message(&mut reader)?;
+                let message: Message = frames
+                    .last()
+                    .ok_or_else(|| protocol_error("message has no frames"))
+                    .and_then(|frame| parse(frame))?;
+                self.handle_message(message)?;
+            });
+        match result {
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                debug!("Transaction processor {} disconnected", self.connection_id)
+            }
+            Err(err) => warn!(
+                "Closing transaction processor connection {}: {}",
+                self.connection_id, err
+            ),
+            Ok(()) => (),
+        }
+
+        let _ = lock(&self.writer).shutdown();
+        let mut deferred = Deferred::default();
+        {
+            let mut shared = lock(&self.shared);
+            shared.remove_connection(self.connection_id, &self.context_manager, &mut deferred);
+            shared.dispatch_queued(&self.context_manager, &mut deferred);
+        }
+        deferred.run();
+    }
+
+    fn handle_message(&self, mut message: Message) -> io::Result<()> {
+        let correlation_id = message.take_correlation_id();
+        let content = message.take_content();
+        match message.get_message_type() {
+            Message_MessageType::TP_REGISTER_REQUEST => {
+                self.register(&correlation_id, parse(&content)?)
+            }
+            Message_MessageType::TP_UNREGISTER_REQUEST => self.unregister(&correlation_id),
+            Message_MessageType::TP_PROCESS_RESPONSE => {
+                self.process_response(&correlation_id, parse(&content)?);
+                Ok(())
+            }
+            Message_MessageType::TP_STATE_GET_REQUEST => {
+                let mut request: TpStateGetRequest = parse(&content)?;
+                let context_id = request.take_context_id();
+                let mut response = TpStateGetResponse::new();
+                let result = self.with_context(&context_id, |context| {
+                    context.get_state_entries(&request.take_addresses().into_vec())
+                });
+                match result {
+                    Ok(entries) => {
+                        response.set_status(TpStateGetResponse_Status::OK);
+                        response.set_entries(
+                            entries
+                                .into_iter()
+                                .map(|(address, data)| {
+                                    let mut entry = TpStateEntry::new();
+                                    entry.set_address(address);
+                                    entry.set_data(data);
+                                    entry
+                                })
+                                .collect(),
+                        );
+                    }
+                    Err(ContextError::AuthorizationError(_)) => {
+                        response.set_status(TpStateGetResponse_Status::AUTHORIZATION_ERROR)
+                    }
+                    Err(err) => {
+                        warn!("Unable to get state: {}", err);
+                        self.fail_transaction(&context_id);
+                        response.set_status(TpStateGetResponse_Status::AUTHORIZATION_ERROR);
+                    }
+                }
+                self.respond(
+                    Message_MessageType::TP_STATE_GET_RESPONSE,
+                    &correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_STATE_SET_REQUEST => {
+                let mut request: TpStateSetRequest = parse(&content)?;
+                let context_id = request.take_context_id();
+                let entries: Vec<(String, Vec<u8>)> = request
+                    .take_entries()
+                    .into_iter()
+                    .map(|mut entry| (entry.take_address(), entry.take_data()))
+                    .collect();
+                let addresses = entries.iter().map(|(address, _)| address.clone()).collect();
+                let mut response = TpStateSetResponse::new();
+                match self.with_context(&context_id, |context| context.set_state_entries(entries)) {
+                    Ok(()) => {
+                        response.set_status(TpStateSetResponse_Status::OK);
+                        response.set_addresses(RepeatedField::from_vec(addresses));
+                    }
+                    Err(ContextError::AuthorizationError(_)) => {
+                        response.set_status(TpStateSetResponse_Status::AUTHORIZATION_ERROR)
+                    }
+                    Err(err) => {
+                        warn!("Unable to set state: {}", err);
+                        self.fail_transaction(&context_id);
+                        response.set_status(TpStateSetResponse_Status::AUTHORIZATION_ERROR);
+                    }
+                }
+                self.respond(
+                    Message_MessageType::TP_STATE_SET_RESPONSE,
+                    &correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_STATE_DELETE_REQUEST => {
+                let mut request: TpStateDeleteRequest = parse(&content)?;
+                let context_id = request.take_context_id();
+                let mut response = TpStateDeleteResponse::new();
+                match self.with_context(&context_id, |context| {
+                    context.delete_state_entries(&request.take_addresses().into_vec())
+                }) {
+                    Ok(addresses) => {
+                        response.set_status(TpStateDeleteResponse_Status::OK);
+                        response.set_addresses(RepeatedField::from_vec(addresses));
+                    }
+                    Err(ContextError::AuthorizationError(_)) => {
+                        response.set_status(TpStateDeleteResponse_Status::AUTHORIZATION_ERROR)
+                    }
+                    Err(err) => {
+                        warn!("Unable to delete state: {}", err);
+                        self.fail_transaction(&context_id);
+                        response.set_status(TpStateDeleteResponse_Status::AUTHORIZATION_ERROR);
+                    }
+                }
+                self.respond(
+                    Message_MessageType::TP_STATE_DELETE_RESPONSE,
+                    &correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST => {
+                let mut request: TpReceiptAddDataRequest = parse(&content)?;
+                let context_id = request.take_context_id();
+                let mut response = TpReceiptAddDataResponse::new();
+                match self.with_context(&context_id, |context| {
+                    context.add_receipt_data(request.take_data())
+                }) {
+                    Ok(()) => response.set_status(TpReceiptAddDataResponse_Status::OK),
+                    Err(err) => {
+                        warn!("Unable to add receipt data: {}", err);
+                        response.set_status(TpReceiptAddDataResponse_Status::ERROR);
+                    }
+                }
+                self.respond(
+                    Message_MessageType::TP_RECEIPT_ADD_DATA_RESPONSE,
+                    &correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_EVENT_ADD_REQUEST => {
+                let mut request: TpEventAddRequest = parse(&content)?;
+                let context_id = request.take_context_id();
+                let mut response = TpEventAddResponse::new();
+                match self.with_context(&context_id, |context| {
+                    let event = Event::from_proto(request.take_event())
+                        .map_err(|err| ContextError::SerializationError(Box::new(err)))?;
+                    context.add_event(event.event_type, event.attributes, event.data)
+                }) {
+                    Ok(()) => response.set_status(TpEventAddResponse_Status::OK),
+                    Err(err) => {
+                        warn!("Unable to add event: {}", err);
+                        response.set_status(TpEventAddResponse_Status::ERROR);
+                    }
+                }
+                self.respond(
+                    Message_MessageType::TP_EVENT_ADD_RESPONSE,
+                    &correlation_id,
+                    &response,
+                )
+            }
+            message_type => {
+                warn!(
+                    "Ignoring unexpected message from transaction processor: {:?}",
+                    message_type
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn register(&self, correlation_id: &str, request: TpRegisterRequest) -> io::Result<()> {
+        let family = TransactionFamily::new(
+            request.get_family().to_owned(),
+            request.get_version().to_owned(),
+        );
+        let mut response = TpRegisterResponse::new();
+        if request.get_protocol_version() > PROTOCOL_VERSION {
+            warn!(
+                "Rejecting registration of {:?} with unsupported protocol version {}",
+                family,
+                request.get_protocol_version()
+            );
+            response.set_status(TpRegisterResponse_Status::ERROR);
+            response.set_protocol_version(PROTOCOL_VERSION);
+            return self.respond(
+                Message_MessageType::TP_REGISTER_RESPONSE,
+                correlation_id,
+                &response,
+            );
+        }
+
+        let mut deferred = Deferred::default();
+        let mut writer = {
+            let mut shared = lock(&self.shared);
+            let newly_served = !shared
+                .processors
+                .values()
+                .any(|processor| processor.families.contains(&family));
+            let writer = self.writer.clone();
+            let processor = shared
+                .processors
+                .entry(self.connection_id)
+                .or_insert_with(|| Processor {
+                    writer,
+                    families: vec![],
+                    raw_header: request.get_request_header_style()
+                        == TpRegisterRequest_TpProcessRequestHeaderStyle::RAW,
+                    max_occupancy: (request.get_max_occupancy() as usize).max(1),
+                    in_flight: 0,
+                });
+            if !processor.families.contains(&family) {
+                processor.families.push(family.clone());
+            }
+            if newly_served {
+                if let Some(registry) = shared.registry.as_mut() {
+                    registry.register_transaction_family(family);
+                }
+            }
+            shared.dispatch_queued(&self.context_manager, &mut deferred);
+
+            // The processor's writer is locked before the shared state is unlocked, so that the
+            // processor learns that it is registered before it is sent any transactions
+            lock(&self.writer)
+        };
+
+        response.set_status(TpRegisterResponse_Status::OK);
+        response.set_protocol_version(PROTOCOL_VERSION);
+        let result = encode(
+            Message_MessageType::TP_REGISTER_RESPONSE,
+            correlation_id,
+            &response,
+        )
+        .and_then(|bytes| zmtp::write_message(&mut *writer, &bytes));
+        drop(writer);
+        deferred.run();
+        result
+    }
+
+    fn unregister(&self, correlation_id: &str) -> io::Result<()> {
+        let mut deferred = Deferred::default();
+        {
+            let mut shared = lock(&self.shared);
+            let families = match shared.processors.get(&self.connection_id) {
+                Some(processor) => processor.families.clone(),
+                None => vec![],
+            };
+            shared.remove_families(self.connection_id, &families, &mut deferred);
+        }
+        deferred.run();
+
+        let mut response = TpUnregisterResponse::new();
+        response.set_status(TpUnregisterResponse_Status::OK);
+        self.respond(
+            Message_MessageType::TP_UNREGISTER_RESPONSE,
+            correlation_id,
+            &response,
+        )
+    }
+
+    fn process_response(&self, correlation_id: &str, mut response: TpProcessResponse) {
+        let mut deferred = Deferred::default();
+        {
+            let mut shared = lock(&self.shared);
+            let pending = match shared.pending.remove(correlation_id) {
+                Some(pending) if pending.connection_id == self.connection_id => pending,
+                Some(pending) => {
+                    shared.pending.insert(correlation_id.to_owned(), pending);
+                    warn!("Ignoring response to a transaction sent to another processor");
+                    return;
+                }
+                None => {
+                    warn!("Ignoring response to an unknown transaction");
+                    return;
+                }
+            };
+            if let Some(processor) = shared.processors.get_mut(&self.connection_id) {
+                processor.in_flight -= 1;
+            }
+
+            let transaction_id = pending
+                .transaction_pair
+                .transaction()
+                .header_signature()
+                .to_owned();
+            deferred.completions.push(match response.get_status() {
+                _ if pending.failed => {
+                    warn!(
+                        "Retrying {}, as one of its state requests failed",
+                        transaction_id
+                    );
+                    pending.retry(&self.context_manager)
+                }
+                TpProcessResponse_Status::OK => (
+                    pending.on_done,
+                    Ok(ExecutionTaskCompletionNotification::Valid(
+                        pending.context_id,
+                        transaction_id,
+                    )),
+                ),
+                TpProcessResponse_Status::INVALID_TRANSACTION => (
+                    pending.on_done,
+                    Ok(ExecutionTaskCompletionNotification::Invalid(
+                        pending.context_id,
+                        InvalidTransactionResult {
+                            transaction_id,
+                            error_message: response.take_message(),
+                            error_data: response.take_extended_data(),
+                        },
+                    )),
+                ),
+                // As in Sawtooth, a transaction which failed with an internal error is retried
+                _ => {
+                    warn!(
+                        "Transaction processor failed to apply {}: {}",
+                        transaction_id,
+                        response.get_message()
+                    );
+                    pending.retry(&self.context_manager)
+                }
+            });
+            shared.dispatch_queued(&self.context_manager, &mut deferred);
+        }
+        deferred.run();
+    }
+
+    /// Calls `f` with the context of the transaction with the given context id, if it was sent
+    /// to this connection's processor.
+    fn with_context<T, F>(&self, context_id: &str, f: F) -> Result<T, ContextError>
+    where
+        F: FnOnce(&dyn TransactionContext) -> Result<T, ContextError>,
+    {
+        let context_id = match lock(&self.shared).pending.get(context_id) {
+            Some(pending) if pending.connection_id == self.connection_id && !pending.failed => {
+                pending.context_id
+            }
+            _ => {
+                return Err(ContextError::AuthorizationError(format!(
+                    "{} is not the context of a transaction being applied",
+                    context_id
+                )))
+            }
+        };
+
+        f(&StaticContext::new(&self.context_manager, &context_id))
+    }
+
+    /// Marks the transaction with the given context id as failed, so that its processor may make
+    /// no further state requests for it, and it is retried once the processor responds.
+    fn fail_transaction(&self, context_id: &str) {
+        if let Some(pending) = lock(&self.shared).pending.get_mut(context_id) {
+            if pending.connection_id == self.connection_id {
+                pending.failed = true;
+            }
+        }
+    }
+
+    fn respond(
+        &self,
+        message_type: Message_MessageType,
+        correlation_id: &str,
+        response: &dyn protobuf::Message,
+    ) -> io::Result<()> {
+        send(&self.writer, message_type, correlation_id, response)
+    }
+}
+
+fn process_request(
+    transaction_pair: &TransactionPair,
+    context_id: &str,
+    raw_header: bool,
+) -> io::Result<TpProcessRequest> {
+    let transaction = transaction_pair.transaction();
+    let mut request = TpProcessRequest::new();
+    if raw_header {
+        request.set_header_bytes(transaction.header().to_vec());
+    } else {
+        request.set_header(parse(transaction.header())?);
+    }
+    request.set_payload(transaction.payload().to_vec());
+    request.set_signature(transaction.header_signature().to_owned());
+    request.set_context_id(context_id.to_owned());
+    Ok(request)
+}
+
+fn send(
+    writer: &Mutex<Stream>,
+    message_type: Message_MessageType,
+    correlation_id: &str,
+    content: &dyn protobuf::Message,
+) -> io::Result<()> {
+    let bytes = encode(message_type, correlation_id, content)?;
+    zmtp::write_message(&mut *lock(writer), &bytes)
+}
+
+fn encode(
+    message_type: Message_MessageType,
+    correlation_id: &str,
+    content: &dyn protobuf::Message,
+) -> io::Result<Vec<u8>> {
+    let mut message = Message::new();
+    message.set_message_type(message_type);
+    message.set_correlation_id(correlation_id.to_owned());
+    message.set_content(content.write_to_bytes().map_err(protobuf_error)?);
+    message.write_to_bytes().map_err(protobuf_error)
+}
+
+fn parse<M: protobuf::Message>(bytes: &[u8]) -> io::Result<M> {
+    protobuf::parse_from_bytes(bytes).map_err(protobuf_error)
+}
+
+fn protobuf_error(err: protobuf::ProtobufError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .expect("SawtoothTpExecutionAdapter lock was poisoned")
+}
+
+/// Reads from a buffered stream, and writes to its shared writer, during the handshake.
+struct ReadWrite<'a>(&'a mut BufReader<Stream>, &'a Mutex<Stream>);
+
+impl<'a> Read for ReadWrite<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<'a> Write for ReadWrite<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(self.1).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(self.1).flush()
+    }
+}
+
+/// A socket listening for transaction processors.
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(endpoint: &str) -> io::Result<Self> {
+        if endpoint.starts_with("tcp://") {
+            Ok(Listener::Tcp(TcpListener::bind(
+                &endpoint["tcp://".len()..],
+            )?))
+        } else if endpoint.starts_with("ipc://") {
+            let path = PathBuf::from(&endpoint["ipc://".len()..]);
+            // Replace a socket left behind by a previous process, as ZeroMQ does
+            if let Ok(metadata) = fs::symlink_metadata(&path) {
+                if metadata.file_type().is_socket() {
+                    fs::remove_file(&path)?;
+                }
+            }
+            Ok(Listener::Unix(UnixListener::bind(&path)?, path))
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a tcp:// or ipc:// endpoint", endpoint),
+            ))
+        }
+    }
+
+    fn endpoint(&self) -> io::Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(format!("tcp://{}", listener.local_addr()?)),
+            Listener::Unix(_, path) => Ok(format!("ipc://{}", path.display())),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => Ok(Stream::Tcp(listener.accept()?.0)),
+            Listener::Unix(listener, _) => Ok(Stream::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+/// A connection to a transaction processor.
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => Ok(Stream::Tcp(stream.try_clone()?)),
+            Stream::Unix(stream) => Ok(Stream::Unix(stream.try_clone()?)),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap as StdHashMap;
+    use std::process::{Child, Stdio};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::Instant;
+
+    use sawtooth_sdk::processor::TransactionProcessor;
+    use sawtooth_xo::handler::XoTransactionHandler;
+    use sha2::{Digest, Sha512};
+
+    use crate::context::ContextLifecycle;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::protos::events::Event as ProtoEvent;
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::command::{make_command_transaction, Command};
+
+    /// The environment variable naming the endpoint which `run_xo_processor` connects to.
+    const XO_ENDPOINT_VAR: &str = "TRANSACT_TEST_XO_ENDPOINT";
+
+    /// Apply transactions with a transaction processor, checking that its state requests are
+    /// applied to the transaction's context, and that the transaction it is applying when it
+    /// disconnects is rolled back and returned with a routing error.
+    #[test]
+    fn apply_sawtooth_tp_adapter() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&StdHashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter =
+            SawtoothTpExecutionAdapter::new_adapter("tcp://127.0.0.1:0", context_manager.clone())
+                .expect("Could not create adapter");
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let mut processor = TestProcessor::connect(adapter.endpoint());
+        let response = processor.register("command", "0.1", 1);
+        assert_eq!(TpRegisterResponse_Status::OK, response.get_status());
+        assert_eq!(PROTOCOL_VERSION, response.get_protocol_version());
+        assert_eq!(
+            vec![TransactionFamily::new("command".into(), "0.1".into())],
+            *registry.families.lock().unwrap()
+        );
+
+        // A valid transaction, which sets a value, adds receipt data and an event, and is denied
+        // a write outside of its outputs
+        let txn_pair = set_transaction("abcd", b"abc");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+        let results = execute(&adapter, txn_pair, context_id);
+
+        let request = processor.process_request();
+        assert_eq!(&["abcd".to_owned()][..], request.get_header().get_outputs());
+        assert!(request.get_header_bytes().is_empty());
+        let context = request.get_context_id().to_owned();
+
+        let response: TpStateSetResponse = processor.set(&context, "abcd", b"abc");
+        assert_eq!(TpStateSetResponse_Status::OK, response.get_status());
+        assert_eq!(&["abcd".to_owned()][..], response.get_addresses());
+
+        let mut get = TpStateGetRequest::new();
+        get.set_context_id(context.clone());
+        get.set_addresses(RepeatedField::from_vec(vec!["abcd".into()]));
+        let response: TpStateGetResponse = processor.request(
+            Message_MessageType::TP_STATE_GET_REQUEST,
+            &get,
+            Message_MessageType::TP_STATE_GET_RESPONSE,
+        );
+        assert_eq!(TpStateGetResponse_Status::OK, response.get_status());
+        assert_eq!(b"abc", response.get_entries()[0].get_data());
+
+        let response: TpStateSetResponse = processor.set(&context, "ef01", b"abc");
+        assert_eq!(
+            TpStateSetResponse_Status::AUTHORIZATION_ERROR,
+            response.get_status()
+        );
+
+        let mut add_data = TpReceiptAddDataRequest::new();
+        add_data.set_context_id(context.clone());
+        add_data.set_data(b"data".to_vec());
+        let response: TpReceiptAddDataResponse = processor.request(
+            Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST,
+            &add_data,
+            Message_MessageType::TP_RECEIPT_ADD_DATA_RESPONSE,
+        );
+        assert_eq!(TpReceiptAddDataResponse_Status::OK, response.get_status());
+
+        let mut event = ProtoEvent::new();
+        event.set_event_type("command/set".into());
+        let mut add_event = TpEventAddRequest::new();
+        add_event.set_context_id(context.clone());
+        add_event.set_event(event);
+        let response: TpEventAddResponse = processor.request(
+            Message_MessageType::TP_EVENT_ADD_REQUEST,
+            &add_event,
+            Message_MessageType::TP_EVENT_ADD_RESPONSE,
+        );
+        assert_eq!(TpEventAddResponse_Status::OK, response.get_status());
+
+        processor.respond(&request, TpProcessResponse_Status::OK, "");
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id.clone()),
+            results.recv().unwrap().unwrap()
+        );
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .unwrap();
+        assert_eq!(1, receipt.state_changes.len());
+        assert_eq!(vec![b"data".to_vec()], receipt.data);
+        assert_eq!("command/set", receipt.events[0].event_type);
+
+        // An invalid transaction
+        let txn_pair = set_transaction("abcd", b"def");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+        let results = execute(&adapter, txn_pair, context_id);
+
+        let request = processor.process_request();
+        processor.respond(
+            &request,
+            TpProcessResponse_Status::INVALID_TRANSACTION,
+            "Test Fail Succeeded",
+        );
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: txn_id,
+                    error_message: "Test Fail Succeeded".into(),
+                    error_data: vec![],
+                }
+            ),
+            results.recv().unwrap().unwrap()
+        );
+
+        // A transaction whose state request fails, as its context's state root is unknown, which
+        // is retried once the processor responds, and whose context may not be used again
+        let txn_pair = set_transaction("abcd", b"def");
+        let context_id = context_manager.create_context(&[], "unknown");
+        let results = execute(&adapter, txn_pair, context_id);
+
+        let request = processor.process_request();
+        let context = request.get_context_id().to_owned();
+        let mut get = TpStateGetRequest::new();
+        get.set_context_id(context.clone());
+        get.set_addresses(RepeatedField::from_vec(vec!["abcd".into()]));
+        let response: TpStateGetResponse = processor.request(
+            Message_MessageType::TP_STATE_GET_REQUEST,
+            &get,
+            Message_MessageType::TP_STATE_GET_RESPONSE,
+        );
+        assert_eq!(
+            TpStateGetResponse_Status::AUTHORIZATION_ERROR,
+            response.get_status()
+        );
+        let response = processor.set(&context, "abcd", b"def");
+        assert_eq!(
+            TpStateSetResponse_Status::AUTHORIZATION_ERROR,
+            response.get_status()
+        );
+        assert!(context_manager
+            .get(&context_id, &["abcd".to_owned()])
+            .is_err());
+        processor.respond(&request, TpProcessResponse_Status::OK, "");
+        match results.recv().unwrap() {
+            Err(ExecutionAdapterError::RoutingError(_)) => (),
+            res => panic!("Expected a routing error, got {:?}", res),
+        }
+
+        // A transaction which is being applied when the processor disconnects
+        let txn_pair = set_transaction("abcd", b"ghi");
+        let context_id = context_manager.create_context(&[], &state_id);
+        let results = execute(&adapter, txn_pair, context_id);
+
+        let request = processor.process_request();
+        let response = processor.set(request.get_context_id(), "abcd", b"ghi");
+        assert_eq!(TpStateSetResponse_Status::OK, response.get_status());
+        drop(processor);
+
+        match results.recv().unwrap() {
+            Err(ExecutionAdapterError::RoutingError(_)) => (),
+            res => panic!("Expected a routing error, got {:?}", res),
+        }
+        assert!(context_manager
+            .get(&context_id, &["abcd".to_owned()])
+            .unwrap()
+            .is_empty());
+        assert!(registry.families.lock().unwrap().is_empty());
+
+        assert!(Box::new(adapter).stop().is_ok());
+    }
+
+    /// Queue transactions for a processor which is busy, over a Unix socket, and return the
+    /// queued transactions with a routing error once the processor unregisters.
+    #[test]
+    fn sawtooth_tp_adapter_queue_and_unregister() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&StdHashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let path = std::env::temp_dir().join(format!("transact-tp-{}.sock", Uuid::new_v4()));
+        let endpoint = format!("ipc://{}", path.display());
+        let mut adapter =
+            SawtoothTpExecutionAdapter::new_adapter(&endpoint, context_manager.clone())
+                .expect("Could not create adapter");
+        assert_eq!(endpoint, adapter.endpoint());
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let mut processor = TestProcessor::connect(adapter.endpoint());
+        assert_eq!(
+            TpRegisterResponse_Status::OK,
+            processor.register("command", "0.1", 1).get_status()
+        );
+
+        let first_context_id = context_manager.create_context(&[], &state_id);
+        let first_results = execute(&adapter, set_transaction("abcd", b"1"), first_context_id);
+        let second_context_id = context_manager.create_context(&[], &state_id);
+        let second_results = execute(&adapter, set_transaction("abcd", b"2"), second_context_id);
+        let third_context_id = context_manager.create_context(&[], &state_id);
+        let third_results = execute(&adapter, set_transaction("abcd", b"3"), third_context_id);
+
+        // Only one transaction is sent at a time
+        let request = processor.process_request();
+        assert_eq!(
+            b"1",
+            &request.get_payload()[request.get_payload().len() - 1..]
+        );
+        processor.respond(&request, TpProcessResponse_Status::OK, "");
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(
+                first_context_id,
+                request.get_signature().to_owned()
+            ),
+            first_results.recv().unwrap().unwrap()
+        );
+
+        let request = processor.process_request();
+        assert_eq!(
+            b"2",
+            &request.get_payload()[request.get_payload().len() - 1..]
+        );
+
+        // Unregistering returns the queued transaction, but not the one being applied
+        let response: TpUnregisterResponse = processor.request(
+            Message_MessageType::TP_UNREGISTER_REQUEST,
+            &crate::protos::processor::TpUnregisterRequest::new(),
+            Message_MessageType::TP_UNREGISTER_RESPONSE,
+        );
+        assert_eq!(TpUnregisterResponse_Status::OK, response.get_status());
+        assert!(registry.families.lock().unwrap().is_empty());
+        match third_results.recv().unwrap() {
+            Err(ExecutionAdapterError::RoutingError(_)) => (),
+            res => panic!("Expected a routing error, got {:?}", res),
+        }
+
+        processor.respond(&request, TpProcessResponse_Status::OK, "");
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(
+                second_context_id,
+                request.get_signature().to_owned()
+            ),
+            second_results.recv().unwrap().unwrap()
+        );
+
+        assert!(Box::new(adapter).stop().is_ok());
+        assert!(!path.exists());
+    }
+
+    /// Apply a transaction with the XO transaction processor from the Sawtooth SDK, connected
+    /// over TCP.
+    #[test]
+    fn sawtooth_tp_adapter_xo_processor_tcp() {
+        apply_xo_transaction("tcp://127.0.0.1:0");
+    }
+
+    /// Apply a transaction with the XO transaction processor from the Sawtooth SDK, connected
+    /// over a Unix socket.
+    #[test]
+    fn sawtooth_tp_adapter_xo_processor_ipc() {
+        let path = std::env::temp_dir().join(format!("transact-tp-{}.sock", Uuid::new_v4()));
+        apply_xo_transaction(&format!("ipc://{}", path.display()));
+    }
+
+    /// Runs the XO transaction processor from the Sawtooth SDK against the endpoint in
+    /// `XO_ENDPOINT_VAR`, until it is killed.
+    ///
+    /// The SDK's processor installs a process-wide signal handler, which may only be done once, so
+    /// each one is run in its own process: this test binary, running only this test.
+    #[test]
+    #[ignore]
+    fn run_xo_processor() {
+        if let Ok(endpoint) = std::env::var(XO_ENDPOINT_VAR) {
+            let handler = XoTransactionHandler::new();
+            let mut processor = TransactionProcessor::new(&endpoint);
+            processor.add_handler(&handler);
+            processor.start();
+        }
+    }
+
+    /// Starts an adapter on the given endpoint and connects the XO transaction processor to it,
+    /// checking that the game created by an XO transaction is set in the transaction's context.
+    fn apply_xo_transaction(endpoint: &str) {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&StdHashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter =
+            SawtoothTpExecutionAdapter::new_adapter(endpoint, context_manager.clone())
+                .expect("Could not create adapter");
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let mut processor = XoProcessor::spawn(adapter.endpoint());
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while registry.families.lock().unwrap().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "The XO transaction processor did not register"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            vec![TransactionFamily::new("xo".into(), "1.0".into())],
+            *registry.families.lock().unwrap()
+        );
+
+        let address = xo_address("game1");
+        let txn_pair = TransactionBuilder::new()
+            .with_batcher_public_key(vec![0u8, 0u8, 0u8, 0u8])
+            .with_family_name("xo".into())
+            .with_family_version("1.0".into())
+            .with_inputs(vec![hex::decode(&address).unwrap()])
+            .with_outputs(vec![hex::decode(&address).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"game1,create,".to_vec())
+            .build_pair(&HashSigner::new())
+            .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let results = execute(&adapter, txn_pair, context_id);
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id),
+            results
+                .recv_timeout(Duration::from_secs(30))
+                .expect("The transaction was not applied")
+                .unwrap()
+        );
+        assert_eq!(
+            vec![(address.clone(), b"game1,---------,P1-NEXT,,".to_vec())],
+            context_manager.get(&context_id, &[address]).unwrap()
+        );
+
+        processor.kill();
+        assert!(Box::new(adapter).stop().is_ok());
+    }
+
+    /// Returns the address of the XO game with the given name.
+    fn xo_address(name: &str) -> String {
+        let prefix = hex::encode(Sha512::digest(b"xo"));
+        let game = hex::encode(Sha512::digest(name.as_bytes()));
+        format!("{}{}", &prefix[..6], &game[..64])
+    }
+
+    /// The process running `run_xo_processor`, which is killed when dropped.
+    struct XoProcessor {
+        child: Child,
+    }
+
+    impl XoProcessor {
+        fn spawn(endpoint: &str) -> Self {
+            let child = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(&[
+                    "--exact",
+                    "execution::adapter::sawtooth_tp_adapter::test::run_xo_processor",
+                    "--ignored",
+                ])
+                .env(XO_ENDPOINT_VAR, endpoint)
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("Unable to run the XO transaction processor");
+            XoProcessor { child }
+        }
+
+        fn kill(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    impl Drop for XoProcessor {
+        fn drop(&mut self) {
+            self.kill();
+        }
+    }
+
+    fn set_transaction(address: &str, value: &[u8]) -> TransactionPair {
+        make_command_transaction(&[Command::Set {
+            address: address.into(),
+            value: value.to_vec(),
+        }])
+        .expect("Unable to build transaction")
+    }
+
+    fn execute(
+        adapter: &SawtoothTpExecutionAdapter,
+        txn_pair: TransactionPair,
+        context_id: ContextId,
+    ) -> Receiver<Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>> {
+        let (send, recv) = channel();
+        assert!(adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        recv
+    }
+
+    /// The transaction processor side of a connection.
+    struct TestProcessor {
+        stream: Stream,
+    }
+
+    impl TestProcessor {
+        fn connect(endpoint: &str) -> Self {
+            let mut stream = if endpoint.starts_with("tcp://") {
+                Stream::Tcp(TcpStream::connect(&endpoint["tcp://".len()..]).unwrap())
+            } else {
+                Stream::Unix(UnixStream::connect(&endpoint["ipc://".len()..]).unwrap())
+            };
+            zmtp::handshake(&mut stream, b"DEALER").unwrap();
+            TestProcessor { stream }
+        }
+
+        fn register(
+            &mut self,
+            family: &str,
+            version: &str,
+            max_occupancy: u32,
+        ) -> TpRegisterResponse {
+            let mut request = TpRegisterRequest::new();
+            request.set_family(family.into());
+            request.set_version(version.into());
+            request.set_max_occupancy(max_occupancy);
+            request.set_protocol_version(PROTOCOL_VERSION);
+            self.request(
+                Message_MessageType::TP_REGISTER_REQUEST,
+                &request,
+                Message_MessageType::TP_REGISTER_RESPONSE,
+            )
+        }
+
+        fn set(&mut self, context_id: &str, address: &str, data: &[u8]) -> TpStateSetResponse {
+            let mut entry = TpStateEntry::new();
+            entry.set_address(address.into());
+            entry.set_data(data.to_vec());
+            let mut request = TpStateSetRequest::new();
+            request.set_context_id(context_id.into());
+            request.set_entries(RepeatedField::from_vec(vec![entry]));
+            self.request(
+                Message_MessageType::TP_STATE_SET_REQUEST,
+                &request,
+                Message_MessageType::TP_STATE_SET_RESPONSE,
+            )
+        }
+
+        /// Receives the next process request, keeping its correlation id in its context id.
+        fn process_request(&mut self) -> TpProcessRequest {
+            let message = self.recv();
+            assert_eq!(
+                Message_MessageType::TP_PROCESS_REQUEST,
+                message.get_message_type()
+            );
+            let request: TpProcessRequest = parse(message.get_content()).unwrap();
+            assert_eq!(message.get_correlation_id(), request.get_context_id());
+            request
+        }
+
+        fn respond(
+            &mut self,
+            request: &TpProcessRequest,
+            status: TpProcessResponse_Status,
+            message: &str,
+        ) {
+            let mut response = TpProcessResponse::new();
+            response.set_status(status);
+            response.set_message(message.into());
+            self.send(
+                Message_MessageType::TP_PROCESS_RESPONSE,
+                request.get_context_id(),
+                &response,
+            );
+        }
+
+        fn request<R: protobuf::Message>(
+            &mut self,
+            message_type: Message_MessageType,
+            request: &dyn protobuf::Message,
+            response_type: Message_MessageType,
+        ) -> R {
+            let correlation_id = Uuid::new_v4().to_string();
+            self.send(message_type, &correlation_id, request);
+            let message = self.recv();
+            assert_eq!(response_type, message.get_message_type());
+            assert_eq!(correlation_id, message.get_correlation_id());
+            parse(message.get_content()).unwrap()
+        }
+
+        fn send(
+            &mut self,
+            message_type: Message_MessageType,
+            correlation_id: &str,
+            content: &dyn protobuf::Message,
+        ) {
+            let mut message = Message::new();
+            message.set_message_type(message_type);
+            message.set_correlation_id(correlation_id.into());
+            message.set_content(content.write_to_bytes().unwrap());
+            zmtp::write_message(&mut self.stream, &message.write_to_bytes().unwrap()).unwrap();
+        }
+
+        fn recv(&mut self) -> Message {
+            parse(
+                zmtp::read_message(&mut self.stream)
+                    .unwrap()
+                    .last()
+                    .unwrap(),
+            )
+            .unwrap()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        families: Arc<Mutex<Vec<TransactionFamily>>>,
+    }
+
+    impl ExecutionRegistry for MockRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.families.lock().unwrap().push(family);
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.families.lock().unwrap().retain(|f| f != family);
+        }
+    }
+}
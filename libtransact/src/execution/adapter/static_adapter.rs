@@ -18,7 +18,9 @@
 //!
 //! This module provides the `StaticExecutionAdapter`, an implementation of `ExecutionAdapter`
 //! which execute transactions via `TransactionHandler` instances directly.
-use std::sync::mpsc::{channel, Sender};
+use std::ops::Deref;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use crate::context::manager::sync::ContextManager;
@@ -26,7 +28,7 @@ use crate::context::manager::ContextManagerError;
 use crate::context::ContextId;
 use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
 use crate::execution::{ExecutionRegistry, TransactionFamily};
-use crate::handler::{ApplyError, ContextError, TransactionContext, TransactionHandler};
+use crate::handler::{ApplyError, ContextError, Savepoint, TransactionContext, TransactionHandler};
 use crate::protocol::receipt::Event;
 use crate::protocol::transaction::TransactionPair;
 use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
@@ -38,9 +40,9 @@ type OnDoneCallback =
 /// The StaticExecutionAdapter to wrap TransactionHandlers
 ///
 /// This struct takes a series of transaction handlers which can be used to execution transactions.
-/// These transactions are executed on a single background thread.
+/// These transactions are executed on one or more background worker threads.
 pub struct StaticExecutionAdapter {
-    join_handle: thread::JoinHandle<bool>,
+    join_handles: Vec<thread::JoinHandle<bool>>,
     sender: Sender<StaticAdapterCommand>,
 }
 
@@ -49,7 +51,8 @@ impl StaticExecutionAdapter {
     ///
     /// Creates a `StaticExecutionAdapter` wrapping the given `TransactionHandler` vector and a
     /// `ContextManager` instance. This adapter will dispatch transaction pairs to the appropriate
-    /// handler, if found.
+    /// handler, if found.  Transactions are executed one at a time, on a single background
+    /// thread.
     ///
     /// # Errors
     ///
@@ -59,43 +62,121 @@ impl StaticExecutionAdapter {
         context_manager: ContextManager,
     ) -> Result<Self, ExecutionAdapterError> {
         let (sender, receiver) = channel();
-        let join_handle = thread::Builder::new()
-            .name("StaticExecutionAdapter".into())
-            .spawn(move || {
-                while let Ok(cmd) = receiver.recv() {
-                    match cmd {
-                        StaticAdapterCommand::Execute(execute_cmd) => {
-                            let (txn_pair, context_id, on_done) = *execute_cmd;
-                            debug!("Executing {:?} in context {:?}", &txn_pair, &context_id);
-                            execute_transaction(
-                                &handlers,
-                                txn_pair,
-                                &context_manager,
-                                context_id,
-                                on_done,
-                            );
-                        }
-                        StaticAdapterCommand::Start(mut execution_registry) => {
-                            register_handlers(&handlers, &mut *execution_registry);
-                        }
-                        StaticAdapterCommand::Stop => {
-                            break;
-                        }
+        let receiver = Arc::new(Mutex::new(receiver));
+        let join_handle = spawn_worker(
+            "StaticExecutionAdapter".into(),
+            Box::new(handlers),
+            context_manager,
+            receiver,
+        )?;
+
+        Ok(StaticExecutionAdapter {
+            join_handles: vec![join_handle],
+            sender,
+        })
+    }
+
+    /// Creates a new adapter which executes transactions on a pool of worker threads.
+    ///
+    /// The handlers and the `ContextManager` are shared by every worker, so up to `worker_count`
+    /// transactions are applied at once; each transaction's `on_done` callback is called by the
+    /// worker which executed it.  At least one worker is started.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if a worker thread cannot be created.
+    pub fn new_adapter_with_workers(
+        handlers: Vec<Box<dyn TransactionHandler + Sync>>,
+        context_manager: ContextManager,
+        worker_count: usize,
+    ) -> Result<Self, ExecutionAdapterError> {
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handlers = Arc::new(handlers);
+
+        let mut join_handles: Vec<thread::JoinHandle<bool>> =
+            Vec::with_capacity(worker_count.max(1));
+        for i in 0..worker_count.max(1) {
+            let join_handle = match spawn_worker(
+                format!("StaticExecutionAdapter-{}", i),
+                handlers.clone(),
+                context_manager.clone(),
+                receiver.clone(),
+            ) {
+                Ok(join_handle) => join_handle,
+                Err(err) => {
+                    // Stop the workers which were started
+                    for _ in &join_handles {
+                        let _ = sender.send(StaticAdapterCommand::Stop);
+                    }
+                    for join_handle in join_handles {
+                        let _ = join_handle.join();
                     }
+                    return Err(err);
                 }
-                true
-            })
-            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+            };
+            join_handles.push(join_handle);
+        }
 
         Ok(StaticExecutionAdapter {
-            join_handle,
+            join_handles,
             sender,
         })
     }
 }
 
-fn execute_transaction(
-    handlers: &[Box<dyn TransactionHandler>],
+/// Spawns a worker thread which executes the commands it takes from the shared receiver, until it
+/// takes a stop command.
+fn spawn_worker<H, T>(
+    name: String,
+    handlers: H,
+    context_manager: ContextManager,
+    receiver: Arc<Mutex<Receiver<StaticAdapterCommand>>>,
+) -> Result<thread::JoinHandle<bool>, ExecutionAdapterError>
+where
+    H: Deref<Target = Vec<Box<T>>> + Send + 'static,
+    T: TransactionHandler + ?Sized,
+{
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            loop {
+                // The lock is only held while waiting for the next command
+                let cmd = match receiver
+                    .lock()
+                    .expect("StaticExecutionAdapter receiver lock was poisoned")
+                    .recv()
+                {
+                    Ok(cmd) => cmd,
+                    Err(_) => break,
+                };
+                match cmd {
+                    StaticAdapterCommand::Execute(execute_cmd) => {
+                        let (txn_pair, context_id, on_done) = *execute_cmd;
+                        debug!("Executing {:?} in context {:?}", &txn_pair, &context_id);
+                        execute_transaction(
+                            &handlers,
+                            txn_pair,
+                            &context_manager,
+                            context_id,
+                            on_done,
+                        );
+                    }
+                    StaticAdapterCommand::Start(mut execution_registry) => {
+                        register_handlers(&handlers, &mut *execution_registry);
+                    }
+                    StaticAdapterCommand::Stop => {
+                        break;
+                    }
+                }
+            }
+            true
+        })
+        .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))
+}
+
+fn execute_transaction<T: TransactionHandler + ?Sized>(
+    handlers: &[Box<T>],
     transaction_pair: TransactionPair,
     context_manager: &ContextManager,
     context_id: ContextId,
@@ -110,9 +191,13 @@ fn execute_transaction(
                 .any(|v| v == family.family_version())
     }) {
         Some(handler) => {
-            let mut static_context = StaticContext::new(context_manager, &context_id);
+            let result = restrict_context(context_manager, &context_id, &transaction_pair)
+                .and_then(|_| {
+                    let mut static_context = StaticContext::new(context_manager, &context_id);
+                    handler.apply(&transaction_pair, &mut static_context)
+                });
 
-            match handler.apply(&transaction_pair, &mut static_context) {
+            match result {
                 Ok(_) => on_done(Ok(ExecutionTaskCompletionNotification::Valid(
                     context_id,
                     transaction_pair.transaction().header_signature().to_owned(),
@@ -141,8 +226,8 @@ fn execute_transaction(
     };
 }
 
-fn register_handlers(
-    handlers: &[Box<dyn TransactionHandler>],
+fn register_handlers<T: TransactionHandler + ?Sized>(
+    handlers: &[Box<T>],
     execution_registry: &mut ExecutionRegistry,
 ) {
     for handler in handlers {
@@ -191,15 +276,23 @@ impl ExecutionAdapter for StaticExecutionAdapter {
     }
 
     fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
-        self.sender
-            .send(StaticAdapterCommand::Stop)
-            .map_err(|err| {
-                ExecutionOperationError::StopError(format!("Unable to send stop command: {}", err))
-            })?;
+        // Each worker stops after taking a single stop command
+        for _ in &self.join_handles {
+            self.sender
+                .send(StaticAdapterCommand::Stop)
+                .map_err(|err| {
+                    ExecutionOperationError::StopError(format!(
+                        "Unable to send stop command: {}",
+                        err
+                    ))
+                })?;
+        }
 
-        self.join_handle.join().map_err(|_| {
-            ExecutionOperationError::StopError("Unable to join internal thread.".into())
-        })?;
+        for join_handle in self.join_handles {
+            join_handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join internal thread.".into())
+            })?;
+        }
 
         Ok(())
     }
@@ -211,13 +304,30 @@ enum StaticAdapterCommand {
     Execute(Box<(TransactionPair, ContextId, OnDoneCallback)>),
 }
 
-struct StaticContext<'a, 'b> {
+/// Restricts the given context to the transaction's inputs and outputs, so that the context
+/// manager refuses any read or write on its behalf outside of them.
+pub(crate) fn restrict_context(
+    context_manager: &ContextManager,
+    context_id: &ContextId,
+    transaction_pair: &TransactionPair,
+) -> Result<(), ApplyError> {
+    let header = transaction_pair.header();
+    context_manager
+        .set_address_permissions(
+            context_id,
+            header.inputs().iter().map(hex::encode).collect(),
+            header.outputs().iter().map(hex::encode).collect(),
+        )
+        .map_err(|err| ApplyError::InternalError(format!("Unable to restrict context: {}", err)))
+}
+
+pub(crate) struct StaticContext<'a, 'b> {
     context_manager: &'a ContextManager,
     context_id: &'b ContextId,
 }
 
 impl<'a, 'b> StaticContext<'a, 'b> {
-    fn new(context_manager: &'a ContextManager, context_id: &'b ContextId) -> Self {
+    pub(crate) fn new(context_manager: &'a ContextManager, context_id: &'b ContextId) -> Self {
         StaticContext {
             context_manager,
             context_id,
@@ -235,6 +345,12 @@ impl<'a, 'b> TransactionContext for StaticContext<'a, 'b> {
             .map_err(ContextError::from)
     }
 
+    fn list_state_entries(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        self.context_manager
+            .list(self.context_id, prefix)
+            .map_err(ContextError::from)
+    }
+
     fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
         for (address, value) in entries.into_iter() {
             self.context_manager
@@ -258,6 +374,18 @@ impl<'a, 'b> TransactionContext for StaticContext<'a, 'b> {
         Ok(results)
     }
 
+    fn savepoint(&self) -> Result<Savepoint, ContextError> {
+        self.context_manager
+            .savepoint(self.context_id)
+            .map_err(ContextError::from)
+    }
+
+    fn rollback_to(&self, savepoint: &Savepoint) -> Result<(), ContextError> {
+        self.context_manager
+            .rollback_to(self.context_id, savepoint)
+            .map_err(ContextError::from)
+    }
+
     fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
         self.context_manager
             .add_data(self.context_id, data)
@@ -285,8 +413,12 @@ impl<'a, 'b> TransactionContext for StaticContext<'a, 'b> {
 
 impl From<ContextManagerError> for ContextError {
     fn from(err: ContextManagerError) -> Self {
-        // Error's should be addressed in the handler::error module.
-        ContextError::SendError(Box::new(err))
+        match err {
+            ContextManagerError::AuthorizationError(msg) => ContextError::AuthorizationError(msg),
+            ContextManagerError::InvalidSavepoint(msg) => ContextError::InvalidSavepoint(msg),
+            // Error's should be addressed in the handler::error module.
+            err => ContextError::SendError(Box::new(err)),
+        }
     }
 }
 
@@ -297,11 +429,14 @@ mod test {
     use std::collections::HashMap;
     use std::sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Condvar,
     };
+    use std::time::{Duration, Instant};
 
     use crate::context::ContextLifecycle;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
     use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+    use crate::signing::hash::HashSigner;
     use crate::state::hashmap::HashMapState;
     use crate::workload::command::{make_command_transaction, Command, CommandTransactionHandler};
 
@@ -325,9 +460,10 @@ mod test {
 
         // Create and execute a simple transaction
         let txn_pair = make_command_transaction(&[Command::Set {
-            address: "abc".into(),
+            address: "abcd".into(),
             value: b"abc".to_vec(),
-        }]);
+        }])
+        .expect("Unable to build transaction");
         let txn_id = txn_pair.transaction().header_signature().into();
         let context_id = context_manager.create_context(&[], &state_id);
 
@@ -348,9 +484,9 @@ mod test {
             result.unwrap()
         );
         assert_eq!(
-            vec![("abc".to_owned(), b"abc".to_vec())],
+            vec![("abcd".to_owned(), b"abc".to_vec())],
             context_manager
-                .get(&context_id, &["abc".to_owned()])
+                .get(&context_id, &["abcd".to_owned()])
                 .unwrap()
         );
 
@@ -378,12 +514,13 @@ mod test {
         // Create and execute a failing transaction.
         let txn_pair = make_command_transaction(&[
             Command::Get {
-                address: "abc".into(),
+                address: "abcd".into(),
             },
             Command::Fail {
                 error_msg: "Test Fail Succeeded".into(),
             },
-        ]);
+        ])
+        .expect("Unable to build transaction");
 
         let txn_id = txn_pair.transaction().header_signature().to_owned();
         let context_id = context_manager.create_context(&[], &state_id);
@@ -415,6 +552,196 @@ mod test {
         assert!(Box::new(static_adapter).stop().is_ok());
     }
 
+    /// Apply the static adapter with a transaction that writes to an address
+    /// outside of its declared outputs.
+    #[test]
+    fn apply_static_adapter_unauthorized_write() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let handler = CommandTransactionHandler::new();
+
+        let mut static_adapter =
+            StaticExecutionAdapter::new_adapter(vec![Box::new(handler)], context_manager.clone())
+                .expect("Could not create adapter");
+
+        assert!(static_adapter.start(Box::new(registry.clone())).is_ok());
+
+        // Create a transaction which declares "abcd" but sets "ef01"
+        let declared = make_command_transaction(&[Command::Set {
+            address: "abcd".into(),
+            value: b"abc".to_vec(),
+        }])
+        .expect("Unable to build transaction");
+        let txn_pair = TransactionBuilder::new()
+            .with_batcher_public_key(vec![0u8, 0u8, 0u8, 0u8])
+            .with_family_name(declared.header().family_name().into())
+            .with_family_version(declared.header().family_version().into())
+            .with_inputs(declared.header().inputs().to_vec())
+            .with_outputs(declared.header().outputs().to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(
+                Command::Set {
+                    address: "ef01".into(),
+                    value: b"abc".to_vec(),
+                }
+                .to_string()
+                .into_bytes(),
+            )
+            .build_pair(&HashSigner::new())
+            .expect("Unable to build transaction");
+
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let (send, recv) = std::sync::mpsc::channel();
+        assert!(static_adapter
+            .execute(
+                txn_pair,
+                context_id.clone(),
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        let result = recv.recv().unwrap();
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: txn_id,
+                    error_message: "AuthorizationError: ef01 is not in the transaction's outputs"
+                        .into(),
+                    error_data: vec![],
+                }
+            ),
+            result.unwrap()
+        );
+        assert!(context_manager
+            .get_transaction_receipt(&context_id, "")
+            .unwrap()
+            .state_changes
+            .is_empty());
+
+        assert!(Box::new(static_adapter).stop().is_ok());
+    }
+
+    /// Apply transactions on a pool of workers, where each transaction can only be applied while
+    /// another one is being applied at the same time.
+    #[test]
+    fn apply_static_adapter_worker_pool() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let handler = ConcurrentHandler {
+            inner: CommandTransactionHandler::new(),
+            started: Arc::new((Mutex::new(0), Condvar::new())),
+        };
+
+        let mut static_adapter = StaticExecutionAdapter::new_adapter_with_workers(
+            vec![Box::new(handler)],
+            context_manager.clone(),
+            4,
+        )
+        .expect("Could not create adapter");
+
+        assert!(static_adapter.start(Box::new(registry.clone())).is_ok());
+
+        let (send, recv) = std::sync::mpsc::channel();
+        let mut expected = HashMap::new();
+        for i in 0..8u8 {
+            let address = hex::encode([i, i]);
+            let txn_pair = make_command_transaction(&[Command::Set {
+                address: address.clone(),
+                value: vec![i],
+            }])
+            .expect("Unable to build transaction");
+            let txn_id: String = txn_pair.transaction().header_signature().into();
+            let context_id = context_manager.create_context(&[], &state_id);
+            expected.insert(context_id, (txn_id, address, vec![i]));
+
+            let send = send.clone();
+            assert!(static_adapter
+                .execute(
+                    txn_pair,
+                    context_id,
+                    Box::new(move |res| {
+                        send.send(res).expect("Unable to send result");
+                    }),
+                )
+                .is_ok());
+        }
+
+        for _ in 0..expected.len() {
+            match recv.recv().unwrap().unwrap() {
+                ExecutionTaskCompletionNotification::Valid(context_id, txn_id) => {
+                    let (expected_txn_id, address, value) = &expected[&context_id];
+                    assert_eq!(expected_txn_id, &txn_id);
+                    assert_eq!(
+                        vec![(address.clone(), value.clone())],
+                        context_manager
+                            .get(&context_id, std::slice::from_ref(address))
+                            .unwrap()
+                    );
+                }
+                res => panic!("Expected a valid transaction, got {:?}", res),
+            }
+        }
+
+        assert!(Box::new(static_adapter).stop().is_ok());
+    }
+
+    /// A handler which fails a transaction unless another transaction has been started while it
+    /// is being applied, or was already being applied.
+    struct ConcurrentHandler {
+        inner: CommandTransactionHandler,
+        started: Arc<(Mutex<usize>, Condvar)>,
+    }
+
+    impl TransactionHandler for ConcurrentHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            transaction: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            let (lock, condvar) = &*self.started;
+            let mut started = lock.lock().unwrap();
+            *started += 1;
+            condvar.notify_all();
+
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while *started < 2 {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(ApplyError::InvalidTransaction(
+                        "No other transaction was applied concurrently".into(),
+                    ));
+                }
+                started = condvar.wait_timeout(started, deadline - now).unwrap().0;
+            }
+            drop(started);
+
+            self.inner.apply(transaction, context)
+        }
+    }
+
     #[derive(Clone, Default)]
     struct MockRegistry {
         registered: Arc<AtomicBool>,
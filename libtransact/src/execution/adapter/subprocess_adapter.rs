@@ -0,0 +1,1340 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! The subprocess execution adapter provides a way to execute transaction handlers in a child
+//! process.
+//!
+//! This module provides the `SubprocessExecutionAdapter`, an implementation of `ExecutionAdapter`
+//! which launches a handler binary as a child process, and the `SubprocessHandlerRunner`, which
+//! serves `TransactionHandler` instances from within such a binary.  The two exchange the messages
+//! defined in `protos/subprocess.proto` over the child's stdin and stdout, each one prefixed with
+//! its length as a 4-byte big-endian integer.
+//!
+//! Once started, the handler sends the transaction families it serves, which the adapter
+//! registers.  For each transaction, the adapter sends a process request; the handler may then
+//! make any number of context requests, each of which is answered by the adapter, before it sends
+//! the result of the transaction.
+//!
+//! If the child exits, or breaks the protocol, while a transaction is being applied, the changes
+//! it made to the transaction's context are rolled back, and the transaction is returned to the
+//! executor with a `RoutingError`, so that it is retried.  A new child is launched for the next
+//! transaction.  If it cannot be launched, the transaction is returned with a `RoutingError`, and
+//! each further launch is attempted only after a backoff which doubles with every failure; once
+//! `MAX_LAUNCH_FAILURES` launches in a row have failed, transactions are returned with a
+//! `GeneralExecutionError` instead.
+//!
+//! Each transaction must be completed within the adapter's timeout, and a newly launched child
+//! must register within the same time.  A child which does not is killed; the transaction's
+//! changes are rolled back and it is returned to the executor with a `TimeoutError`.
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use protobuf::{Message, RepeatedField};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::static_adapter::{restrict_context, StaticContext};
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::{ApplyError, ContextError, TransactionContext, TransactionHandler};
+use crate::protocol::receipt::Event;
+use crate::protocol::transaction::{Transaction, TransactionPair};
+use crate::protos::subprocess::{
+    AdapterMessage, AdapterMessage_oneof_message, ContextRequest, ContextRequest_AddEvent,
+    ContextRequest_AddReceiptData, ContextRequest_Delete, ContextRequest_Get, ContextRequest_List,
+    ContextRequest_Set, ContextRequest_oneof_request, ContextResponse, ContextResponse_Status,
+    Entry, HandlerMessage, HandlerMessage_oneof_message, ProcessRequest, ProcessResponse,
+    ProcessResponse_Status, RegisterRequest, RegisterRequest_Family,
+};
+use crate::protos::{FromNative, FromProto};
+use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+
+// A type declaration to make the use of this complicated type-bounded box easier to work with
+type OnDoneCallback =
+    Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
+
+/// Launches a handler, returning a connection to it.
+type Launcher = Box<dyn FnMut() -> io::Result<HandlerConnection> + Send>;
+
+/// Aborts a connection from another thread, causing any read blocked on it to fail.
+type Abort = Arc<dyn Fn() + Send + Sync>;
+
+/// The largest message which is accepted from the other side of a connection.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// How long a handler is given to exit once its stdin is closed, before it is killed.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a handler is given to register, or to complete a transaction, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait before relaunching a handler after a launch has failed; doubled for each
+/// further failure.
+const RELAUNCH_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest wait before relaunching a handler.
+const MAX_RELAUNCH_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The number of launches in a row which may fail before transactions are no longer returned to
+/// be retried.
+pub const MAX_LAUNCH_FAILURES: u32 = 5;
+
+/// The SubprocessExecutionAdapter to run a handler binary
+///
+/// The handler binary is launched when the adapter is started, and the families it reports are
+/// registered.  Transactions are sent to the handler one at a time, from a single background
+/// thread.
+pub struct SubprocessExecutionAdapter {
+    launcher: Option<Launcher>,
+    context_manager: ContextManager,
+    timeout: Duration,
+    worker: Option<(Sender<SubprocessAdapterCommand>, thread::JoinHandle<()>)>,
+}
+
+impl SubprocessExecutionAdapter {
+    /// Creates a new adapter.
+    ///
+    /// Creates a `SubprocessExecutionAdapter` which runs the given program, with the given
+    /// arguments, as its handler, and applies the handler's context requests through the given
+    /// `ContextManager`.  The program is expected to serve its handlers with a
+    /// `SubprocessHandlerRunner`; its stderr is inherited from the current process.
+    pub fn new_adapter<P: AsRef<Path>>(
+        program: P,
+        args: &[String],
+        context_manager: ContextManager,
+    ) -> Self {
+        let program = program.as_ref().to_path_buf();
+        let args = args.to_vec();
+        Self::with_launcher(
+            Box::new(move || launch_process(&program, &args)),
+            context_manager,
+        )
+    }
+
+    fn with_launcher(launcher: Launcher, context_manager: ContextManager) -> Self {
+        SubprocessExecutionAdapter {
+            launcher: Some(launcher),
+            context_manager,
+            timeout: DEFAULT_TIMEOUT,
+            worker: None,
+        }
+    }
+
+    /// Sets how long the handler is given to register once launched, and to complete each
+    /// transaction; `DEFAULT_TIMEOUT` is used otherwise.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl ExecutionAdapter for SubprocessExecutionAdapter {
+    fn start(
+        &mut self,
+        mut execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        let mut launcher = self.launcher.take().ok_or_else(|| {
+            ExecutionOperationError::StartError(
+                "Subprocess execution adapter was already started".into(),
+            )
+        })?;
+
+        let (connection, families) =
+            launch_handler(&mut launcher, self.timeout).map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start handler process: {}",
+                    err
+                ))
+            })?;
+        for family in &families {
+            execution_registry.register_transaction_family(family.clone());
+        }
+
+        let mut worker = HandlerWorker {
+            launcher,
+            context_manager: self.context_manager.clone(),
+            execution_registry,
+            families,
+            connection: Some(connection),
+            timeout: self.timeout,
+            launch_failures: 0,
+        };
+        let (sender, receiver) = channel();
+        let join_handle = thread::Builder::new()
+            .name("SubprocessExecutionAdapter".into())
+            .spawn(move || worker.run(receiver))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start subprocess execution adapter thread: {}",
+                    err
+                ))
+            })?;
+        self.worker = Some((sender, join_handle));
+
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        let (sender, _) = self.worker.as_ref().ok_or_else(|| {
+            ExecutionOperationError::ExecuteError(
+                "Subprocess execution adapter has not been started".into(),
+            )
+        })?;
+        sender
+            .send(SubprocessAdapterCommand::Execute(Box::new((
+                transaction_pair,
+                context_id,
+                on_done,
+            ))))
+            .map_err(|err| {
+                ExecutionOperationError::ExecuteError(format!(
+                    "Unable to send transaction for subprocess execution: {}",
+                    err
+                ))
+            })
+    }
+
+    fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        if let Some((sender, join_handle)) = self.worker {
+            sender.send(SubprocessAdapterCommand::Stop).map_err(|err| {
+                ExecutionOperationError::StopError(format!("Unable to send stop command: {}", err))
+            })?;
+            join_handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join internal thread.".into())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+enum SubprocessAdapterCommand {
+    Stop,
+    Execute(Box<(TransactionPair, ContextId, OnDoneCallback)>),
+}
+
+/// A connection to a running handler.
+struct HandlerConnection {
+    reader: Box<dyn Read + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Option<Arc<Mutex<Child>>>,
+    abort: Abort,
+}
+
+impl HandlerConnection {
+    /// Closes the connection, giving the child process a chance to exit before it is killed, if
+    /// `graceful` is true.
+    fn close(self, graceful: bool) {
+        let HandlerConnection {
+            reader,
+            writer,
+            child,
+            ..
+        } = self;
+        drop(writer);
+        drop(reader);
+
+        if let Some(child) = child {
+            let mut child = match child.lock() {
+                Ok(child) => child,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if graceful {
+                let deadline = Instant::now() + EXIT_TIMEOUT;
+                while Instant::now() < deadline {
+                    match child.try_wait() {
+                        Ok(None) => thread::sleep(Duration::from_millis(10)),
+                        _ => return,
+                    }
+                }
+            }
+            if let Err(err) = child.kill() {
+                debug!("Unable to kill handler process: {}", err);
+            }
+            if let Err(err) = child.wait() {
+                warn!("Unable to wait for handler process: {}", err);
+            }
+        }
+    }
+}
+
+/// Owns the handler process, and applies transactions with it.
+struct HandlerWorker {
+    launcher: Launcher,
+    context_manager: ContextManager,
+    execution_registry: Box<dyn ExecutionRegistry>,
+    families: HashSet<TransactionFamily>,
+    connection: Option<HandlerConnection>,
+    timeout: Duration,
+    /// The number of launches in a row which have failed.
+    launch_failures: u32,
+}
+
+impl HandlerWorker {
+    fn run(&mut self, receiver: Receiver<SubprocessAdapterCommand>) {
+        while let Ok(SubprocessAdapterCommand::Execute(execute_cmd)) = receiver.recv() {
+            let (txn_pair, context_id, on_done) = *execute_cmd;
+            debug!("Executing {:?} in context {:?}", &txn_pair, &context_id);
+            self.execute_transaction(txn_pair, context_id, on_done);
+        }
+
+        if let Some(connection) = self.connection.take() {
+            connection.close(true);
+        }
+    }
+
+    fn execute_transaction(
+        &mut self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) {
+        if self.connection.is_none() {
+            if self.launch_failures > 0 {
+                thread::sleep(relaunch_backoff(self.launch_failures));
+            }
+            if let Err(err) = self.relaunch() {
+                self.launch_failures += 1;
+                if self.launch_failures >= MAX_LAUNCH_FAILURES {
+                    error!(
+                        "Unable to relaunch handler process after {} attempts: {}",
+                        self.launch_failures, err
+                    );
+                    return on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                        ApplyError::InternalError(format!(
+                            "Unable to launch handler process: {}",
+                            err
+                        )),
+                    ))));
+                }
+                warn!("Unable to relaunch handler process: {}", err);
+                return on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                    transaction_pair,
+                ))));
+            }
+            self.launch_failures = 0;
+        }
+        if !self
+            .families
+            .contains(&TransactionFamily::from_pair(&transaction_pair))
+        {
+            return on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                transaction_pair,
+            ))));
+        }
+
+        let savepoint = match self.context_manager.savepoint(&context_id) {
+            Ok(savepoint) => savepoint,
+            Err(err) => {
+                return on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                    ApplyError::InternalError(format!("Unable to create savepoint: {}", err)),
+                ))))
+            }
+        };
+        if let Err(err) = restrict_context(&self.context_manager, &context_id, &transaction_pair) {
+            return on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                err,
+            ))));
+        }
+
+        let result = {
+            let connection = self
+                .connection
+                .as_mut()
+                .expect("Handler connection was not launched");
+            let context = StaticContext::new(&self.context_manager, &context_id);
+            with_deadline(connection, self.timeout, |connection| {
+                process_transaction(connection, &transaction_pair, &context)
+            })
+        };
+
+        let transaction_id = transaction_pair.transaction().header_signature().to_owned();
+        match result {
+            Ok(response) => match response.get_status() {
+                ProcessResponse_Status::OK => on_done(Ok(
+                    ExecutionTaskCompletionNotification::Valid(context_id, transaction_id),
+                )),
+                ProcessResponse_Status::INVALID_TRANSACTION => {
+                    on_done(Ok(ExecutionTaskCompletionNotification::Invalid(
+                        context_id,
+                        InvalidTransactionResult {
+                            transaction_id,
+                            error_message: response.get_message().to_owned(),
+                            error_data: vec![],
+                        },
+                    )))
+                }
+                _ => on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                    ApplyError::InternalError(response.get_message().to_owned()),
+                )))),
+            },
+            Err(err) => {
+                warn!(
+                    "Handler process failed while applying {}: {}",
+                    transaction_id, err
+                );
+                if let Some(connection) = self.connection.take() {
+                    connection.close(false);
+                }
+                if let Err(err) = self.context_manager.rollback_to(&context_id, &savepoint) {
+                    warn!("Unable to roll back context {:?}: {}", context_id, err);
+                }
+                if err.kind() == io::ErrorKind::TimedOut {
+                    on_done(Err(ExecutionAdapterError::TimeoutError(Box::new(
+                        transaction_pair,
+                    ))))
+                } else {
+                    on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                        transaction_pair,
+                    ))))
+                }
+            }
+        }
+    }
+
+    /// Launches a new handler, updating the registered families to the ones it serves.
+    fn relaunch(&mut self) -> io::Result<()> {
+        let (connection, families) = launch_handler(&mut self.launcher, self.timeout)?;
+        for family in self.families.difference(&families) {
+            self.execution_registry
+                .unregister_transaction_family(family);
+        }
+        for family in families.difference(&self.families) {
+            self.execution_registry
+                .register_transaction_family(family.clone());
+        }
+        self.families = families;
+        self.connection = Some(connection);
+        Ok(())
+    }
+}
+
+/// Returns how long to wait before relaunching a handler, after the given number of failed
+/// launches in a row.
+fn relaunch_backoff(launch_failures: u32) -> Duration {
+    let mut backoff = RELAUNCH_BACKOFF;
+    for _ in 1..launch_failures {
+        backoff = std::cmp::min(backoff * 2, MAX_RELAUNCH_BACKOFF);
+    }
+    backoff
+}
+
+fn launch_process(program: &Path, args: &[String]) -> io::Result<HandlerConnection> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take().expect("Child stdin was not piped");
+    let stdout = child.stdout.take().expect("Child stdout was not piped");
+
+    let child = Arc::new(Mutex::new(child));
+    let abort_child = child.clone();
+    Ok(HandlerConnection {
+        reader: Box::new(BufReader::new(stdout)),
+        writer: Box::new(BufWriter::new(stdin)),
+        child: Some(child),
+        abort: Arc::new(move || {
+            if let Ok(mut child) = abort_child.lock() {
+                if let Err(err) = child.kill() {
+                    debug!("Unable to kill handler process: {}", err);
+                }
+            }
+        }),
+    })
+}
+
+/// Launches a handler, and reads the families it serves, waiting at most `timeout` for them.
+fn launch_handler(
+    launcher: &mut Launcher,
+    timeout: Duration,
+) -> io::Result<(HandlerConnection, HashSet<TransactionFamily>)> {
+    let mut connection = launcher()?;
+    let result = match with_deadline(&mut connection, timeout, |connection| {
+        read_message::<HandlerMessage>(&mut *connection.reader)
+    }) {
+        Ok(HandlerMessage {
+            message: Some(HandlerMessage_oneof_message::register_request(request)),
+            ..
+        }) => Ok(request
+            .get_families()
+            .iter()
+            .flat_map(|family| {
+                family.get_family_versions().iter().map(move |version| {
+                    TransactionFamily::new(family.get_family_name().into(), version.clone())
+                })
+            })
+            .collect()),
+        Ok(_) => Err(protocol_error("expected a register request")),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(families) => Ok((connection, families)),
+        Err(err) => {
+            connection.close(false);
+            Err(err)
+        }
+    }
+}
+
+/// Calls `f` with the connection, aborting the connection if `f` has not returned within
+/// `timeout`.
+///
+/// If the connection was aborted, an error of kind `TimedOut` is returned in place of the result
+/// of `f`.
+fn with_deadline<T, F>(connection: &mut HandlerConnection, timeout: Duration, f: F) -> io::Result<T>
+where
+    F: FnOnce(&mut HandlerConnection) -> io::Result<T>,
+{
+    let (done_sender, done_receiver) = channel::<()>();
+    let abort = connection.abort.clone();
+    let watchdog = thread::Builder::new()
+        .name("SubprocessExecutionAdapterWatchdog".into())
+        .spawn(move || match done_receiver.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                abort();
+                true
+            }
+            _ => false,
+        })?;
+
+    let result = f(connection);
+
+    drop(done_sender);
+    if watchdog.join().unwrap_or(true) {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("handler did not respond within {:?}", timeout),
+        ));
+    }
+    result
+}
+
+/// Sends the transaction to the handler, and answers its context requests until it returns the
+/// result of the transaction.
+fn process_transaction(
+    connection: &mut HandlerConnection,
+    transaction_pair: &TransactionPair,
+    context: &dyn TransactionContext,
+) -> io::Result<ProcessResponse> {
+    let transaction = transaction_pair.transaction();
+    let mut proto_transaction = crate::protos::transaction::Transaction::new();
+    proto_transaction.set_header(transaction.header().to_vec());
+    proto_transaction.set_header_signature(transaction.header_signature().to_owned());
+    proto_transaction.set_payload(transaction.payload().to_vec());
+
+    let mut request = ProcessRequest::new();
+    request.set_transaction(proto_transaction);
+    let mut message = AdapterMessage::new();
+    message.set_process_request(request);
+    write_message(&mut *connection.writer, &message)?;
+
+    loop {
+        match read_message::<HandlerMessage>(&mut *connection.reader)?.message {
+            Some(HandlerMessage_oneof_message::context_request(request)) => {
+                let mut message = AdapterMessage::new();
+                message.set_context_response(handle_context_request(context, request));
+                write_message(&mut *connection.writer, &message)?;
+            }
+            Some(HandlerMessage_oneof_message::process_response(response)) => return Ok(response),
+            _ => {
+                return Err(protocol_error(
+                    "expected a context request or a process response",
+                ))
+            }
+        }
+    }
+}
+
+fn handle_context_request(
+    context: &dyn TransactionContext,
+    request: ContextRequest,
+) -> ContextResponse {
+    let mut response = ContextResponse::new();
+
+    let result = match request.request {
+        Some(ContextRequest_oneof_request::get(mut get)) => context
+            .get_state_entries(&get.take_addresses().into_vec())
+            .map(|entries| response.set_entries(into_proto_entries(entries))),
+        Some(ContextRequest_oneof_request::list(list)) => context
+            .list_state_entries(list.get_prefix())
+            .map(|entries| response.set_entries(into_proto_entries(entries))),
+        Some(ContextRequest_oneof_request::set(mut set)) => context.set_state_entries(
+            set.take_entries()
+                .into_iter()
+                .map(|mut entry| (entry.take_address(), entry.take_data()))
+                .collect(),
+        ),
+        Some(ContextRequest_oneof_request::delete(mut delete)) => context
+            .delete_state_entries(&delete.take_addresses().into_vec())
+            .map(|addresses| response.set_addresses(RepeatedField::from_vec(addresses))),
+        Some(ContextRequest_oneof_request::add_event(mut add_event)) => {
+            Event::from_proto(add_event.take_event())
+                .map_err(|err| ContextError::SerializationError(Box::new(err)))
+                .and_then(|event| context.add_event(event.event_type, event.attributes, event.data))
+        }
+        Some(ContextRequest_oneof_request::add_receipt_data(mut add_receipt_data)) => {
+            context.add_receipt_data(add_receipt_data.take_data())
+        }
+        None => Err(ContextError::ResponseAttributeError(
+            "context request is empty".into(),
+        )),
+    };
+
+    match result {
+        Ok(()) => response.set_status(ContextResponse_Status::OK),
+        Err(ContextError::AuthorizationError(msg)) => {
+            response.set_status(ContextResponse_Status::AUTHORIZATION_ERROR);
+            response.set_message(msg);
+        }
+        Err(err) => {
+            response.set_status(ContextResponse_Status::ERROR);
+            response.set_message(err.to_string());
+        }
+    }
+
+    response
+}
+
+/// Serves `TransactionHandler` instances from within a handler process.
+///
+/// A binary run by a `SubprocessExecutionAdapter` passes its handlers to a runner, and calls
+/// `run`, which returns once the adapter closes the process's stdin.
+pub struct SubprocessHandlerRunner {
+    handlers: Vec<Box<dyn TransactionHandler>>,
+}
+
+impl SubprocessHandlerRunner {
+    pub fn new(handlers: Vec<Box<dyn TransactionHandler>>) -> Self {
+        SubprocessHandlerRunner { handlers }
+    }
+
+    /// Serves the handlers over stdin and stdout, until stdin is closed.
+    ///
+    /// Nothing else may be written to stdout while the handlers are being served.
+    ///
+    /// # Errors
+    ///
+    /// An `io::Error` is returned if stdin or stdout fail, or if a malformed message is read.
+    pub fn run(&self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        let mut reader = stdin.lock();
+        let mut writer = stdout.lock();
+        self.serve(&mut reader, &mut writer)
+    }
+
+    fn serve(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<()> {
+        let mut request = RegisterRequest::new();
+        request.set_families(
+            self.handlers
+                .iter()
+                .map(|handler| {
+                    let mut family = RegisterRequest_Family::new();
+                    family.set_family_name(handler.family_name().into());
+                    family.set_family_versions(RepeatedField::from_vec(
+                        handler.family_versions().to_vec(),
+                    ));
+                    family
+                })
+                .collect(),
+        );
+        let mut message = HandlerMessage::new();
+        message.set_register_request(request);
+        write_message(writer, &message)?;
+
+        loop {
+            let message = match read_message::<AdapterMessage>(reader) {
+                Ok(message) => message,
+                // The adapter closed the connection
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let mut request = match message.message {
+                Some(AdapterMessage_oneof_message::process_request(request)) => request,
+                _ => return Err(protocol_error("expected a process request")),
+            };
+
+            let result = Transaction::from(request.take_transaction())
+                .into_pair()
+                .map_err(|err| ApplyError::InvalidTransaction(err.to_string()))
+                .and_then(|transaction_pair| {
+                    self.apply(&transaction_pair, &mut ProxyContext::new(reader, writer))
+                });
+
+            let mut response = ProcessResponse::new();
+            match result {
+                Ok(()) => response.set_status(ProcessResponse_Status::OK),
+                Err(ApplyError::InvalidTransaction(msg)) => {
+                    response.set_status(ProcessResponse_Status::INVALID_TRANSACTION);
+                    response.set_message(msg);
+                }
+                Err(ApplyError::InternalError(msg)) => {
+                    response.set_status(ProcessResponse_Status::INTERNAL_ERROR);
+                    response.set_message(msg);
+                }
+            }
+            let mut message = HandlerMessage::new();
+            message.set_process_response(response);
+            write_message(writer, &message)?;
+        }
+    }
+
+    fn apply(
+        &self,
+        transaction_pair: &TransactionPair,
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let header = transaction_pair.header();
+        match self.handlers.iter().find(|handler| {
+            handler.family_name() == header.family_name()
+                && handler
+                    .family_versions()
+                    .iter()
+                    .any(|v| v == header.family_version())
+        }) {
+            Some(handler) => handler.apply(transaction_pair, context),
+            None => Err(ApplyError::InternalError(format!(
+                "No handler for {}/{}",
+                header.family_name(),
+                header.family_version()
+            ))),
+        }
+    }
+}
+
+/// A `TransactionContext` which sends each operation to the adapter.
+struct ProxyContext<'a> {
+    connection: std::cell::RefCell<(&'a mut dyn Read, &'a mut dyn Write)>,
+}
+
+impl<'a> ProxyContext<'a> {
+    fn new(reader: &'a mut dyn Read, writer: &'a mut dyn Write) -> Self {
+        ProxyContext {
+            connection: std::cell::RefCell::new((reader, writer)),
+        }
+    }
+
+    fn request(&self, request: ContextRequest) -> Result<ContextResponse, ContextError> {
+        let mut connection = self.connection.borrow_mut();
+        let (ref mut reader, ref mut writer) = *connection;
+
+        let mut message = HandlerMessage::new();
+        message.set_context_request(request);
+        write_message(*writer, &message).map_err(|err| ContextError::SendError(Box::new(err)))?;
+
+        let mut response = match read_message::<AdapterMessage>(*reader)
+            .map_err(|err| ContextError::ReceiveError(Box::new(err)))?
+            .message
+        {
+            Some(AdapterMessage_oneof_message::context_response(response)) => response,
+            _ => {
+                return Err(ContextError::ReceiveError(Box::new(protocol_error(
+                    "expected a context response",
+                ))))
+            }
+        };
+
+        match response.get_status() {
+            ContextResponse_Status::OK => Ok(response),
+            ContextResponse_Status::AUTHORIZATION_ERROR => {
+                Err(ContextError::AuthorizationError(response.take_message()))
+            }
+            _ => Err(ContextError::ReceiveError(response.take_message().into())),
+        }
+    }
+}
+
+impl<'a> TransactionContext for ProxyContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        let mut get = ContextRequest_Get::new();
+        get.set_addresses(RepeatedField::from_vec(addresses.to_vec()));
+        let mut request = ContextRequest::new();
+        request.set_get(get);
+        Ok(from_proto_entries(self.request(request)?.take_entries()))
+    }
+
+    fn list_state_entries(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        let mut list = ContextRequest_List::new();
+        list.set_prefix(prefix.into());
+        let mut request = ContextRequest::new();
+        request.set_list(list);
+        Ok(from_proto_entries(self.request(request)?.take_entries()))
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        let mut set = ContextRequest_Set::new();
+        set.set_entries(into_proto_entries(entries));
+        let mut request = ContextRequest::new();
+        request.set_set(set);
+        self.request(request).map(|_| ())
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        let mut delete = ContextRequest_Delete::new();
+        delete.set_addresses(RepeatedField::from_vec(addresses.to_vec()));
+        let mut request = ContextRequest::new();
+        request.set_delete(delete);
+        Ok(self.request(request)?.take_addresses().into_vec())
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        let mut add_receipt_data = ContextRequest_AddReceiptData::new();
+        add_receipt_data.set_data(data);
+        let mut request = ContextRequest::new();
+        request.set_add_receipt_data(add_receipt_data);
+        self.request(request).map(|_| ())
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        let event = crate::protos::events::Event::from_native(Event {
+            event_type,
+            attributes,
+            data,
+        })
+        .map_err(|err| ContextError::SerializationError(Box::new(err)))?;
+        let mut add_event = ContextRequest_AddEvent::new();
+        add_event.set_event(event);
+        let mut request = ContextRequest::new();
+        request.set_add_event(add_event);
+        self.request(request).map(|_| ())
+    }
+}
+
+fn into_proto_entries(entries: Vec<(String, Vec<u8>)>) -> RepeatedField<Entry> {
+    entries
+        .into_iter()
+        .map(|(address, data)| {
+            let mut entry = Entry::new();
+            entry.set_address(address);
+            entry.set_data(data);
+            entry
+        })
+        .collect()
+}
+
+fn from_proto_entries(entries: RepeatedField<Entry>) -> Vec<(String, Vec<u8>)> {
+    entries
+        .into_iter()
+        .map(|mut entry| (entry.take_address(), entry.take_data()))
+        .collect()
+}
+
+/// Writes a message, prefixed with its length.
+fn write_message<M: Message>(writer: &mut dyn Write, message: &M) -> io::Result<()> {
+    let bytes = message
+        .write_to_bytes()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+/// Reads a message, prefixed with its length.
+fn read_message<M: Message>(reader: &mut dyn Read) -> io::Result<M> {
+    let mut len_bytes = [0u8; 4];
+    reader.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(protocol_error(&format!(
+            "message of {} bytes exceeds the limit",
+            len
+        )));
+    }
+
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    protobuf::parse_from_bytes(&bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use crate::context::ContextLifecycle;
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::command::{make_command_transaction, Command, CommandTransactionHandler};
+
+    /// Apply transactions with a handler, checking that its families are registered and that
+    /// its results and state changes are returned.
+    #[test]
+    fn apply_subprocess_adapter() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter = SubprocessExecutionAdapter::with_launcher(
+            in_process_launcher(
+                || vec![Box::new(CommandTransactionHandler::new())],
+                Arc::new(AtomicUsize::new(0)),
+            ),
+            context_manager.clone(),
+        );
+
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+        assert_eq!(
+            vec![TransactionFamily::new("command".into(), "0.1".into())],
+            *registry.families.lock().unwrap()
+        );
+
+        let txn_pair = make_command_transaction(&[
+            Command::Set {
+                address: "abcd".into(),
+                value: b"abc".to_vec(),
+            },
+            Command::Get {
+                address: "abcd".into(),
+            },
+        ])
+        .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id),
+            execute(&adapter, txn_pair, &context_id).unwrap()
+        );
+        assert_eq!(
+            vec![("abcd".to_owned(), b"abc".to_vec())],
+            context_manager
+                .get(&context_id, &["abcd".to_owned()])
+                .unwrap()
+        );
+
+        let txn_pair = make_command_transaction(&[Command::Fail {
+            error_msg: "Test Fail Succeeded".into(),
+        }])
+        .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: txn_id,
+                    error_message: "Test Fail Succeeded".into(),
+                    error_data: vec![],
+                }
+            ),
+            execute(&adapter, txn_pair, &context_id).unwrap()
+        );
+
+        assert!(Box::new(adapter).stop().is_ok());
+    }
+
+    /// Apply a transaction with a handler which crashes after setting a value, checking that the
+    /// transaction is returned with a routing error and its changes are rolled back, and that the
+    /// handler is relaunched for the next transaction.
+    #[test]
+    fn subprocess_adapter_handler_crash() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let crash = Arc::new(AtomicBool::new(true));
+        let launches = Arc::new(AtomicUsize::new(0));
+        let handler_crash = crash.clone();
+        let mut adapter = SubprocessExecutionAdapter::with_launcher(
+            in_process_launcher(
+                move || {
+                    vec![Box::new(CrashingHandler {
+                        inner: CommandTransactionHandler::new(),
+                        crash: handler_crash.clone(),
+                    })]
+                },
+                launches.clone(),
+            ),
+            context_manager.clone(),
+        );
+
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let txn_pair = make_command_transaction(&[Command::Set {
+            address: "abcd".into(),
+            value: b"abc".to_vec(),
+        }])
+        .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let txn_pair = match execute(&adapter, txn_pair, &context_id) {
+            Err(ExecutionAdapterError::RoutingError(txn_pair)) => *txn_pair,
+            res => panic!("Expected a routing error, got {:?}", res),
+        };
+        assert!(!crash.load(Ordering::SeqCst));
+        assert!(context_manager
+            .get(&context_id, &["abcd".to_owned()])
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id),
+            execute(&adapter, txn_pair, &context_id).unwrap()
+        );
+        assert_eq!(
+            vec![("abcd".to_owned(), b"abc".to_vec())],
+            context_manager
+                .get(&context_id, &["abcd".to_owned()])
+                .unwrap()
+        );
+        assert_eq!(2, launches.load(Ordering::SeqCst));
+
+        assert!(Box::new(adapter).stop().is_ok());
+    }
+
+    /// Apply a transaction with a handler which stalls after setting a value, checking that the
+    /// transaction is returned with a timeout error and its changes are rolled back, and that the
+    /// handler is relaunched for the next transaction.
+    #[test]
+    fn subprocess_adapter_handler_timeout() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let stall = Arc::new(AtomicBool::new(true));
+        let launches = Arc::new(AtomicUsize::new(0));
+        let handler_stall = stall.clone();
+        let mut adapter = SubprocessExecutionAdapter::with_launcher(
+            in_process_launcher(
+                move || {
+                    vec![Box::new(StallingHandler {
+                        inner: CommandTransactionHandler::new(),
+                        stall: handler_stall.clone(),
+                    })]
+                },
+                launches.clone(),
+            ),
+            context_manager.clone(),
+        )
+        .with_timeout(Duration::from_millis(200));
+
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let txn_pair = make_command_transaction(&[Command::Set {
+            address: "abcd".into(),
+            value: b"abc".to_vec(),
+        }])
+        .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let txn_pair = match execute(&adapter, txn_pair, &context_id) {
+            Err(ExecutionAdapterError::TimeoutError(txn_pair)) => *txn_pair,
+            res => panic!("Expected a timeout error, got {:?}", res),
+        };
+        assert!(context_manager
+            .get(&context_id, &["abcd".to_owned()])
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id),
+            execute(&adapter, txn_pair, &context_id).unwrap()
+        );
+        assert_eq!(
+            vec![("abcd".to_owned(), b"abc".to_vec())],
+            context_manager
+                .get(&context_id, &["abcd".to_owned()])
+                .unwrap()
+        );
+        assert_eq!(2, launches.load(Ordering::SeqCst));
+
+        assert!(Box::new(adapter).stop().is_ok());
+    }
+
+    /// Start the adapter with a program which never registers, checking that it is killed once
+    /// the timeout has passed.
+    #[test]
+    fn subprocess_adapter_program_hangs() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter = SubprocessExecutionAdapter::new_adapter(
+            "sh",
+            &["-c".to_owned(), "exec sleep 30".to_owned()],
+            context_manager,
+        )
+        .with_timeout(Duration::from_millis(200));
+
+        let start = Instant::now();
+        match adapter.start(Box::new(registry.clone())) {
+            Err(ExecutionOperationError::StartError(_)) => (),
+            res => panic!("Expected a start error, got {:?}", res),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(registry.families.lock().unwrap().is_empty());
+    }
+
+    /// Apply transactions after the handler crashes and its program can no longer be launched,
+    /// checking that the launches are backed off, and that the transaction is returned with a
+    /// general execution error once `MAX_LAUNCH_FAILURES` launches have failed.
+    #[test]
+    fn subprocess_adapter_relaunch_fails() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut first_launcher = Some(in_process_launcher(
+            || {
+                vec![Box::new(CrashingHandler {
+                    inner: CommandTransactionHandler::new(),
+                    crash: Arc::new(AtomicBool::new(true)),
+                })]
+            },
+            Arc::new(AtomicUsize::new(0)),
+        ));
+        let mut adapter = SubprocessExecutionAdapter::with_launcher(
+            Box::new(move || match first_launcher.take() {
+                Some(mut launcher) => launcher(),
+                None => launch_process(Path::new("/nonexistent/transact-handler"), &[]),
+            }),
+            context_manager.clone(),
+        );
+
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let mut txn_pair = make_command_transaction(&[Command::Set {
+            address: "abcd".into(),
+            value: b"abc".to_vec(),
+        }])
+        .expect("Unable to build transaction");
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        // The handler crashes, and then each relaunch fails
+        let start = Instant::now();
+        for _ in 0..MAX_LAUNCH_FAILURES {
+            txn_pair = match execute(&adapter, txn_pair, &context_id) {
+                Err(ExecutionAdapterError::RoutingError(txn_pair)) => *txn_pair,
+                res => panic!("Expected a routing error, got {:?}", res),
+            };
+        }
+        match execute(&adapter, txn_pair, &context_id) {
+            Err(ExecutionAdapterError::GeneralExecutionError(_)) => (),
+            res => panic!("Expected a general execution error, got {:?}", res),
+        }
+        let backoff: Duration = (1..MAX_LAUNCH_FAILURES).map(relaunch_backoff).sum();
+        assert!(start.elapsed() >= backoff);
+
+        assert!(Box::new(adapter).stop().is_ok());
+    }
+
+    /// Start the adapter with a program which does not exist.
+    #[test]
+    fn subprocess_adapter_program_missing() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter = SubprocessExecutionAdapter::new_adapter(
+            "/nonexistent/transact-handler",
+            &[],
+            context_manager,
+        );
+
+        match adapter.start(Box::new(registry.clone())) {
+            Err(ExecutionOperationError::StartError(_)) => (),
+            res => panic!("Expected a start error, got {:?}", res),
+        }
+        assert!(registry.families.lock().unwrap().is_empty());
+    }
+
+    /// Start the adapter with a program which exits without registering any families.
+    #[test]
+    fn subprocess_adapter_program_exits() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter = SubprocessExecutionAdapter::new_adapter(
+            "sh",
+            &["-c".to_owned(), "exit 0".to_owned()],
+            context_manager,
+        );
+
+        match adapter.start(Box::new(registry.clone())) {
+            Err(ExecutionOperationError::StartError(_)) => (),
+            res => panic!("Expected a start error, got {:?}", res),
+        }
+        assert!(registry.families.lock().unwrap().is_empty());
+    }
+
+    fn execute(
+        adapter: &SubprocessExecutionAdapter,
+        txn_pair: TransactionPair,
+        context_id: &ContextId,
+    ) -> Result<ExecutionTaskCompletionNotification, ExecutionAdapterError> {
+        let (send, recv) = std::sync::mpsc::channel();
+        assert!(adapter
+            .execute(
+                txn_pair,
+                *context_id,
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        recv.recv().unwrap()
+    }
+
+    /// Returns a launcher which serves the handlers returned by `handlers` from a thread, over a
+    /// socket, counting each launch.
+    fn in_process_launcher<F>(handlers: F, launches: Arc<AtomicUsize>) -> Launcher
+    where
+        F: Fn() -> Vec<Box<dyn TransactionHandler>> + Send + Sync + 'static,
+    {
+        let handlers = Arc::new(handlers);
+        Box::new(move || {
+            launches.fetch_add(1, Ordering::SeqCst);
+            let (adapter_end, handler_end) = UnixStream::pair()?;
+            let handlers = handlers.clone();
+            thread::spawn(move || {
+                let mut reader = handler_end.try_clone().unwrap();
+                let mut writer = handler_end;
+                SubprocessHandlerRunner::new(handlers())
+                    .serve(&mut reader, &mut writer)
+                    .unwrap();
+            });
+
+            let abort_end = adapter_end.try_clone()?;
+            Ok(HandlerConnection {
+                reader: Box::new(adapter_end.try_clone()?),
+                writer: Box::new(adapter_end),
+                child: None,
+                abort: Arc::new(move || {
+                    let _ = abort_end.shutdown(Shutdown::Both);
+                }),
+            })
+        })
+    }
+
+    /// A handler which crashes after applying a transaction, if its crash flag is set.
+    struct CrashingHandler {
+        inner: CommandTransactionHandler,
+        crash: Arc<AtomicBool>,
+    }
+
+    impl TransactionHandler for CrashingHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            transaction: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            self.inner.apply(transaction, context)?;
+            if self.crash.swap(false, Ordering::SeqCst) {
+                panic!("Handler crashed");
+            }
+            Ok(())
+        }
+    }
+
+    /// A handler which stalls after applying a transaction, if its stall flag is set.
+    struct StallingHandler {
+        inner: CommandTransactionHandler,
+        stall: Arc<AtomicBool>,
+    }
+
+    impl TransactionHandler for StallingHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            transaction: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            self.inner.apply(transaction, context)?;
+            if self.stall.swap(false, Ordering::SeqCst) {
+                thread::sleep(Duration::from_secs(1));
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        families: Arc<Mutex<Vec<TransactionFamily>>>,
+    }
+
+    impl ExecutionRegistry for MockRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.families.lock().unwrap().push(family);
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.families.lock().unwrap().retain(|f| f != family);
+        }
+    }
+}
@@ -0,0 +1,956 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! The WebAssembly execution adapter runs transaction families as smart contracts kept in state.
+//!
+//! This module provides the `WasmExecutionAdapter`, an implementation of `ExecutionAdapter` which
+//! applies transactions by running a contract compiled to WebAssembly in a sandboxed interpreter.
+//! The contract for a transaction family version is stored, as a WebAssembly binary, at the
+//! address returned by `compute_contract_address`.  The contract is read from the transaction's
+//! context, so a new contract may be deployed by writing it to state, without restarting the
+//! node; the contract's address must therefore be one of the transaction's inputs, so that the
+//! scheduler orders the transaction after any transaction which deploys its contract.
+//!
+//! A contract exports its linear memory as `memory`, and an `apply` function which takes no
+//! arguments and returns nothing; the transaction is valid if `apply` returns.  The contract can
+//! only reach the transaction and its context through the following functions, which it imports
+//! from the `env` module:
+//!
+//! * `get_payload() -> i32` loads the transaction's payload into the host buffer, and returns its
+//!   length.
+//! * `get_signer() -> i32` loads the public key of the transaction's signer into the host buffer,
+//!   and returns its length.
+//! * `get_state(address_ptr: i32, address_len: i32) -> i32` loads the value at the given address
+//!   into the host buffer, and returns its length, or -1 if the address is not set.
+//! * `read_buffer(ptr: i32)` copies the host buffer into memory, starting at the given offset.
+//! * `set_state(address_ptr: i32, address_len: i32, data_ptr: i32, data_len: i32)` sets the
+//!   value at the given address.
+//! * `delete_state(address_ptr: i32, address_len: i32) -> i32` deletes the value at the given
+//!   address, and returns 1 if it was set, or 0 if it was not.
+//! * `add_event_attribute(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32)` adds an
+//!   attribute to the next event.
+//! * `add_event(type_ptr: i32, type_len: i32, data_ptr: i32, data_len: i32)` adds an event, with
+//!   the attributes added since the last event, to the transaction's receipt.
+//! * `add_receipt_data(data_ptr: i32, data_len: i32)` adds opaque data to the transaction's
+//!   receipt.
+//! * `invalid_transaction(message_ptr: i32, message_len: i32)` stops the contract, and marks the
+//!   transaction as invalid with the given message.
+//!
+//! Addresses, event types and attributes are UTF-8 strings.  The transaction is invalid if the
+//! contract traps, reads or writes an address outside of the transaction's inputs or outputs,
+//! uses floating point instructions, or executes more instructions than the adapter's limit.
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use parity_wasm::elements;
+use pwasm_utils::rules;
+use sha2::{Digest, Sha512};
+use wasmi::{
+    Externals, FuncInstance, FuncRef, HostError, ImportsBuilder, MemoryRef, ModuleImportResolver,
+    ModuleInstance, RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind, ValueType,
+};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::static_adapter::{restrict_context, StaticContext};
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::{ApplyError, ContextError, TransactionContext};
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+
+/// The prefix of the addresses at which contracts are stored.
+pub const CONTRACT_ADDRESS_PREFIX: &str = "00ec02";
+
+/// The number of instructions a contract may execute while applying a single transaction, unless
+/// the adapter is given another limit.
+pub const DEFAULT_INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+/// The most memory a contract may use, in 64KiB pages.
+const MAX_MEMORY_PAGES: u32 = 256;
+
+// The indices of the host functions
+const GAS: usize = 0;
+const GET_PAYLOAD: usize = 1;
+const GET_SIGNER: usize = 2;
+const GET_STATE: usize = 3;
+const READ_BUFFER: usize = 4;
+const SET_STATE: usize = 5;
+const DELETE_STATE: usize = 6;
+const ADD_EVENT_ATTRIBUTE: usize = 7;
+const ADD_EVENT: usize = 8;
+const ADD_RECEIPT_DATA: usize = 9;
+const INVALID_TRANSACTION: usize = 10;
+
+// A type declaration to make the use of this complicated type-bounded box easier to work with
+type OnDoneCallback =
+    Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
+
+/// Returns the address at which the contract for the given transaction family version is stored.
+pub fn compute_contract_address(family_name: &str, family_version: &str) -> String {
+    let mut sha = Sha512::default();
+    sha.input(format!("{},{}", family_name, family_version));
+    CONTRACT_ADDRESS_PREFIX.to_owned() + &hex::encode(&sha.result())[..64]
+}
+
+/// The WasmExecutionAdapter runs transaction families compiled to WebAssembly.
+///
+/// Transactions are executed one at a time, on a single background thread.
+pub struct WasmExecutionAdapter {
+    join_handle: thread::JoinHandle<bool>,
+    sender: Sender<WasmAdapterCommand>,
+}
+
+impl WasmExecutionAdapter {
+    /// Creates a new adapter, if possible.
+    ///
+    /// Creates a `WasmExecutionAdapter` which serves the given transaction families, using the
+    /// contracts stored in the state managed by the given `ContextManager`.  Each contract may
+    /// execute up to `DEFAULT_INSTRUCTION_LIMIT` instructions per transaction.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the background thread cannot be created.
+    pub fn new_adapter(
+        families: Vec<TransactionFamily>,
+        context_manager: ContextManager,
+    ) -> Result<Self, ExecutionAdapterError> {
+        Self::new_adapter_with_limit(families, context_manager, DEFAULT_INSTRUCTION_LIMIT)
+    }
+
+    /// Creates a new adapter whose contracts may execute up to `instruction_limit` instructions
+    /// per transaction.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the background thread cannot be created.
+    pub fn new_adapter_with_limit(
+        families: Vec<TransactionFamily>,
+        context_manager: ContextManager,
+        instruction_limit: u64,
+    ) -> Result<Self, ExecutionAdapterError> {
+        let (sender, receiver) = channel();
+        let join_handle = thread::Builder::new()
+            .name("WasmExecutionAdapter".into())
+            .spawn(move || {
+                run_worker(families, &context_manager, instruction_limit, receiver);
+                true
+            })
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+
+        Ok(WasmExecutionAdapter {
+            join_handle,
+            sender,
+        })
+    }
+
+    /// Adds a transaction family to those served by the adapter.
+    ///
+    /// The family is registered with the `ExecutionRegistry` once the adapter has started.  Its
+    /// contract is read from state when a transaction is executed, so it may be deployed before
+    /// or after the family is added.
+    pub fn add_family(&self, family: TransactionFamily) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(WasmAdapterCommand::AddFamily(family))
+            .map_err(|err| {
+                ExecutionOperationError::ExecuteError(format!(
+                    "Unable to add transaction family to wasm execution adapter: {}",
+                    err
+                ))
+            })
+    }
+}
+
+fn run_worker(
+    mut families: Vec<TransactionFamily>,
+    context_manager: &ContextManager,
+    instruction_limit: u64,
+    receiver: Receiver<WasmAdapterCommand>,
+) {
+    let mut execution_registry: Option<Box<dyn ExecutionRegistry>> = None;
+    while let Ok(cmd) = receiver.recv() {
+        match cmd {
+            WasmAdapterCommand::Execute(execute_cmd) => {
+                let (txn_pair, context_id, on_done) = *execute_cmd;
+                debug!("Executing {:?} in context {:?}", &txn_pair, &context_id);
+                execute_transaction(
+                    &families,
+                    txn_pair,
+                    context_manager,
+                    context_id,
+                    instruction_limit,
+                    on_done,
+                );
+            }
+            WasmAdapterCommand::AddFamily(family) => {
+                if !families.contains(&family) {
+                    if let Some(execution_registry) = execution_registry.as_mut() {
+                        execution_registry.register_transaction_family(family.clone());
+                    }
+                    families.push(family);
+                }
+            }
+            WasmAdapterCommand::Start(mut registry) => {
+                for family in &families {
+                    registry.register_transaction_family(family.clone());
+                }
+                execution_registry = Some(registry);
+            }
+            WasmAdapterCommand::Stop => break,
+        }
+    }
+}
+
+fn execute_transaction(
+    families: &[TransactionFamily],
+    transaction_pair: TransactionPair,
+    context_manager: &ContextManager,
+    context_id: ContextId,
+    instruction_limit: u64,
+    on_done: OnDoneCallback,
+) {
+    let family = TransactionFamily::from_pair(&transaction_pair);
+    if !families.contains(&family) {
+        on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+            transaction_pair,
+        ))));
+        return;
+    }
+
+    let context = StaticContext::new(context_manager, &context_id);
+    let result = restrict_context(context_manager, &context_id, &transaction_pair)
+        .and_then(|_| load_contract(&context, &family))
+        .and_then(|contract| {
+            apply_contract(&contract, &transaction_pair, &context, instruction_limit)
+        });
+
+    match result {
+        Ok(()) => on_done(Ok(ExecutionTaskCompletionNotification::Valid(
+            context_id,
+            transaction_pair.transaction().header_signature().to_owned(),
+        ))),
+        Err(ApplyError::InvalidTransaction(error_message)) => {
+            on_done(Ok(ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: transaction_pair.transaction().header_signature().to_owned(),
+                    error_message,
+                    error_data: vec![],
+                },
+            )))
+        }
+        Err(err) => on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+            err,
+        )))),
+    }
+}
+
+/// Reads the contract for the given family from the transaction's context, which only permits it
+/// if the contract's address is one of the transaction's inputs.
+fn load_contract(
+    context: &dyn TransactionContext,
+    family: &TransactionFamily,
+) -> Result<Vec<u8>, ApplyError> {
+    let address = compute_contract_address(family.family_name(), family.family_version());
+    context
+        .get_state_entries(std::slice::from_ref(&address))
+        .map_err(|err| match err {
+            ContextError::AuthorizationError(_) => ApplyError::InvalidTransaction(format!(
+                "The contract address {} is not in the transaction's inputs",
+                address
+            )),
+            err => ApplyError::InternalError(format!("Unable to read contract: {}", err)),
+        })?
+        .into_iter()
+        .map(|(_, contract)| contract)
+        .next()
+        .ok_or_else(|| {
+            ApplyError::InvalidTransaction(format!(
+                "No contract is deployed for {} {}",
+                family.family_name(),
+                family.family_version()
+            ))
+        })
+}
+
+fn apply_contract(
+    contract: &[u8],
+    transaction_pair: &TransactionPair,
+    context: &dyn TransactionContext,
+    instruction_limit: u64,
+) -> Result<(), ApplyError> {
+    let module = prepare_module(contract)?;
+    let instance = ModuleInstance::new(
+        &module,
+        &ImportsBuilder::new().with_resolver("env", &HostFunctions),
+    )
+    .map_err(contract_error)?;
+    let memory = instance
+        .not_started_instance()
+        .export_by_name("memory")
+        .and_then(|export| export.as_memory().cloned());
+
+    let mut runtime = Runtime {
+        transaction_pair,
+        context,
+        memory,
+        buffer: vec![],
+        event_attributes: vec![],
+        instruction_limit,
+        instructions_remaining: instruction_limit,
+    };
+    let instance = instance
+        .run_start(&mut runtime)
+        .map_err(|trap| contract_error(wasmi::Error::Trap(trap)))?;
+    instance
+        .invoke_export("apply", &[], &mut runtime)
+        .map_err(contract_error)?;
+
+    Ok(())
+}
+
+/// Parses and validates a contract, limiting its memory and metering the instructions it
+/// executes.
+fn prepare_module(contract: &[u8]) -> Result<wasmi::Module, ApplyError> {
+    let mut module: elements::Module = elements::deserialize_buffer(contract).map_err(|err| {
+        ApplyError::InvalidTransaction(format!("Contract is not a WebAssembly module: {}", err))
+    })?;
+
+    if let Some(memory_section) = module.memory_section_mut() {
+        for memory in memory_section.entries_mut() {
+            let initial = memory.limits().initial();
+            if initial > MAX_MEMORY_PAGES {
+                return Err(ApplyError::InvalidTransaction(format!(
+                    "Contract requires {} pages of memory, more than the limit of {}",
+                    initial, MAX_MEMORY_PAGES
+                )));
+            }
+            let maximum = memory
+                .limits()
+                .maximum()
+                .map_or(MAX_MEMORY_PAGES, |maximum| maximum.min(MAX_MEMORY_PAGES));
+            *memory = elements::MemoryType::new(initial, Some(maximum));
+        }
+    }
+
+    // Floating point instructions are forbidden, as their results are not deterministic across
+    // platforms
+    let module =
+        pwasm_utils::inject_gas_counter(module, &rules::Set::default().with_forbidden_floats())
+            .map_err(|_| {
+                ApplyError::InvalidTransaction(
+                    "Contract uses floating point instructions".to_string(),
+                )
+            })?;
+
+    wasmi::Module::from_parity_wasm_module(module).map_err(contract_error)
+}
+
+/// Converts an error raised while instantiating or running a contract.
+fn contract_error(err: wasmi::Error) -> ApplyError {
+    match err
+        .as_host_error()
+        .and_then(|host_err| host_err.downcast_ref::<ApplyError>())
+    {
+        Some(ApplyError::InvalidTransaction(msg)) => ApplyError::InvalidTransaction(msg.clone()),
+        Some(ApplyError::InternalError(msg)) => ApplyError::InternalError(msg.clone()),
+        None => match err {
+            wasmi::Error::Trap(trap) => {
+                ApplyError::InvalidTransaction(format!("Contract trapped: {:?}", trap.kind()))
+            }
+            err => ApplyError::InvalidTransaction(format!("Unable to run contract: {}", err)),
+        },
+    }
+}
+
+impl HostError for ApplyError {}
+
+fn host_trap(err: ApplyError) -> Trap {
+    Trap::new(TrapKind::Host(Box::new(err)))
+}
+
+fn context_trap(err: ContextError) -> Trap {
+    host_trap(ApplyError::from(err))
+}
+
+fn out_of_bounds<E>(_: E) -> Trap {
+    Trap::new(TrapKind::MemoryAccessOutOfBounds)
+}
+
+/// Resolves the functions a contract imports from the `env` module.
+struct HostFunctions;
+
+impl ModuleImportResolver for HostFunctions {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, wasmi::Error> {
+        use wasmi::ValueType::I32;
+
+        let (index, params, return_type): (usize, &'static [ValueType], Option<ValueType>) =
+            match field_name {
+                "gas" => (GAS, &[I32], None),
+                "get_payload" => (GET_PAYLOAD, &[], Some(I32)),
+                "get_signer" => (GET_SIGNER, &[], Some(I32)),
+                "get_state" => (GET_STATE, &[I32, I32], Some(I32)),
+                "read_buffer" => (READ_BUFFER, &[I32], None),
+                "set_state" => (SET_STATE, &[I32, I32, I32, I32], None),
+                "delete_state" => (DELETE_STATE, &[I32, I32], Some(I32)),
+                "add_event_attribute" => (ADD_EVENT_ATTRIBUTE, &[I32, I32, I32, I32], None),
+                "add_event" => (ADD_EVENT, &[I32, I32, I32, I32], None),
+                "add_receipt_data" => (ADD_RECEIPT_DATA, &[I32, I32], None),
+                "invalid_transaction" => (INVALID_TRANSACTION, &[I32, I32], None),
+                _ => {
+                    return Err(wasmi::Error::Instantiation(format!(
+                        "Host function {} does not exist",
+                        field_name
+                    )))
+                }
+            };
+
+        let expected = Signature::new(params, return_type);
+        if signature != &expected {
+            return Err(wasmi::Error::Instantiation(format!(
+                "Host function {} has the signature {:?}",
+                field_name, expected
+            )));
+        }
+        Ok(FuncInstance::alloc_host(expected, index))
+    }
+}
+
+/// The host side of a running contract.
+struct Runtime<'a> {
+    transaction_pair: &'a TransactionPair,
+    context: &'a dyn TransactionContext,
+    memory: Option<MemoryRef>,
+    /// The data returned by the last host function which loads data
+    buffer: Vec<u8>,
+    /// The attributes of the next event
+    event_attributes: Vec<(String, String)>,
+    instruction_limit: u64,
+    instructions_remaining: u64,
+}
+
+impl<'a> Runtime<'a> {
+    fn memory(&self) -> Result<&MemoryRef, Trap> {
+        self.memory.as_ref().ok_or_else(|| {
+            host_trap(ApplyError::InvalidTransaction(
+                "Contract does not export its memory".to_string(),
+            ))
+        })
+    }
+
+    fn read_bytes(&self, args: &RuntimeArgs, idx: usize) -> Result<Vec<u8>, Trap> {
+        let ptr: u32 = args.nth_checked(idx)?;
+        let len: u32 = args.nth_checked(idx + 1)?;
+        self.memory()?.get(ptr, len as usize).map_err(out_of_bounds)
+    }
+
+    fn read_string(&self, args: &RuntimeArgs, idx: usize) -> Result<String, Trap> {
+        String::from_utf8(self.read_bytes(args, idx)?).map_err(|_| {
+            host_trap(ApplyError::InvalidTransaction(
+                "Contract passed a string which is not valid UTF-8".to_string(),
+            ))
+        })
+    }
+
+    /// Replaces the host buffer, returning the length of its new contents.
+    fn load_buffer(&mut self, data: Vec<u8>) -> Option<RuntimeValue> {
+        self.buffer = data;
+        Some(RuntimeValue::I32(self.buffer.len() as i32))
+    }
+}
+
+impl<'a> Externals for Runtime<'a> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            GAS => {
+                let cost = u64::from(args.nth_checked::<u32>(0)?);
+                if cost > self.instructions_remaining {
+                    self.instructions_remaining = 0;
+                    return Err(host_trap(ApplyError::InvalidTransaction(format!(
+                        "Contract exceeded its limit of {} instructions",
+                        self.instruction_limit
+                    ))));
+                }
+                self.instructions_remaining -= cost;
+                Ok(None)
+            }
+            GET_PAYLOAD => {
+                let payload = self.transaction_pair.transaction().payload().to_vec();
+                Ok(self.load_buffer(payload))
+            }
+            GET_SIGNER => {
+                let signer = self.transaction_pair.header().signer_public_key().to_vec();
+                Ok(self.load_buffer(signer))
+            }
+            GET_STATE => {
+                let address = self.read_string(&args, 0)?;
+                match self
+                    .context
+                    .get_state_entry(&address)
+                    .map_err(context_trap)?
+                {
+                    Some(data) => Ok(self.load_buffer(data)),
+                    None => {
+                        self.buffer.clear();
+                        Ok(Some(RuntimeValue::I32(-1)))
+                    }
+                }
+            }
+            READ_BUFFER => {
+                let ptr: u32 = args.nth_checked(0)?;
+                self.memory()?
+                    .set(ptr, &self.buffer)
+                    .map_err(out_of_bounds)?;
+                Ok(None)
+            }
+            SET_STATE => {
+                let address = self.read_string(&args, 0)?;
+                let data = self.read_bytes(&args, 2)?;
+                self.context
+                    .set_state_entry(address, data)
+                    .map_err(context_trap)?;
+                Ok(None)
+            }
+            DELETE_STATE => {
+                let address = self.read_string(&args, 0)?;
+                let deleted = self
+                    .context
+                    .delete_state_entry(&address)
+                    .map_err(context_trap)?;
+                Ok(Some(RuntimeValue::I32(i32::from(deleted.is_some()))))
+            }
+            ADD_EVENT_ATTRIBUTE => {
+                let key = self.read_string(&args, 0)?;
+                let value = self.read_string(&args, 2)?;
+                self.event_attributes.push((key, value));
+                Ok(None)
+            }
+            ADD_EVENT => {
+                let event_type = self.read_string(&args, 0)?;
+                let data = self.read_bytes(&args, 2)?;
+                let attributes = std::mem::replace(&mut self.event_attributes, vec![]);
+                self.context
+                    .add_event(event_type, attributes, data)
+                    .map_err(context_trap)?;
+                Ok(None)
+            }
+            ADD_RECEIPT_DATA => {
+                let data = self.read_bytes(&args, 0)?;
+                self.context.add_receipt_data(data).map_err(context_trap)?;
+                Ok(None)
+            }
+            INVALID_TRANSACTION => {
+                let message = self.read_string(&args, 0)?;
+                Err(host_trap(ApplyError::InvalidTransaction(message)))
+            }
+            _ => Err(host_trap(ApplyError::InternalError(format!(
+                "Unknown host function {}",
+                index
+            )))),
+        }
+    }
+}
+
+impl ExecutionAdapter for WasmExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(WasmAdapterCommand::Start(execution_registry))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start wasm execution adapter: {}",
+                    err
+                ))
+            })
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(WasmAdapterCommand::Execute(Box::new((
+                transaction_pair,
+                context_id,
+                on_done,
+            ))))
+            .map_err(|err| {
+                ExecutionOperationError::ExecuteError(format!(
+                    "Unable to send transaction for wasm execution: {}",
+                    err
+                ))
+            })
+    }
+
+    fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        self.sender.send(WasmAdapterCommand::Stop).map_err(|err| {
+            ExecutionOperationError::StopError(format!("Unable to send stop command: {}", err))
+        })?;
+
+        self.join_handle.join().map_err(|_| {
+            ExecutionOperationError::StopError("Unable to join internal thread.".into())
+        })?;
+
+        Ok(())
+    }
+}
+
+enum WasmAdapterCommand {
+    Start(Box<dyn ExecutionRegistry>),
+    AddFamily(TransactionFamily),
+    Stop,
+    Execute(Box<(TransactionPair, ContextId, OnDoneCallback)>),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    use crate::context::ContextLifecycle;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+
+    static FAMILY_NAME: &str = "wasm_test";
+
+    /// A contract which sets the payload at address abcd, reads it back and adds it to the
+    /// receipt, and adds an event; the transaction is invalid if the payload is empty.
+    static SET_CONTRACT: &str = r#"
+        (module
+          (import "env" "get_payload" (func $get_payload (result i32)))
+          (import "env" "get_state" (func $get_state (param i32 i32) (result i32)))
+          (import "env" "read_buffer" (func $read_buffer (param i32)))
+          (import "env" "set_state" (func $set_state (param i32 i32 i32 i32)))
+          (import "env" "add_event_attribute"
+            (func $add_event_attribute (param i32 i32 i32 i32)))
+          (import "env" "add_event" (func $add_event (param i32 i32 i32 i32)))
+          (import "env" "add_receipt_data" (func $add_receipt_data (param i32 i32)))
+          (import "env" "invalid_transaction" (func $invalid_transaction (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "abcd")
+          (data (i32.const 16) "empty payload")
+          (data (i32.const 32) "wasm_test/set")
+          (data (i32.const 48) "address")
+          (func (export "apply")
+            (local $len i32)
+            (local.set $len (call $get_payload))
+            (if (i32.eqz (local.get $len))
+              (then (call $invalid_transaction (i32.const 16) (i32.const 13))))
+            (call $read_buffer (i32.const 1024))
+            (call $set_state (i32.const 0) (i32.const 4) (i32.const 1024) (local.get $len))
+            (local.set $len (call $get_state (i32.const 0) (i32.const 4)))
+            (call $read_buffer (i32.const 2048))
+            (call $add_receipt_data (i32.const 2048) (local.get $len))
+            (call $add_event_attribute (i32.const 48) (i32.const 7) (i32.const 0) (i32.const 4))
+            (call $add_event (i32.const 32) (i32.const 13) (i32.const 2048) (local.get $len))))
+    "#;
+
+    /// Apply transactions with a contract which sets state and adds receipt data and events;
+    /// transactions which the contract rejects, which write outside of their outputs, or which
+    /// cannot read their contract, are invalid.
+    #[test]
+    fn apply_wasm_adapter() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter = WasmExecutionAdapter::new_adapter(
+            vec![TransactionFamily::new(FAMILY_NAME.into(), "1.0".into())],
+            context_manager.clone(),
+        )
+        .expect("Could not create adapter");
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        // A valid transaction
+        let txn_pair = make_transaction("1.0", &["abcd"], b"hello");
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+        deploy(&context_manager, &context_id, "1.0", SET_CONTRACT);
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id.clone()),
+            execute(&adapter, txn_pair, context_id).unwrap()
+        );
+        assert_eq!(
+            vec![("abcd".to_owned(), b"hello".to_vec())],
+            context_manager
+                .get(&context_id, &["abcd".to_owned()])
+                .unwrap()
+        );
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .unwrap();
+        assert_eq!(vec![b"hello".to_vec()], receipt.data);
+        assert_eq!(1, receipt.events.len());
+        assert_eq!("wasm_test/set", receipt.events[0].event_type);
+        assert_eq!(
+            vec![("address".to_owned(), "abcd".to_owned())],
+            receipt.events[0].attributes
+        );
+        assert_eq!(b"hello".to_vec(), receipt.events[0].data);
+
+        // A transaction which the contract rejects
+        let txn_pair = make_transaction("1.0", &["abcd"], b"");
+        let context_id = context_manager.create_context(&[], &state_id);
+        deploy(&context_manager, &context_id, "1.0", SET_CONTRACT);
+
+        assert_eq!(
+            Some("empty payload".to_owned()),
+            invalid_message(execute(&adapter, txn_pair, context_id))
+        );
+
+        // A transaction which does not have the address the contract sets in its outputs
+        let txn_pair = make_transaction("1.0", &["ef01"], b"hello");
+        let context_id = context_manager.create_context(&[], &state_id);
+        deploy(&context_manager, &context_id, "1.0", SET_CONTRACT);
+
+        let error_message = invalid_message(execute(&adapter, txn_pair, context_id))
+            .expect("Transaction should be invalid");
+        assert!(error_message.starts_with("AuthorizationError"));
+
+        // A transaction which does not have its contract's address in its inputs
+        let txn_pair = build_transaction("1.0", &["abcd"], &["abcd"], b"hello");
+        let context_id = context_manager.create_context(&[], &state_id);
+        deploy(&context_manager, &context_id, "1.0", SET_CONTRACT);
+
+        let error_message = invalid_message(execute(&adapter, txn_pair, context_id))
+            .expect("Transaction should be invalid");
+        assert!(error_message.starts_with("The contract address"));
+        assert!(context_manager
+            .get(&context_id, &["abcd".to_owned()])
+            .unwrap()
+            .is_empty());
+
+        assert!(Box::new(adapter).stop().is_ok());
+
+        assert_eq!(
+            vec![TransactionFamily::new(FAMILY_NAME.into(), "1.0".into())],
+            *registry.families.lock().unwrap()
+        );
+    }
+
+    /// Transactions whose contracts are missing, malformed, use floating point instructions,
+    /// trap or run for too long are invalid; transactions for families which have not been added
+    /// to the adapter are not routed.
+    #[test]
+    fn wasm_adapter_misbehaving_contracts() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut adapter =
+            WasmExecutionAdapter::new_adapter_with_limit(vec![], context_manager.clone(), 10_000)
+                .expect("Could not create adapter");
+        assert!(adapter.start(Box::new(registry.clone())).is_ok());
+
+        let txn_pair = make_transaction("loop", &[], b"");
+        let context_id = context_manager.create_context(&[], &state_id);
+        match execute(&adapter, txn_pair, context_id) {
+            Err(ExecutionAdapterError::RoutingError(_)) => (),
+            res => panic!("Expected a routing error, got {:?}", res),
+        }
+
+        let contracts = [
+            ("missing", None, "No contract is deployed"),
+            (
+                "garbage",
+                Some("garbage"),
+                "Contract is not a WebAssembly module",
+            ),
+            (
+                "loop",
+                Some(r#"(module (func (export "apply") (loop $l (br $l))))"#),
+                "Contract exceeded its limit of 10000 instructions",
+            ),
+            (
+                "unreachable",
+                Some(r#"(module (func (export "apply") (unreachable)))"#),
+                "Contract trapped: Unreachable",
+            ),
+            (
+                "float",
+                Some(
+                    r#"(module (func (export "apply")
+                         (drop (f32.add (f32.const 1) (f32.const 2)))))"#,
+                ),
+                "Contract uses floating point instructions",
+            ),
+            (
+                "memory",
+                Some(r#"(module (memory 1024) (func (export "apply")))"#),
+                "Contract requires 1024 pages of memory",
+            ),
+            (
+                "import",
+                Some(r#"(module (import "env" "exec" (func)) (func (export "apply")))"#),
+                "Unable to run contract",
+            ),
+        ];
+
+        for (version, contract, expected_message) in contracts.iter() {
+            assert!(adapter
+                .add_family(TransactionFamily::new(
+                    FAMILY_NAME.into(),
+                    (*version).to_string()
+                ))
+                .is_ok());
+
+            let txn_pair = make_transaction(version, &[], b"");
+            let context_id = context_manager.create_context(&[], &state_id);
+            match contract {
+                Some("garbage") => context_manager
+                    .set_state(
+                        &context_id,
+                        compute_contract_address(FAMILY_NAME, version),
+                        b"garbage".to_vec(),
+                    )
+                    .unwrap(),
+                Some(contract) => deploy(&context_manager, &context_id, version, contract),
+                None => (),
+            }
+
+            let error_message = invalid_message(execute(&adapter, txn_pair, context_id))
+                .expect("Transaction should be invalid");
+            assert!(
+                error_message.starts_with(expected_message),
+                "Unexpected error for {}: {}",
+                version,
+                error_message
+            );
+        }
+
+        assert!(Box::new(adapter).stop().is_ok());
+
+        assert_eq!(contracts.len(), registry.families.lock().unwrap().len());
+    }
+
+    /// Makes a transaction which may read and write the given addresses, and read its contract.
+    fn make_transaction(
+        family_version: &str,
+        addresses: &[&str],
+        payload: &[u8],
+    ) -> TransactionPair {
+        let contract_address = compute_contract_address(FAMILY_NAME, family_version);
+        let mut inputs = addresses.to_vec();
+        inputs.push(&contract_address);
+        build_transaction(family_version, &inputs, addresses, payload)
+    }
+
+    fn build_transaction(
+        family_version: &str,
+        inputs: &[&str],
+        outputs: &[&str],
+        payload: &[u8],
+    ) -> TransactionPair {
+        let decode = |addresses: &[&str]| -> Vec<Vec<u8>> {
+            addresses
+                .iter()
+                .map(|address| hex::decode(address).unwrap())
+                .collect()
+        };
+        TransactionBuilder::new()
+            .with_batcher_public_key(vec![0u8, 0u8, 0u8, 0u8])
+            .with_family_name(FAMILY_NAME.into())
+            .with_family_version(family_version.into())
+            .with_inputs(decode(inputs))
+            .with_outputs(decode(outputs))
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload.to_vec())
+            .build_pair(&HashSigner::new())
+            .unwrap()
+    }
+
+    fn deploy(
+        context_manager: &ContextManager,
+        context_id: &ContextId,
+        family_version: &str,
+        contract: &str,
+    ) {
+        context_manager
+            .set_state(
+                context_id,
+                compute_contract_address(FAMILY_NAME, family_version),
+                wat::parse_str(contract).unwrap(),
+            )
+            .unwrap();
+    }
+
+    fn execute(
+        adapter: &WasmExecutionAdapter,
+        txn_pair: TransactionPair,
+        context_id: ContextId,
+    ) -> Result<ExecutionTaskCompletionNotification, ExecutionAdapterError> {
+        let (send, recv) = channel();
+        assert!(adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        recv.recv().unwrap()
+    }
+
+    fn invalid_message(
+        result: Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>,
+    ) -> Option<String> {
+        match result {
+            Ok(ExecutionTaskCompletionNotification::Invalid(_, result)) => {
+                Some(result.error_message)
+            }
+            _ => None,
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        families: Arc<Mutex<Vec<TransactionFamily>>>,
+    }
+
+    impl ExecutionRegistry for MockRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.families.lock().unwrap().push(family);
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.families.lock().unwrap().retain(|f| f != family);
+        }
+    }
+}
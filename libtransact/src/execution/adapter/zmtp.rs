@@ -0,0 +1,244 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A minimal implementation of ZMTP 3.0, the wire protocol used by ZeroMQ sockets.
+//!
+//! Only what is needed for a connection between a ROUTER and a DEALER socket is implemented: the
+//! greeting, the NULL security mechanism's handshake, and the framing of messages.  Commands
+//! received after the handshake are ignored.
+
+use std::io::{self, Read, Write};
+
+/// The largest frame which is accepted from a peer.
+const MAX_FRAME_SIZE: u64 = 64 * 1024 * 1024;
+
+const GREETING_SIZE: usize = 64;
+const SIGNATURE_SIZE: usize = 10;
+const MECHANISM: &[u8] = b"NULL";
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Exchanges greetings and READY commands with a newly connected peer, announcing the given
+/// socket type.
+///
+/// # Errors
+///
+/// An `io::Error` with the kind `InvalidData` is returned if the peer does not speak ZMTP 3.0, or
+/// later, with the NULL mechanism, or if its socket type cannot connect to the given one.
+pub fn handshake<S: Read + Write>(stream: &mut S, socket_type: &[u8]) -> io::Result<()> {
+    let mut greeting = [0u8; GREETING_SIZE];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..12 + MECHANISM.len()].copy_from_slice(MECHANISM);
+    stream.write_all(&greeting)?;
+    stream.flush()?;
+
+    let mut peer_greeting = [0u8; GREETING_SIZE];
+    stream.read_exact(&mut peer_greeting[..SIGNATURE_SIZE])?;
+    if peer_greeting[0] != 0xff || peer_greeting[9] & 0x01 == 0 {
+        return Err(protocol_error("peer is not a ZMTP peer"));
+    }
+    stream.read_exact(&mut peer_greeting[SIGNATURE_SIZE..])?;
+    if peer_greeting[10] < 3 {
+        return Err(protocol_error(&format!(
+            "unsupported ZMTP version {}",
+            peer_greeting[10]
+        )));
+    }
+    let mechanism = &peer_greeting[12..32];
+    if &mechanism[..MECHANISM.len()] != MECHANISM
+        || mechanism[MECHANISM.len()..].iter().any(|b| *b != 0)
+    {
+        return Err(protocol_error("unsupported security mechanism"));
+    }
+
+    write_frame(
+        stream,
+        FLAG_COMMAND,
+        &ready_command(&[("Socket-Type", socket_type)]),
+    )?;
+    stream.flush()?;
+
+    let (flags, body) = read_frame(stream)?;
+    if flags & FLAG_COMMAND == 0 {
+        return Err(protocol_error("expected a READY command"));
+    }
+    let properties = parse_ready_command(&body)?;
+    match properties
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Socket-Type"))
+    {
+        Some((_, peer_type)) if compatible(socket_type, peer_type) => Ok(()),
+        Some((_, peer_type)) => Err(protocol_error(&format!(
+            "socket type {} cannot connect to {}",
+            String::from_utf8_lossy(peer_type),
+            String::from_utf8_lossy(socket_type)
+        ))),
+        None => Err(protocol_error("peer did not send its socket type")),
+    }
+}
+
+/// Returns whether a peer with the given socket type may connect to a socket of the given type.
+fn compatible(socket_type: &[u8], peer_type: &[u8]) -> bool {
+    match socket_type {
+        b"ROUTER" => peer_type == b"DEALER" || peer_type == b"REQ" || peer_type == b"ROUTER",
+        b"DEALER" => peer_type == b"ROUTER" || peer_type == b"REP" || peer_type == b"DEALER",
+        _ => false,
+    }
+}
+
+/// Reads the next message, skipping any commands, and returns its frames.
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Vec<Vec<u8>>> {
+    let mut frames = vec![];
+    loop {
+        let (flags, body) = read_frame(reader)?;
+        if flags & FLAG_COMMAND != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags & FLAG_MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+/// Writes a message made of a single frame.
+pub fn write_message<W: Write>(writer: &mut W, body: &[u8]) -> io::Result<()> {
+    write_frame(writer, 0, body)?;
+    writer.flush()
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    reader.read_exact(&mut flags)?;
+    let flags = flags[0];
+
+    let size = if flags & FLAG_LONG != 0 {
+        let mut size = [0u8; 8];
+        reader.read_exact(&mut size)?;
+        u64::from_be_bytes(size)
+    } else {
+        let mut size = [0u8; 1];
+        reader.read_exact(&mut size)?;
+        u64::from(size[0])
+    };
+    if size > MAX_FRAME_SIZE {
+        return Err(protocol_error(&format!(
+            "frame of {} bytes exceeds the limit",
+            size
+        )));
+    }
+
+    let mut body = vec![0u8; size as usize];
+    reader.read_exact(&mut body)?;
+    Ok((flags, body))
+}
+
+fn write_frame<W: Write>(writer: &mut W, flags: u8, body: &[u8]) -> io::Result<()> {
+    if body.len() > 255 {
+        writer.write_all(&[flags | FLAG_LONG])?;
+        writer.write_all(&(body.len() as u64).to_be_bytes())?;
+    } else {
+        writer.write_all(&[flags, body.len() as u8])?;
+    }
+    writer.write_all(body)
+}
+
+fn ready_command(properties: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut body = vec![5u8];
+    body.extend_from_slice(b"READY");
+    for (name, value) in properties {
+        body.push(name.len() as u8);
+        body.extend_from_slice(name.as_bytes());
+        body.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        body.extend_from_slice(value);
+    }
+    body
+}
+
+fn parse_ready_command(body: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut input = body;
+    if take(&mut input, 1)? != [5] || take(&mut input, 5)? != b"READY" {
+        return Err(protocol_error("expected a READY command"));
+    }
+
+    let mut properties = vec![];
+    while !input.is_empty() {
+        let name_len = take(&mut input, 1)?[0] as usize;
+        let name = String::from_utf8_lossy(take(&mut input, name_len)?).into_owned();
+        let mut value_len = [0u8; 4];
+        value_len.copy_from_slice(take(&mut input, 4)?);
+        let value = take(&mut input, u32::from_be_bytes(value_len) as usize)?.to_vec();
+        properties.push((name, value));
+    }
+    Ok(properties)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(protocol_error("command is truncated"));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_roundtrip() {
+        let long_body = vec![7u8; 300];
+        let mut bytes = vec![];
+        write_message(&mut bytes, b"short").unwrap();
+        write_message(&mut bytes, &long_body).unwrap();
+        // A command, and a message made of two frames
+        write_frame(&mut bytes, FLAG_COMMAND, b"\x04PING\x00\x00").unwrap();
+        write_frame(&mut bytes, FLAG_MORE, b"").unwrap();
+        write_frame(&mut bytes, 0, b"last").unwrap();
+
+        let mut input = &bytes[..];
+        assert_eq!(vec![b"short".to_vec()], read_message(&mut input).unwrap());
+        assert_eq!(vec![long_body], read_message(&mut input).unwrap());
+        assert_eq!(
+            vec![vec![], b"last".to_vec()],
+            read_message(&mut input).unwrap()
+        );
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn ready_command_roundtrip() {
+        let body = ready_command(&[("Socket-Type", b"DEALER"), ("Identity", b"")]);
+        assert_eq!(
+            vec![
+                ("Socket-Type".to_string(), b"DEALER".to_vec()),
+                ("Identity".to_string(), vec![]),
+            ],
+            parse_ready_command(&body).unwrap()
+        );
+        assert!(parse_ready_command(&body[..body.len() - 2]).is_err());
+    }
+}
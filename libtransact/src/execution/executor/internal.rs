@@ -30,20 +30,26 @@ use std::error::Error;
 use std::hash::{Hash, Hasher};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    mpsc::{channel, Receiver, Sender},
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender},
     Arc,
 };
 use std::thread::JoinHandle;
+use std::time::Instant;
 
 use log::warn;
 
+use super::RetryPolicy;
+use crate::context::ContextId;
 use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError};
 use crate::execution::{ExecutionRegistry, TransactionFamily};
-use crate::scheduler::{ExecutionTask, ExecutionTaskCompletionNotifier};
+use crate::scheduler::{
+    ExecutionTask, ExecutionTaskCompletionNotification, ExecutionTaskCompletionNotifier,
+    InvalidTransactionResult,
+};
 
 /// The `TransactionPair` and `ContextId` along with where to send
-/// results.
-pub type ExecutionEvent = (Box<dyn ExecutionTaskCompletionNotifier>, ExecutionTask);
+/// results, and the number of times its execution has already failed.
+pub type ExecutionEvent = (Box<dyn ExecutionTaskCompletionNotifier>, ExecutionTask, u32);
 
 /// The type that gets sent to the `ExecutionAdapter`.
 pub enum ExecutionCommand {
@@ -64,6 +70,9 @@ pub enum RegistrationChange {
 pub enum ExecutorCommand {
     RegistrationChange(RegistrationChange),
     Execution(Box<ExecutionEvent>),
+    /// An `ExecutionEvent` whose execution failed, to be sent again once the given time has
+    /// passed.
+    Retry(Instant, Box<ExecutionEvent>),
     Shutdown,
 }
 
@@ -149,6 +158,7 @@ impl std::fmt::Display for ExecutorThreadError {
 
 pub struct ExecutorThread {
     execution_adapters: Vec<Box<ExecutionAdapter>>,
+    retry_policy: RetryPolicy,
     join_handles: Vec<JoinHandle<()>>,
     internal_thread: Option<JoinHandle<()>>,
     sender: Option<ExecutorCommandSender>,
@@ -157,8 +167,16 @@ pub struct ExecutorThread {
 
 impl ExecutorThread {
     pub fn new(execution_adapters: Vec<Box<ExecutionAdapter>>) -> Self {
+        Self::new_with_retry_policy(execution_adapters, RetryPolicy::unlimited())
+    }
+
+    pub fn new_with_retry_policy(
+        execution_adapters: Vec<Box<ExecutionAdapter>>,
+        retry_policy: RetryPolicy,
+    ) -> Self {
         ExecutorThread {
             execution_adapters,
+            retry_policy,
             join_handles: vec![],
             internal_thread: None,
             sender: None,
@@ -188,6 +206,7 @@ impl ExecutorThread {
 
                 match Self::start_execution_adapter_thread(
                     Arc::clone(&self.stop),
+                    self.retry_policy,
                     execution_adapter,
                     adapter_receiver,
                     &registry_sender,
@@ -238,6 +257,7 @@ impl ExecutorThread {
 
     fn start_execution_adapter_thread(
         stop: Arc<AtomicBool>,
+        retry_policy: RetryPolicy,
         execution_adapter: Box<ExecutionAdapter>,
         receiver: ExecutionEventReceiver,
         sender: &ExecutorCommandSender,
@@ -252,8 +272,9 @@ impl ExecutorThread {
                     match execution_command {
                         ExecutionCommand::Event(execution_event) => {
                             let sender = sender.clone();
-                            let (completion_notifier, task) = *execution_event;
+                            let (completion_notifier, task, failures) = *execution_event;
                             let (pair, context_id) = task.take();
+                            let transaction_id = pair.transaction().header_signature().to_string();
 
                             let callback = Box::new(move |result| {
                                 // Without this line, the function is considered a FnOnce, instead
@@ -266,25 +287,30 @@ impl ExecutorThread {
                                     Err(ExecutionAdapterError::TimeoutError(transaction_pair)) => {
                                         let execution_task =
                                             ExecutionTask::new(*transaction_pair, context_id);
-                                        let execution_event = (completion_notifier, execution_task);
-                                        if let Err(err) = sender.send(ExecutorCommand::Execution(
-                                            Box::new(execution_event),
-                                        )) {
-                                            warn!("During retry of TimeOutError: {}", err);
-                                        }
+                                        Self::retry_execution_event(
+                                            &sender,
+                                            &retry_policy,
+                                            (completion_notifier, execution_task, failures + 1),
+                                            "timed out",
+                                        );
                                     }
                                     Err(ExecutionAdapterError::RoutingError(transaction_pair)) => {
                                         let execution_task =
                                             ExecutionTask::new(*transaction_pair, context_id);
-                                        let execution_event = (completion_notifier, execution_task);
-                                        if let Err(err) = sender.send(ExecutorCommand::Execution(
-                                            Box::new(execution_event),
-                                        )) {
-                                            warn!("During retry of RoutingError: {}", err);
-                                        }
+                                        Self::retry_execution_event(
+                                            &sender,
+                                            &retry_policy,
+                                            (completion_notifier, execution_task, failures + 1),
+                                            "could not be routed",
+                                        );
                                     }
                                     Err(ExecutionAdapterError::GeneralExecutionError(err)) => {
                                         error!("General Execution Error: {}", err);
+                                        completion_notifier.notify(Self::failed_notification(
+                                            context_id,
+                                            transaction_id.clone(),
+                                            format!("Transaction could not be executed: {}", err),
+                                        ));
                                     }
                                 }
                             });
@@ -310,6 +336,59 @@ impl ExecutorThread {
             })
     }
 
+    /// Sends an `ExecutionEvent` whose execution failed to be executed again, once the retry
+    /// policy's backoff has passed.  If the policy's attempts are exhausted, the transaction is
+    /// instead reported to the scheduler as invalid, so that the scheduler is not left waiting
+    /// for it.
+    fn retry_execution_event(
+        sender: &ExecutorCommandSender,
+        retry_policy: &RetryPolicy,
+        execution_event: ExecutionEvent,
+        reason: &str,
+    ) {
+        let (completion_notifier, task, failures) = execution_event;
+        let exhausted = match retry_policy.max_attempts() {
+            Some(max_attempts) => failures >= max_attempts,
+            None => false,
+        };
+        if exhausted {
+            let transaction_id = task.pair().transaction().header_signature().to_string();
+            warn!(
+                "Transaction {} {} after {} attempts",
+                transaction_id, reason, failures
+            );
+            completion_notifier.notify(Self::failed_notification(
+                *task.context_id(),
+                transaction_id,
+                format!("Transaction {} after {} attempts", reason, failures),
+            ));
+            return;
+        }
+
+        let retry_at = Instant::now() + retry_policy.backoff(failures);
+        if let Err(err) = sender.send(ExecutorCommand::Retry(
+            retry_at,
+            Box::new((completion_notifier, task, failures)),
+        )) {
+            warn!("During retry of transaction which {}: {}", reason, err);
+        }
+    }
+
+    fn failed_notification(
+        context_id: ContextId,
+        transaction_id: String,
+        error_message: String,
+    ) -> ExecutionTaskCompletionNotification {
+        ExecutionTaskCompletionNotification::Invalid(
+            context_id,
+            InvalidTransactionResult {
+                transaction_id,
+                error_message,
+                error_data: vec![],
+            },
+        )
+    }
+
     fn start_thread(
         &self,
         receiver: ExecutorCommandReceiver,
@@ -324,7 +403,22 @@ impl ExecutorThread {
                 > = HashMap::new();
                 let mut parked: ParkedExecutionEventsMap = HashMap::new();
                 let mut unparked = vec![];
+                // Retried ExecutionEvents, along with when they may be sent
+                let mut delayed: Vec<(Instant, ExecutionEvent)> = vec![];
                 loop {
+                    let now = Instant::now();
+                    if delayed.iter().any(|(retry_at, _)| *retry_at <= now) {
+                        let (ready, waiting): (Vec<_>, Vec<_>) = delayed
+                            .drain(0..)
+                            .partition(|(retry_at, _)| *retry_at <= now);
+                        delayed = waiting;
+                        unparked.extend(
+                            ready
+                                .into_iter()
+                                .map(|(_, execution_event)| execution_event),
+                        );
+                    }
+
                     for execution_event in unparked.drain(0..) {
                         Self::try_send_execution_event(
                             Box::new(execution_event),
@@ -333,7 +427,15 @@ impl ExecutorThread {
                         );
                     }
 
-                    match receiver.recv() {
+                    let command = match delayed.iter().map(|(retry_at, _)| *retry_at).min() {
+                        Some(retry_at) => match receiver.recv_timeout(retry_at - now) {
+                            Err(RecvTimeoutError::Timeout) => continue,
+                            command => command,
+                        },
+                        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    };
+
+                    match command {
                         Ok(ExecutorCommand::Execution(execution_event)) => {
                             if stop.load(Ordering::Relaxed) {
                                 Self::shutdown_fanout_threads(&fanout_threads);
@@ -345,6 +447,13 @@ impl ExecutorThread {
                                 &mut parked,
                             )
                         }
+                        Ok(ExecutorCommand::Retry(retry_at, execution_event)) => {
+                            if stop.load(Ordering::Relaxed) {
+                                Self::shutdown_fanout_threads(&fanout_threads);
+                                break;
+                            }
+                            delayed.push((retry_at, *execution_event));
+                        }
                         Ok(ExecutorCommand::RegistrationChange(
                             RegistrationChange::RegisterRequest((transaction_family, sender)),
                         )) => {
@@ -458,9 +567,12 @@ impl ExecutorThread {
 mod tests {
     use super::*;
     use crate::execution::adapter::test_adapter::TestExecutionAdapter;
+    use crate::execution::adapter::ExecutionOperationError;
     use crate::protocol::transaction::{HashMethod, TransactionBuilder, TransactionPair};
     use crate::scheduler::ExecutionTaskCompletionNotification;
     use crate::signing::{hash::HashSigner, Signer};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
     use std::{self, collections::HashSet, sync::mpsc::channel};
 
     static FAMILY_NAME: &str = "test";
@@ -512,7 +624,7 @@ mod tests {
         // Send the ExecutionEvents on the multiplexing channel.
 
         for reg_ex_event in execution_tasks
-            .map(|execution_task| (notifier.clone(), execution_task))
+            .map(|execution_task| (notifier.clone(), execution_task, 0))
             .map(|execution_event| ExecutorCommand::Execution(Box::new(execution_event)))
         {
             registration_execution_event_sender
@@ -530,7 +642,7 @@ mod tests {
         while let Ok(event) = internal_receiver.try_recv() {
             match event {
                 ExecutorCommand::Execution(execution_event) => {
-                    let (_, execution_state) = execution_event.as_ref();
+                    let (_, execution_state, _) = execution_event.as_ref();
 
                     let tf = TransactionFamily::from_pair(execution_state.pair());
                     match named_senders.get(&tf) {
@@ -599,6 +711,7 @@ mod tests {
                         });
                     }
                 },
+                ExecutorCommand::Retry(_, _) => panic!("Should not have retried during test"),
                 ExecutorCommand::Shutdown => panic!("Should not have called shutdown during test"),
             }
         }
@@ -607,7 +720,7 @@ mod tests {
 
         while let Ok(event) = receiver.try_recv() {
             if let ExecutionCommand::Event(execution_event) = event {
-                let (notifier, task) = *execution_event;
+                let (notifier, task, _) = *execution_event;
 
                 let notification = ExecutionTaskCompletionNotification::Valid(
                     *task.context_id(),
@@ -655,7 +768,7 @@ mod tests {
             Box::new(ChannelExecutionTaskCompletionNotifier { tx });
 
         for reg_ex_event in execution_tasks
-            .map(|execution_task| (notifier.clone(), execution_task))
+            .map(|execution_task| (notifier.clone(), execution_task, 0))
             .map(|execution_event| ExecutorCommand::Execution(Box::new(execution_event)))
         {
             sender
@@ -687,6 +800,71 @@ mod tests {
         executor_thread.stop();
     }
 
+    /// Transactions which cannot be routed are retried until the retry policy's attempts are
+    /// exhausted, and transactions which fail with a general error are not retried; both are
+    /// reported as invalid.
+    #[test]
+    fn test_executor_thread_retry_policy() {
+        let cases = [
+            (false, 3, "Transaction could not be routed after 3 attempts"),
+            (true, 1, "Transaction could not be executed: "),
+        ];
+        for &(general_error, attempts, error_message) in cases.iter() {
+            let adapter = FailingExecutionAdapter {
+                general_error,
+                attempts: Arc::new(AtomicUsize::new(0)),
+            };
+
+            let mut executor_thread = ExecutorThread::new_with_retry_policy(
+                vec![Box::new(adapter.clone())],
+                RetryPolicy::new(3, Duration::from_millis(1), Duration::from_millis(4)),
+            );
+
+            executor_thread
+                .start()
+                .expect("Start can only be called once");
+
+            let sender = executor_thread
+                .sender()
+                .expect("Sender is some after start is called");
+
+            let (tx, receiver) = channel();
+            let notifier: Box<dyn ExecutionTaskCompletionNotifier> =
+                Box::new(ChannelExecutionTaskCompletionNotifier { tx });
+
+            for reg_ex_event in create_iterator()
+                .map(|execution_task| (notifier.clone(), execution_task, 0))
+                .map(|execution_event| ExecutorCommand::Execution(Box::new(execution_event)))
+            {
+                sender
+                    .send(reg_ex_event)
+                    .expect("Receiver has been dropped");
+            }
+
+            for _ in 0..NUMBER_OF_TRANSACTIONS {
+                match receiver
+                    .recv_timeout(Duration::from_secs(5))
+                    .expect("The transaction was not reported")
+                {
+                    ExecutionTaskCompletionNotification::Invalid(_, result) => assert!(
+                        result.error_message.starts_with(error_message),
+                        "Unexpected error message: {}",
+                        result.error_message
+                    ),
+                    notification => panic!("Expected an invalid result, got {:?}", notification),
+                }
+            }
+
+            assert_eq!(
+                attempts * NUMBER_OF_TRANSACTIONS,
+                adapter.attempts.load(Ordering::SeqCst),
+                "Incorrect number of attempts"
+            );
+
+            executor_thread.stop();
+        }
+    }
+
     fn create_txn(signer: &Signer) -> TransactionPair {
         TransactionBuilder::new()
             .with_batcher_public_key(hex::decode(KEY1).unwrap())
@@ -717,6 +895,49 @@ mod tests {
             .map(move |txn_pair| ExecutionTask::new(txn_pair, context_id.clone()))
     }
 
+    /// An adapter which registers the test family, and fails to execute every transaction.
+    #[derive(Clone)]
+    struct FailingExecutionAdapter {
+        general_error: bool,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl ExecutionAdapter for FailingExecutionAdapter {
+        fn start(
+            &mut self,
+            mut execution_registry: Box<dyn ExecutionRegistry>,
+        ) -> Result<(), ExecutionOperationError> {
+            execution_registry.register_transaction_family(TransactionFamily::new(
+                FAMILY_NAME.to_string(),
+                FAMILY_VERSION.to_string(),
+            ));
+            Ok(())
+        }
+
+        fn execute(
+            &self,
+            transaction_pair: TransactionPair,
+            _context_id: ContextId,
+            on_done: Box<
+                dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+            >,
+        ) -> Result<(), ExecutionOperationError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            on_done(Err(if self.general_error {
+                ExecutionAdapterError::GeneralExecutionError(Box::new(
+                    ExecutorThreadError::ResourcesUnavailable,
+                ))
+            } else {
+                ExecutionAdapterError::RoutingError(Box::new(transaction_pair))
+            }));
+            Ok(())
+        }
+
+        fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+            Ok(())
+        }
+    }
+
     #[derive(Clone)]
     struct ChannelExecutionTaskCompletionNotifier {
         tx: Sender<ExecutionTaskCompletionNotification>,
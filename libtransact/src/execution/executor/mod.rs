@@ -29,6 +29,56 @@ use log::debug;
 use std::collections::HashMap;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How the `Executor` retries a transaction whose execution adapter timed out, or could not route
+/// it.
+///
+/// A transaction is attempted up to `max_attempts` times.  Each retry waits for a backoff which
+/// starts at `initial_backoff` and doubles with every failed attempt, up to `max_backoff`.  Once
+/// the attempts are exhausted the transaction is reported to its scheduler as invalid.  An
+/// unlimited policy, which is used by `Executor::new`, retries a transaction immediately, until
+/// it succeeds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: Option<u32>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a retry policy; at least one attempt is always made.
+    pub fn new(max_attempts: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: Some(max_attempts.max(1)),
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Creates a retry policy which retries a transaction immediately, as many times as it takes.
+    pub fn unlimited() -> Self {
+        RetryPolicy {
+            max_attempts: None,
+            initial_backoff: Duration::from_secs(0),
+            max_backoff: Duration::from_secs(0),
+        }
+    }
+
+    /// The number of times a transaction is attempted before it is reported as invalid, or
+    /// `None` if it is retried until it succeeds.
+    pub fn max_attempts(&self) -> Option<u32> {
+        self.max_attempts
+    }
+
+    /// Returns how long to wait before retrying a transaction which has failed the given number
+    /// of times.
+    fn backoff(&self, failures: u32) -> Duration {
+        2u32.checked_pow(failures.saturating_sub(1))
+            .and_then(|factor| self.initial_backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
 
 pub struct Executor {
     readers: Arc<Mutex<HashMap<usize, ExecutionTaskReader>>>,
@@ -92,12 +142,28 @@ impl Executor {
         self.executor_thread.stop();
     }
 
+    /// Creates an `Executor` which retries a transaction whose execution adapter timed out, or
+    /// could not route it, until it succeeds.
     pub fn new(execution_adapters: Vec<Box<ExecutionAdapter>>) -> Self {
         Executor {
             readers: Arc::new(Mutex::new(HashMap::new())),
             executor_thread: ExecutorThread::new(execution_adapters),
         }
     }
+
+    /// Creates an `Executor` which retries failed transactions according to the given policy.
+    pub fn new_with_retry_policy(
+        execution_adapters: Vec<Box<ExecutionAdapter>>,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Executor {
+            readers: Arc::new(Mutex::new(HashMap::new())),
+            executor_thread: ExecutorThread::new_with_retry_policy(
+                execution_adapters,
+                retry_policy,
+            ),
+        }
+    }
 }
 
 impl SubSchedulerHandler for Executor {
@@ -165,6 +231,24 @@ mod tests {
 
     static NUMBER_OF_TRANSACTIONS: usize = 20;
 
+    #[test]
+    fn test_retry_policy_backoff() {
+        let retry_policy =
+            RetryPolicy::new(0, Duration::from_millis(10), Duration::from_millis(50));
+
+        assert_eq!(Some(1), retry_policy.max_attempts());
+        assert_eq!(Duration::from_millis(10), retry_policy.backoff(1));
+        assert_eq!(Duration::from_millis(20), retry_policy.backoff(2));
+        assert_eq!(Duration::from_millis(40), retry_policy.backoff(3));
+        assert_eq!(Duration::from_millis(50), retry_policy.backoff(4));
+        assert_eq!(Duration::from_millis(50), retry_policy.backoff(100));
+
+        let retry_policy = RetryPolicy::unlimited();
+
+        assert_eq!(None, retry_policy.max_attempts());
+        assert_eq!(Duration::from_secs(0), retry_policy.backoff(100));
+    }
+
     #[test]
     fn test_executor() {
         let test_execution_adapter1 = TestExecutionAdapter::new();
@@ -61,7 +61,7 @@ impl ExecutionTaskReader {
                             break;
                         }
 
-                        let execution_event = (notifier.clone(), execution_task);
+                        let execution_event = (notifier.clone(), execution_task, 0);
                         let event = ExecutorCommand::Execution(Box::new(execution_event));
 
                         if let Err(err) = internal.send(event) {
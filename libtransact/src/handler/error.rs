@@ -44,8 +44,12 @@ impl std::fmt::Display for ApplyError {
 pub enum ContextError {
     /// Returned for an authorization error
     AuthorizationError(String),
+    /// Returned when a context is rolled back to a savepoint which is not valid for it
+    InvalidSavepoint(String),
     /// Returned when a error occurs due to missing info in a response
     ResponseAttributeError(String),
+    /// Returned when the context does not support the requested operation
+    UnsupportedError(String),
     /// Returned when there is an issues setting receipt data or events.
     TransactionReceiptError(String),
     /// Returned when a ProtobufError is returned during serializing
@@ -60,7 +64,9 @@ impl Error for ContextError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             ContextError::AuthorizationError(_) => None,
+            ContextError::InvalidSavepoint(_) => None,
             ContextError::ResponseAttributeError(_) => None,
+            ContextError::UnsupportedError(_) => None,
             ContextError::TransactionReceiptError(_) => None,
             ContextError::SerializationError(err) => Some(&**err),
             ContextError::SendError(err) => Some(&**err),
@@ -73,12 +79,14 @@ impl std::fmt::Display for ContextError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
             ContextError::AuthorizationError(ref s) => write!(f, "AuthorizationError: {}", s),
+            ContextError::InvalidSavepoint(ref s) => write!(f, "InvalidSavepoint: {}", s),
             ContextError::ResponseAttributeError(ref s) => {
                 write!(f, "ResponseAttributeError: {}", s)
             }
             ContextError::TransactionReceiptError(ref s) => {
                 write!(f, "TransactionReceiptError: {}", s)
             }
+            ContextError::UnsupportedError(ref s) => write!(f, "UnsupportedError: {}", s),
             ContextError::SerializationError(ref err) => {
                 write!(f, "SerializationError: {}", err.description())
             }
@@ -25,6 +25,7 @@
 
 mod error;
 
+pub use crate::context::Savepoint;
 pub use crate::handler::error::{ApplyError, ContextError};
 use crate::protocol::transaction::TransactionPair;
 
@@ -55,6 +56,22 @@ pub trait TransactionContext {
         addresses: &[String],
     ) -> Result<Vec<(String, Vec<u8>)>, ContextError>;
 
+    /// list_state_entries queries the validator state for every address
+    /// starting with the given prefix, including the changes made so far by
+    /// this transaction. The addresses that have been set are returned, with
+    /// their data, ordered by address.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - the address prefix to list, which must be in the transaction's inputs
+    ///
+    /// The default implementation returns `ContextError::UnsupportedError`.
+    fn list_state_entries(&self, _prefix: &str) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        Err(ContextError::UnsupportedError(
+            "Listing state entries is not supported by this context".into(),
+        ))
+    }
+
     /// set_state_entry requests that the provided address is set in the validator state to its
     /// corresponding value.
     ///
@@ -97,6 +114,30 @@ pub trait TransactionContext {
     /// * `addresses` - the addresses to delete
     fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError>;
 
+    /// savepoint returns a savepoint for the state changes, events and data
+    /// added so far by this transaction, which may later be rolled back to.
+    ///
+    /// The default implementation returns `ContextError::UnsupportedError`.
+    fn savepoint(&self) -> Result<Savepoint, ContextError> {
+        Err(ContextError::UnsupportedError(
+            "Savepoints are not supported by this context".into(),
+        ))
+    }
+
+    /// rollback_to discards the state changes, events and data added by this
+    /// transaction since the given savepoint was taken.
+    ///
+    /// # Arguments
+    ///
+    /// * `savepoint` - a savepoint previously returned by this context
+    ///
+    /// The default implementation returns `ContextError::UnsupportedError`.
+    fn rollback_to(&self, _savepoint: &Savepoint) -> Result<(), ContextError> {
+        Err(ContextError::UnsupportedError(
+            "Savepoints are not supported by this context".into(),
+        ))
+    }
+
     /// add_receipt_data adds a blob to the execution result for this transaction
     ///
     /// # Arguments
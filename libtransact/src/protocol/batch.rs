@@ -159,6 +159,61 @@ impl BatchPair {
     pub fn take(self) -> (Batch, BatchHeader) {
         (self.batch, self.header)
     }
+
+    /// Verifies that the batch's header signature was made by the header's signer public key,
+    /// that the header's transaction IDs match the batch's transactions in order, and that each
+    /// transaction is valid, as checked by `TransactionPair::verify`.
+    pub fn verify(&self, verifier: &signing::Verifier) -> Result<(), BatchVerificationError> {
+        let signature = hex::decode(self.batch.header_signature()).map_err(|e| {
+            BatchVerificationError::InvalidSignature(format!(
+                "header signature is not valid hex: {}",
+                e
+            ))
+        })?;
+        let is_valid = verifier
+            .verify(
+                self.batch.header(),
+                &signature,
+                self.header.signer_public_key(),
+            )
+            .map_err(|e| BatchVerificationError::VerificationError(format!("{}", e)))?;
+        if !is_valid {
+            return Err(BatchVerificationError::InvalidSignature(format!(
+                "header signature {} does not match the signer public key",
+                self.batch.header_signature()
+            )));
+        }
+
+        let transaction_ids = self
+            .batch
+            .transactions()
+            .iter()
+            .map(|transaction| hex::decode(transaction.header_signature()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                BatchVerificationError::InvalidTransaction(format!(
+                    "transaction header signature is not valid hex: {}",
+                    e
+                ))
+            })?;
+        if transaction_ids.as_slice() != self.header.transaction_ids() {
+            return Err(BatchVerificationError::TransactionIdMismatch(format!(
+                "transaction IDs of batch {} do not match its transactions",
+                self.batch.header_signature()
+            )));
+        }
+
+        for transaction in self.batch.transactions() {
+            transaction
+                .clone()
+                .into_pair()
+                .map_err(|e| BatchVerificationError::InvalidTransaction(format!("{}", e)))?
+                .verify(verifier)
+                .map_err(|e| BatchVerificationError::InvalidTransaction(format!("{}", e)))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl From<protos::batch::Batch> for Batch {
@@ -215,6 +270,55 @@ impl From<ProtoConversionError> for BatchBuildError {
     }
 }
 
+#[derive(Debug)]
+pub enum BatchVerificationError {
+    /// Returned when the header signature was not made by the header's signer public key.
+    InvalidSignature(String),
+    /// Returned when the header's transaction IDs do not match the batch's transactions.
+    TransactionIdMismatch(String),
+    /// Returned when one of the batch's transactions fails verification.
+    InvalidTransaction(String),
+    /// Returned when the verifier is unable to check the signature.
+    VerificationError(String),
+}
+
+impl StdError for BatchVerificationError {
+    fn description(&self) -> &str {
+        match *self {
+            BatchVerificationError::InvalidSignature(ref msg) => msg,
+            BatchVerificationError::TransactionIdMismatch(ref msg) => msg,
+            BatchVerificationError::InvalidTransaction(ref msg) => msg,
+            BatchVerificationError::VerificationError(ref msg) => msg,
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            BatchVerificationError::InvalidSignature(_) => None,
+            BatchVerificationError::TransactionIdMismatch(_) => None,
+            BatchVerificationError::InvalidTransaction(_) => None,
+            BatchVerificationError::VerificationError(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BatchVerificationError::InvalidSignature(ref s) => write!(f, "InvalidSignature: {}", s),
+            BatchVerificationError::TransactionIdMismatch(ref s) => {
+                write!(f, "TransactionIdMismatch: {}", s)
+            }
+            BatchVerificationError::InvalidTransaction(ref s) => {
+                write!(f, "InvalidTransaction: {}", s)
+            }
+            BatchVerificationError::VerificationError(ref s) => {
+                write!(f, "VerificationError: {}", s)
+            }
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct BatchBuilder {
     transactions: Option<Vec<Transaction>>,
@@ -288,6 +392,7 @@ impl BatchBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
     use crate::signing::hash::HashSigner;
     use crate::signing::Signer;
     #[cfg(feature = "sawtooth-compat")]
@@ -467,6 +572,102 @@ mod tests {
         assert_eq!(true, batch.trace());
     }
 
+    fn build_transaction(signer: &HashSigner, payload: &[u8]) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![hex::decode(KEY2).unwrap()])
+            .with_outputs(vec![hex::decode(KEY3).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload.to_vec())
+            .build(signer)
+            .unwrap()
+    }
+
+    #[test]
+    fn batch_verify() {
+        let signer = HashSigner::new();
+        let pair = BatchBuilder::new()
+            .with_transactions(vec![
+                build_transaction(&signer, &BYTES1),
+                build_transaction(&signer, &BYTES2),
+            ])
+            .build_pair(&signer)
+            .unwrap();
+
+        assert!(pair.verify(&signer).is_ok());
+    }
+
+    #[test]
+    fn batch_verify_transaction_order() {
+        let signer = HashSigner::new();
+        let (batch, header) = BatchBuilder::new()
+            .with_transactions(vec![
+                build_transaction(&signer, &BYTES1),
+                build_transaction(&signer, &BYTES2),
+            ])
+            .build_pair(&signer)
+            .unwrap()
+            .take();
+
+        let mut transactions = batch.transactions().to_vec();
+        transactions.reverse();
+        let pair = BatchPair {
+            batch: Batch {
+                transactions,
+                ..batch
+            },
+            header,
+        };
+
+        match pair.verify(&signer) {
+            Err(BatchVerificationError::TransactionIdMismatch(_)) => (),
+            res => panic!("Expected TransactionIdMismatch, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn batch_verify_invalid_transaction() {
+        let signer = HashSigner::new();
+        let transaction = build_transaction(&signer, &BYTES1);
+        let forged_transaction = Transaction::new(
+            transaction.header().to_vec(),
+            transaction.header_signature().to_string(),
+            BYTES2.to_vec(),
+        );
+        let pair = BatchBuilder::new()
+            .with_transactions(vec![forged_transaction])
+            .build_pair(&signer)
+            .unwrap();
+
+        match pair.verify(&signer) {
+            Err(BatchVerificationError::InvalidTransaction(_)) => (),
+            res => panic!("Expected InvalidTransaction, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn batch_verify_invalid_signature() {
+        let signer = HashSigner::new();
+        let (batch, header) = BatchBuilder::new()
+            .with_transactions(vec![build_transaction(&signer, &BYTES1)])
+            .build_pair(&signer)
+            .unwrap()
+            .take();
+        let pair = BatchPair {
+            batch: Batch {
+                header_signature: hex::encode(SIGNATURE1),
+                ..batch
+            },
+            header,
+        };
+
+        match pair.verify(&signer) {
+            Err(BatchVerificationError::InvalidSignature(_)) => (),
+            res => panic!("Expected InvalidSignature, got {:?}", res),
+        }
+    }
+
     #[cfg(feature = "sawtooth-compat")]
     #[test]
     fn batch_sawtooth10_compatibility() {}
@@ -250,6 +250,53 @@ impl TransactionPair {
     pub fn take(self) -> (Transaction, TransactionHeader) {
         (self.transaction, self.header)
     }
+
+    /// Verifies that the transaction's header signature was made by the header's signer public
+    /// key, and that the header's payload hash matches the payload.
+    pub fn verify(&self, verifier: &signing::Verifier) -> Result<(), TransactionVerificationError> {
+        let signature = hex::decode(self.transaction.header_signature()).map_err(|e| {
+            TransactionVerificationError::InvalidSignature(format!(
+                "header signature is not valid hex: {}",
+                e
+            ))
+        })?;
+        let is_valid = verifier
+            .verify(
+                self.transaction.header(),
+                &signature,
+                self.header.signer_public_key(),
+            )
+            .map_err(|e| TransactionVerificationError::VerificationError(format!("{}", e)))?;
+        if !is_valid {
+            return Err(TransactionVerificationError::InvalidSignature(format!(
+                "header signature {} does not match the signer public key",
+                self.transaction.header_signature()
+            )));
+        }
+
+        let payload_hash = hash_payload(
+            self.header.payload_hash_method(),
+            self.transaction.payload(),
+        );
+        if payload_hash != self.header.payload_hash() {
+            return Err(TransactionVerificationError::InvalidPayloadHash(format!(
+                "payload hash of transaction {} does not match the payload",
+                self.transaction.header_signature()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn hash_payload(payload_hash_method: &HashMethod, payload: &[u8]) -> Vec<u8> {
+    match payload_hash_method {
+        HashMethod::SHA512 => {
+            let mut hasher = Sha512::new();
+            hasher.input(payload);
+            hasher.result().to_vec()
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -301,6 +348,50 @@ impl From<ProtoConversionError> for TransactionBuildError {
     }
 }
 
+#[derive(Debug)]
+pub enum TransactionVerificationError {
+    /// Returned when the header signature was not made by the header's signer public key.
+    InvalidSignature(String),
+    /// Returned when the header's payload hash does not match the payload.
+    InvalidPayloadHash(String),
+    /// Returned when the verifier is unable to check the signature.
+    VerificationError(String),
+}
+
+impl StdError for TransactionVerificationError {
+    fn description(&self) -> &str {
+        match *self {
+            TransactionVerificationError::InvalidSignature(ref msg) => msg,
+            TransactionVerificationError::InvalidPayloadHash(ref msg) => msg,
+            TransactionVerificationError::VerificationError(ref msg) => msg,
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            TransactionVerificationError::InvalidSignature(_) => None,
+            TransactionVerificationError::InvalidPayloadHash(_) => None,
+            TransactionVerificationError::VerificationError(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TransactionVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            TransactionVerificationError::InvalidSignature(ref s) => {
+                write!(f, "InvalidSignature: {}", s)
+            }
+            TransactionVerificationError::InvalidPayloadHash(ref s) => {
+                write!(f, "InvalidPayloadHash: {}", s)
+            }
+            TransactionVerificationError::VerificationError(ref s) => {
+                write!(f, "VerificationError: {}", s)
+            }
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct TransactionBuilder {
     batcher_public_key: Option<Vec<u8>>,
@@ -405,13 +496,7 @@ impl TransactionBuilder {
         })?;
         let signer_public_key = signer.public_key().to_vec();
 
-        let payload_hash = match payload_hash_method {
-            HashMethod::SHA512 => {
-                let mut hasher = Sha512::new();
-                hasher.input(&payload);
-                hasher.result().to_vec()
-            }
-        };
+        let payload_hash = hash_payload(&payload_hash_method, &payload);
 
         let header = TransactionHeader {
             batcher_public_key,
@@ -742,6 +827,62 @@ mod tests {
         assert_eq!(BYTES2.to_vec(), transaction.payload());
     }
 
+    fn build_verifiable_pair(signer: &HashSigner) -> TransactionPair {
+        TransactionBuilder::new()
+            .with_family_name(FAMILY_NAME.to_string())
+            .with_family_version(FAMILY_VERSION.to_string())
+            .with_inputs(vec![hex::decode(KEY4).unwrap()])
+            .with_outputs(vec![hex::decode(KEY6).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(BYTES2.to_vec())
+            .build_pair(signer)
+            .unwrap()
+    }
+
+    #[test]
+    fn transaction_verify() {
+        let signer = HashSigner::new();
+        let pair = build_verifiable_pair(&signer);
+
+        assert!(pair.verify(&signer).is_ok());
+    }
+
+    #[test]
+    fn transaction_verify_invalid_signature() {
+        let signer = HashSigner::new();
+        let (transaction, _) = build_verifiable_pair(&signer).take();
+        let pair = Transaction::new(
+            transaction.header().to_vec(),
+            hex::encode(SIGNATURE1),
+            transaction.payload().to_vec(),
+        )
+        .into_pair()
+        .unwrap();
+
+        match pair.verify(&signer) {
+            Err(TransactionVerificationError::InvalidSignature(_)) => (),
+            res => panic!("Expected InvalidSignature, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn transaction_verify_invalid_payload_hash() {
+        let signer = HashSigner::new();
+        let (transaction, _) = build_verifiable_pair(&signer).take();
+        let pair = Transaction::new(
+            transaction.header().to_vec(),
+            transaction.header_signature().to_string(),
+            BYTES1.to_vec(),
+        )
+        .into_pair()
+        .unwrap();
+
+        match pair.verify(&signer) {
+            Err(TransactionVerificationError::InvalidPayloadHash(_)) => (),
+            res => panic!("Expected InvalidPayloadHash, got {:?}", res),
+        }
+    }
+
     #[cfg(feature = "sawtooth-compat")]
     #[test]
     fn transaction_sawtooth10_compatibility() {
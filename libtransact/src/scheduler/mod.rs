@@ -163,6 +163,19 @@ pub trait Scheduler {
         callback: Box<Fn(SchedulerError) + Send>,
     ) -> Result<(), SchedulerError>;
 
+    /// Sets a callback which checks whether the transaction with the given ID has already been
+    /// committed, such as in an earlier block. A dependency on a committed transaction is
+    /// satisfied; otherwise, a transaction may only depend on transactions added to the scheduler
+    /// before it, either in an earlier batch or earlier in the same batch.
+    fn set_committed_check(
+        &mut self,
+        _check: Box<dyn Fn(&str) -> bool + Send>,
+    ) -> Result<(), SchedulerError> {
+        Err(SchedulerError::Internal(
+            "scheduler does not support checking for committed transactions".into(),
+        ))
+    }
+
     /// Adds a BatchPair to the scheduler.
     fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError>;
 
@@ -217,16 +230,25 @@ fn default_error_callback(error: SchedulerError) {
     error!("No error callback set; SchedulerError: {}", error);
 }
 
+fn default_committed_check(_transaction_id: &str) -> bool {
+    false
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::context::manager::ContextManagerError;
     use crate::context::ContextLifecycle;
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, Transaction, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
     use crate::workload::xo::XoBatchWorkload;
     use crate::workload::BatchWorkload;
 
+    use std::sync::mpsc;
     use std::sync::{Arc, Condvar, Mutex};
     use std::thread;
+    use std::time::Duration;
 
     pub fn valid_result_from_batch(batch: BatchPair) -> Option<BatchExecutionResult> {
         let results = batch
@@ -292,14 +314,243 @@ mod tests {
         fn get_transaction_receipt(
             &self,
             _context_id: &ContextId,
-            _transaction_id: &str,
+            transaction_id: &str,
         ) -> Result<TransactionReceipt, ContextManagerError> {
-            unimplemented!()
+            Ok(TransactionReceipt {
+                state_changes: vec![],
+                events: vec![],
+                data: vec![],
+                transaction_id: transaction_id.into(),
+            })
         }
 
         fn drop_context(&mut self, _context_id: ContextId) {}
     }
 
+    /// A `ContextLifecycle` which creates a new context ID for every call to
+    /// `create_context` and records the base contexts it was given, as well
+    /// as the contexts which have been dropped.
+    #[derive(Clone)]
+    pub struct RecordingContextLifecycle {
+        created: Arc<Mutex<Vec<(ContextId, Vec<ContextId>)>>>,
+        dropped: Arc<Mutex<Vec<ContextId>>>,
+    }
+
+    impl RecordingContextLifecycle {
+        pub fn new() -> Self {
+            RecordingContextLifecycle {
+                created: Arc::new(Mutex::new(vec![])),
+                dropped: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        pub fn base_contexts(&self, context_id: &ContextId) -> Vec<ContextId> {
+            self.created
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(id, _)| id == context_id)
+                .map(|(_, bases)| bases.clone())
+                .expect("Context was not created")
+        }
+
+        pub fn dropped(&self) -> Vec<ContextId> {
+            self.dropped.lock().unwrap().clone()
+        }
+    }
+
+    impl ContextLifecycle for RecordingContextLifecycle {
+        fn create_context(
+            &mut self,
+            dependent_contexts: &[ContextId],
+            _state_id: &str,
+        ) -> ContextId {
+            let mut created = self.created.lock().unwrap();
+            let mut context_id = [0u8; 16];
+            context_id[15] = created.len() as u8 + 1;
+            created.push((context_id, dependent_contexts.to_vec()));
+            context_id
+        }
+
+        fn get_transaction_receipt(
+            &self,
+            _context_id: &ContextId,
+            transaction_id: &str,
+        ) -> Result<TransactionReceipt, ContextManagerError> {
+            Ok(TransactionReceipt {
+                state_changes: vec![],
+                events: vec![],
+                data: vec![],
+                transaction_id: transaction_id.into(),
+            })
+        }
+
+        fn drop_context(&mut self, context_id: ContextId) {
+            self.dropped.lock().unwrap().push(context_id);
+        }
+    }
+
+    /// Asserts that every context created by the scheduler has been dropped
+    /// exactly once.
+    pub fn assert_all_contexts_dropped(context_lifecycle: &RecordingContextLifecycle) {
+        let mut created: Vec<ContextId> = context_lifecycle
+            .created
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(context_id, _)| *context_id)
+            .collect();
+        let mut dropped = context_lifecycle.dropped.lock().unwrap().clone();
+        created.sort();
+        dropped.sort();
+        assert!(!created.is_empty());
+        assert_eq!(created, dropped);
+    }
+
+    /// Builds a transaction with the given hex addresses as its inputs and
+    /// outputs, which depends on the transactions with the given IDs.
+    pub fn make_transaction(
+        inputs: &[&str],
+        outputs: &[&str],
+        dependencies: &[&str],
+        nonce: &str,
+    ) -> Transaction {
+        TransactionBuilder::new()
+            .with_batcher_public_key(vec![0u8, 0u8, 0u8, 0u8])
+            .with_family_name("test".into())
+            .with_family_version("1.0".into())
+            .with_inputs(inputs.iter().map(|a| hex::decode(a).unwrap()).collect())
+            .with_outputs(outputs.iter().map(|a| hex::decode(a).unwrap()).collect())
+            .with_dependencies(
+                dependencies
+                    .iter()
+                    .map(|id| hex::decode(id).unwrap())
+                    .collect(),
+            )
+            .with_nonce(nonce.as_bytes().to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(vec![])
+            .build(&HashSigner::new())
+            .expect("Failed to build transaction")
+    }
+
+    pub fn make_batch(transactions: Vec<Transaction>) -> BatchPair {
+        BatchBuilder::new()
+            .with_transactions(transactions)
+            .build_pair(&HashSigner::new())
+            .expect("Failed to build batch")
+    }
+
+    /// Forwards all tasks from the scheduler's task iterator to the returned
+    /// receiver, so tests can wait for tasks with a timeout.
+    pub fn forward_tasks(scheduler: &mut Scheduler) -> mpsc::Receiver<ExecutionTask> {
+        let task_iterator = scheduler
+            .take_task_iterator()
+            .expect("Failed to get task iterator");
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for task in task_iterator {
+                if tx.send(task).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    pub fn next_task(tasks: &mpsc::Receiver<ExecutionTask>) -> Option<ExecutionTask> {
+        tasks.recv_timeout(Duration::from_millis(200)).ok()
+    }
+
+    /// Forwards all results from the scheduler's result callback to the
+    /// returned receiver.
+    pub fn forward_results(
+        scheduler: &mut Scheduler,
+    ) -> mpsc::Receiver<Option<BatchExecutionResult>> {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        scheduler
+            .set_result_callback(Box::new(move |result| {
+                tx.lock()
+                    .unwrap()
+                    .send(result)
+                    .expect("Failed to send result");
+            }))
+            .expect("Failed to set result callback");
+        rx
+    }
+
+    pub fn next_result(
+        results: &mpsc::Receiver<Option<BatchExecutionResult>>,
+    ) -> BatchExecutionResult {
+        results
+            .recv_timeout(Duration::from_secs(1))
+            .expect("Failed to receive result")
+            .expect("Result was None")
+    }
+
+    fn assert_invalid(result: &TransactionExecutionResult, error_message: &str) {
+        match result {
+            TransactionExecutionResult::Invalid(invalid) => {
+                assert_eq!(invalid.error_message, error_message)
+            }
+            _ => panic!("Transaction should be invalid: {:?}", result),
+        }
+    }
+
+    /// Executes a valid batch, an invalid batch and another valid batch, and
+    /// waits for all of their results. The caller is expected to shut down the
+    /// scheduler afterwards and check that the contexts were dropped.
+    pub fn test_scheduler_drop_contexts(scheduler: &mut Scheduler) {
+        let results = forward_results(scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["aa00"], &["aa00"], &[], "2");
+        let txn3 = make_transaction(&["aa00"], &["aa00"], &[], "3");
+        for txn in &[&txn1, &txn2, &txn3] {
+            scheduler
+                .add_batch(make_batch(vec![(*txn).clone()]))
+                .expect("Failed to add batch");
+        }
+        scheduler.finalize().expect("Failed to finalize");
+
+        let tasks = forward_tasks(scheduler);
+        for _ in 0..3 {
+            let task = next_task(&tasks).expect("Task not available");
+            let transaction_id = task.pair().transaction().header_signature().to_string();
+            if transaction_id == txn2.header_signature() {
+                notifier.notify(ExecutionTaskCompletionNotification::Invalid(
+                    *task.context_id(),
+                    InvalidTransactionResult {
+                        transaction_id,
+                        error_message: "invalid".into(),
+                        error_data: vec![],
+                    },
+                ));
+            } else {
+                notifier.notify(ExecutionTaskCompletionNotification::Valid(
+                    *task.context_id(),
+                    transaction_id,
+                ));
+            }
+        }
+
+        for (index, result) in (0..3).map(|_| next_result(&results)).enumerate() {
+            match (index, &result.results[..]) {
+                (1, [invalid]) => assert_invalid(invalid, "invalid"),
+                (_, [TransactionExecutionResult::Valid(_)]) => (),
+                _ => panic!("Unexpected results: {:?}", result.results),
+            }
+        }
+        assert!(results
+            .recv_timeout(Duration::from_secs(1))
+            .expect("Failed to receive final result")
+            .is_none());
+    }
+
     pub fn test_scheduler(scheduler: &mut Scheduler) {
         let mut workload = XoBatchWorkload::new_with_seed(5);
         scheduler
@@ -391,4 +642,161 @@ mod tests {
             inner = cvar.wait(inner).unwrap();
         }
     }
+
+    /// Tests that a transaction which depends on a transaction unknown to the
+    /// scheduler is invalid, without being executed.
+    pub fn test_scheduler_missing_dependency(scheduler: &mut Scheduler) {
+        let results = forward_results(scheduler);
+        let tasks = forward_tasks(scheduler);
+
+        let missing_id = "ab".repeat(64);
+        let txn = make_transaction(&["aa00"], &["aa00"], &[&missing_id], "1");
+        scheduler
+            .add_batch(make_batch(vec![txn]))
+            .expect("Failed to add batch");
+
+        let result = next_result(&results);
+        assert_invalid(
+            &result.results[0],
+            &format!("dependency {} is missing", missing_id),
+        );
+        assert!(next_task(&tasks).is_none());
+    }
+
+    /// Tests that batch results are returned in the order the batches were
+    /// added: a transaction may depend on a transaction
+    /// from an earlier batch or earlier in the same batch, but a dependency on
+    /// a transaction in a later batch is missing.
+    pub fn test_scheduler_dependency_ordering(scheduler: &mut Scheduler) {
+        let results = forward_results(scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["bb00"], &["bb00"], &[txn1.header_signature()], "2");
+        let txn3 = make_transaction(&["cc00"], &["cc00"], &[txn2.header_signature()], "3");
+        let txn4 = make_transaction(&["dd00"], &["dd00"], &[], "4");
+        let txn5 = make_transaction(&["ee00"], &["ee00"], &[txn4.header_signature()], "5");
+        let batch1 = make_batch(vec![txn1.clone(), txn2.clone()]);
+        let batch2 = make_batch(vec![txn3.clone()]);
+        let batch3 = make_batch(vec![txn5.clone()]);
+        let batch4 = make_batch(vec![txn4.clone()]);
+        for batch in &[&batch1, &batch2, &batch3, &batch4] {
+            scheduler
+                .add_batch((*batch).clone())
+                .expect("Failed to add batch");
+        }
+
+        // Only start taking tasks once all batches have been added
+        let tasks = forward_tasks(scheduler);
+
+        let mut executed = vec![];
+        while let Some(task) = next_task(&tasks) {
+            let transaction_id = task.pair().transaction().header_signature().to_string();
+            executed.push(transaction_id.clone());
+            notifier.notify(ExecutionTaskCompletionNotification::Valid(
+                *task.context_id(),
+                transaction_id,
+            ));
+        }
+        // Non-conflicting transactions may be executed in any order by a parallel scheduler
+        let mut expected = vec![
+            txn1.header_signature(),
+            txn2.header_signature(),
+            txn3.header_signature(),
+            txn4.header_signature(),
+        ];
+        executed.sort();
+        expected.sort();
+        assert_eq!(executed, expected);
+
+        let result = next_result(&results);
+        assert_eq!(result.batch, batch1);
+        assert_eq!(result.results.len(), 2);
+        assert_eq!(next_result(&results).batch, batch2);
+
+        let result = next_result(&results);
+        assert_eq!(result.batch, batch3);
+        assert_invalid(
+            &result.results[0],
+            &format!("dependency {} is missing", txn4.header_signature()),
+        );
+
+        let result = next_result(&results);
+        assert_eq!(result.batch, batch4);
+        match &result.results[..] {
+            [TransactionExecutionResult::Valid(_)] => (),
+            _ => panic!("Unexpected results: {:?}", result.results),
+        }
+    }
+
+    /// Tests that a transaction which depends on a transaction unknown to the
+    /// scheduler is executed if the scheduler's committed check reports the
+    /// dependency as committed.
+    pub fn test_scheduler_committed_dependency(scheduler: &mut Scheduler) {
+        let results = forward_results(scheduler);
+        let tasks = forward_tasks(scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let committed_id = "ab".repeat(64);
+        let check_id = committed_id.clone();
+        scheduler
+            .set_committed_check(Box::new(move |transaction_id| transaction_id == check_id))
+            .expect("Failed to set committed check");
+
+        let txn = make_transaction(&["aa00"], &["aa00"], &[&committed_id], "1");
+        scheduler
+            .add_batch(make_batch(vec![txn.clone()]))
+            .expect("Failed to add batch");
+
+        let task = next_task(&tasks).expect("Task not available");
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            *task.context_id(),
+            txn.header_signature().into(),
+        ));
+        match &next_result(&results).results[..] {
+            [TransactionExecutionResult::Valid(_)] => (),
+            results => panic!("Unexpected results: {:?}", results),
+        }
+    }
+
+    /// Tests that a transaction which depends on an invalid transaction is
+    /// invalid.
+    pub fn test_scheduler_invalid_dependency(scheduler: &mut Scheduler) {
+        let results = forward_results(scheduler);
+        let tasks = forward_tasks(scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["bb00"], &["bb00"], &[txn1.header_signature()], "2");
+        scheduler
+            .add_batch(make_batch(vec![txn1.clone()]))
+            .expect("Failed to add batch");
+        scheduler
+            .add_batch(make_batch(vec![txn2.clone()]))
+            .expect("Failed to add batch");
+
+        let task = next_task(&tasks).expect("First task not available");
+        notifier.notify(ExecutionTaskCompletionNotification::Invalid(
+            *task.context_id(),
+            InvalidTransactionResult {
+                transaction_id: txn1.header_signature().into(),
+                error_message: "invalid".into(),
+                error_data: vec![],
+            },
+        ));
+        assert_invalid(&next_result(&results).results[0], "invalid");
+
+        let result = next_result(&results);
+        assert_invalid(
+            &result.results[0],
+            &format!("dependency {} is invalid", txn1.header_signature()),
+        );
+        assert!(next_task(&tasks).is_none());
+    }
 }
@@ -189,6 +189,13 @@ impl Scheduler for MultiScheduler {
         Ok(())
     }
 
+    fn set_committed_check(
+        &mut self,
+        check: Box<dyn Fn(&str) -> bool + Send>,
+    ) -> Result<(), SchedulerError> {
+        self.shared_lock.lock()?.set_committed_check(check)
+    }
+
     fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError> {
         let mut shared = self.shared_lock.lock()?;
         if shared.finalized() {
@@ -24,6 +24,7 @@ use crate::scheduler::{
 };
 
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 /// Stores all MultiScheduler data which is shared between threads.
 pub struct MultiSchedulerShared {
@@ -69,6 +70,31 @@ impl MultiSchedulerShared {
         self.error_callback = callback;
     }
 
+    /// Sets the committed check of every sub-scheduler, which all share the given check.
+    pub fn set_committed_check(
+        &mut self,
+        check: Box<dyn Fn(&str) -> bool + Send>,
+    ) -> Result<(), SchedulerError> {
+        let check = Arc::new(Mutex::new(check));
+        for (i, scheduler) in self.schedulers.iter_mut().enumerate() {
+            let check = check.clone();
+            scheduler
+                .set_committed_check(Box::new(move |transaction_id| {
+                    check
+                        .lock()
+                        .map(|check| check(transaction_id))
+                        .unwrap_or(false)
+                }))
+                .map_err(|err| {
+                    SchedulerError::Internal(format!(
+                        "failed to set committed check for sub-scheduler {}: {}",
+                        i, err
+                    ))
+                })?;
+        }
+        Ok(())
+    }
+
     pub fn batch_already_pending(&self, batch: &BatchPair) -> bool {
         self.pending_results.contains_key(batch)
     }
@@ -0,0 +1,1094 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Implementation of core scheduler thread.
+
+use crate::context::manager::ContextManagerError;
+use crate::context::{ContextId, ContextLifecycle};
+use crate::protocol::batch::BatchPair;
+use crate::protocol::receipt::TransactionReceipt;
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::BatchExecutionResult;
+use crate::scheduler::ExecutionTask;
+use crate::scheduler::ExecutionTaskCompletionNotification;
+use crate::scheduler::InvalidTransactionResult;
+use crate::scheduler::SchedulerError;
+use crate::scheduler::TransactionExecutionResult;
+
+use hex;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::error::Error;
+use std::sync::mpsc::{Receiver, SendError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::shared::Shared;
+use super::tree::RadixTree;
+
+/// An enum of messages which can be sent to the SchedulerCore via a
+/// `Sender<CoreMessage>`.
+pub enum CoreMessage {
+    /// An indicator to the scheduler that a batch has been added.
+    BatchAdded,
+
+    /// An indicator that an execution task has been completed. If the
+    /// notification is for a valid transaction, then the relevant data will be
+    /// contained in its context; for an invalid transaction, the error
+    /// information is within the notification itself.
+    ExecutionResult(ExecutionTaskCompletionNotification),
+
+    /// An indicator to the scheduler that the executor is ready to receive an
+    /// ExecuteTask message.
+    Next,
+
+    /// An indicator to the `SchedulerCore` thread that the scheduler has been finalized
+    Finalized,
+
+    /// An indicator to the `SchedulerCore` thread that it should exit its
+    /// loop.
+    Shutdown,
+}
+
+#[derive(Debug)]
+enum CoreError {
+    ExecutionSend(Box<SendError<ExecutionTask>>),
+    ContextManager(Box<ContextManagerError>),
+    Internal(String),
+}
+
+impl std::error::Error for CoreError {
+    fn description(&self) -> &str {
+        match *self {
+            CoreError::ExecutionSend(ref err) => err.description(),
+            CoreError::ContextManager(ref err) => err.description(),
+            CoreError::Internal(ref err) => err,
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            CoreError::ExecutionSend(ref err) => Some(err),
+            CoreError::ContextManager(ref err) => Some(err),
+            CoreError::Internal(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CoreError::ExecutionSend(ref err) => write!(
+                f,
+                "failed to send transaction to executor: {}",
+                err.description()
+            ),
+            CoreError::ContextManager(ref err) => {
+                write!(f, "call to ContextManager failed: {}", err.description())
+            }
+            CoreError::Internal(ref err) => write!(f, "internal error occurred: {}", err),
+        }
+    }
+}
+
+impl From<SendError<ExecutionTask>> for CoreError {
+    fn from(error: SendError<ExecutionTask>) -> CoreError {
+        CoreError::ExecutionSend(Box::new(error))
+    }
+}
+
+impl From<ContextManagerError> for CoreError {
+    fn from(error: ContextManagerError) -> CoreError {
+        CoreError::ContextManager(Box::new(error))
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, Shared>>> for CoreError {
+    fn from(error: std::sync::PoisonError<std::sync::MutexGuard<'_, Shared>>) -> CoreError {
+        CoreError::Internal(format!("scheduler shared lock is poisoned: {}", error))
+    }
+}
+
+/// The transactions which have read from or written to an address, stored at
+/// that address in the conflict tree.
+#[derive(Clone, Default)]
+struct AddressAccess {
+    readers: Vec<u64>,
+    writers: Vec<u64>,
+}
+
+enum TxnStatus {
+    /// The transaction is waiting for the transactions it conflicts with.
+    Pending(TransactionPair),
+
+    /// The transaction has been sent for execution in the given context.
+    Executing(ContextId),
+
+    /// The transaction executed successfully in the given context; the receipt
+    /// is held until the containing batch's result is sent.
+    Valid {
+        context_id: ContextId,
+        receipt: Option<TransactionReceipt>,
+    },
+}
+
+struct ScheduledTxn {
+    transaction_id: String,
+    batch_seq: u64,
+    /// The earlier transactions which this transaction conflicts with,
+    /// including the transactions it depends on.
+    conflicts: BTreeSet<u64>,
+    /// The transactions which this transaction depends on, with their IDs.
+    dependencies: Vec<(u64, String)>,
+    /// The addresses the transaction may read from.
+    inputs: Vec<String>,
+    /// The addresses the transaction may write to.
+    outputs: Vec<String>,
+    status: TxnStatus,
+    /// Whether every transaction in the containing batch has executed
+    /// successfully.
+    batch_complete: bool,
+}
+
+struct ScheduledBatch {
+    seq: u64,
+    batch: BatchPair,
+    txn_seqs: Vec<u64>,
+    results: Option<Vec<TransactionExecutionResult>>,
+    /// The contexts of the batch's transactions to be dropped once the
+    /// result of the invalid batch has been sent.
+    invalid_contexts: Vec<ContextId>,
+}
+
+pub struct SchedulerCore {
+    /// The data shared between this core thread and the thread which owns
+    /// `ParallelScheduler`.
+    shared_lock: Arc<Mutex<Shared>>,
+
+    /// The receiver for all messages sent to the core thread.
+    rx: Receiver<CoreMessage>,
+
+    /// The sender to be used to send an ExecutionTask to the iterator after
+    /// it requested one with CoreMessage::Next.
+    execution_tx: Sender<ExecutionTask>,
+
+    /// Indicates that next() has been called on the SchedulerExecutionInterface
+    /// and is waiting for an ExecutionTask to be sent.
+    next_ready: bool,
+
+    /// The batches which have been taken from the unscheduled queue, in the
+    /// order they were added, whose results have not been sent yet.
+    batches: VecDeque<ScheduledBatch>,
+
+    /// All transactions of batches that have been scheduled and were not
+    /// invalidated, keyed by the order in which they were scheduled, until
+    /// they are released.
+    txns: BTreeMap<u64, ScheduledTxn>,
+
+    /// The IDs of all transactions which were released after their batches
+    /// executed successfully.
+    released_txn_ids: HashSet<String>,
+
+    /// The IDs of all transactions from batches which were invalidated.
+    invalid_txn_ids: HashSet<String>,
+
+    /// The IDs of transactions which were executing when their batch was
+    /// invalidated; their results will be ignored.
+    abandoned_txns: HashSet<String>,
+
+    /// The transactions of valid batches whose results have been sent, but
+    /// whose contexts have not been released yet.
+    sent_txns: Vec<u64>,
+
+    /// Indicates that batch results have been sent since contexts were last
+    /// released.
+    release_needed: bool,
+
+    /// The sequence number to be given to the next scheduled batch or
+    /// transaction.
+    next_seq: u64,
+
+    /// Indicates that the final `None` result has been sent.
+    done: bool,
+
+    /// The interface for context creation and deletion.
+    context_lifecycle: Box<ContextLifecycle>,
+
+    /// The state root upon which transactions in this scheduler will be
+    /// executed.
+    state_id: String,
+}
+
+impl SchedulerCore {
+    pub fn new(
+        shared_lock: Arc<Mutex<Shared>>,
+        rx: Receiver<CoreMessage>,
+        execution_tx: Sender<ExecutionTask>,
+        context_lifecycle: Box<ContextLifecycle>,
+        state_id: String,
+    ) -> Self {
+        SchedulerCore {
+            shared_lock,
+            rx,
+            execution_tx,
+            next_ready: false,
+            batches: VecDeque::new(),
+            txns: BTreeMap::new(),
+            released_txn_ids: HashSet::new(),
+            invalid_txn_ids: HashSet::new(),
+            abandoned_txns: HashSet::new(),
+            sent_txns: vec![],
+            release_needed: false,
+            next_seq: 0,
+            done: false,
+            context_lifecycle,
+            state_id,
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    /// Takes the next batch off of the unscheduled queue and records the
+    /// conflicts of each of its transactions with all previously scheduled
+    /// transactions. Returns false if there are no unscheduled batches.
+    fn schedule_batch(&mut self, tree: &RadixTree<AddressAccess>) -> Result<bool, CoreError> {
+        let batch = match self.shared_lock.lock()?.pop_unscheduled_batch() {
+            Some(batch) => batch,
+            None => return Ok(false),
+        };
+
+        let batch_seq = self.next_seq();
+        let mut txn_seqs = vec![];
+        let mut scheduled_txns = vec![];
+        let mut invalid_result = None;
+
+        for transaction in batch.batch().transactions() {
+            let transaction_id = transaction.header_signature().to_string();
+            let pair = match transaction.clone().into_pair() {
+                Ok(pair) => pair,
+                Err(err) => {
+                    invalid_result = Some(InvalidTransactionResult {
+                        transaction_id,
+                        error_message: format!("ill-formed transaction: {}", err),
+                        error_data: vec![],
+                    });
+                    break;
+                }
+            };
+
+            let mut dependencies = vec![];
+            let mut dependency_error = None;
+            for dependency in pair.header().dependencies().iter().map(hex::encode) {
+                if self.invalid_txn_ids.contains(&dependency) {
+                    dependency_error = Some(format!("dependency {} is invalid", dependency));
+                    break;
+                }
+                // A released transaction has executed, and any transaction accessing the
+                // addresses it wrote to conflicts with a later transaction which covers them
+                if self.released_txn_ids.contains(&dependency) {
+                    continue;
+                }
+                let dependency_seq = self.find_txn(&dependency).or_else(|| {
+                    scheduled_txns
+                        .iter()
+                        .find(|(_, txn): &&(u64, ScheduledTxn)| txn.transaction_id == dependency)
+                        .map(|(seq, _)| *seq)
+                });
+                match dependency_seq {
+                    Some(dependency_seq) => dependencies.push((dependency_seq, dependency)),
+                    None if self.shared_lock.lock()?.committed_check()(&dependency) => (),
+                    None => {
+                        dependency_error = Some(format!("dependency {} is missing", dependency));
+                        break;
+                    }
+                }
+            }
+            if let Some(error_message) = dependency_error {
+                invalid_result = Some(InvalidTransactionResult {
+                    transaction_id,
+                    error_message,
+                    error_data: vec![],
+                });
+                break;
+            }
+
+            let seq = self.next_seq();
+            let inputs: Vec<String> = pair.header().inputs().iter().map(hex::encode).collect();
+            let outputs: Vec<String> = pair.header().outputs().iter().map(hex::encode).collect();
+
+            // A transaction conflicts with any earlier transaction that wrote to an address it
+            // reads from or writes to, and with any earlier transaction that read from an
+            // address it writes to.
+            let mut conflicts: BTreeSet<u64> = dependencies.iter().map(|(seq, _)| *seq).collect();
+            for address in &inputs {
+                for access in accesses(tree, address) {
+                    conflicts.extend(access.writers);
+                }
+            }
+            for address in &outputs {
+                for access in accesses(tree, address) {
+                    conflicts.extend(access.readers);
+                    conflicts.extend(access.writers);
+                }
+            }
+
+            for address in &inputs {
+                tree.update(
+                    address,
+                    &|data: Option<AddressAccess>| {
+                        let mut access = data.unwrap_or_default();
+                        access.readers.push(seq);
+                        Some(access)
+                    },
+                    false,
+                );
+            }
+            for address in &outputs {
+                tree.update(
+                    address,
+                    &|data: Option<AddressAccess>| {
+                        let mut access = data.unwrap_or_default();
+                        access.writers.push(seq);
+                        Some(access)
+                    },
+                    false,
+                );
+            }
+
+            txn_seqs.push(seq);
+            scheduled_txns.push((
+                seq,
+                ScheduledTxn {
+                    transaction_id,
+                    batch_seq,
+                    conflicts,
+                    dependencies,
+                    inputs,
+                    outputs,
+                    status: TxnStatus::Pending(pair),
+                    batch_complete: false,
+                },
+            ));
+        }
+
+        self.batches.push_back(ScheduledBatch {
+            seq: batch_seq,
+            batch,
+            txn_seqs,
+            results: None,
+            invalid_contexts: vec![],
+        });
+
+        match invalid_result {
+            Some(invalid_result) => {
+                // None of the batch's transactions were added to the set of scheduled
+                // transactions, so the accesses recorded in the tree for them are removed.
+                for (seq, txn) in &scheduled_txns {
+                    for address in txn.inputs.iter().chain(txn.outputs.iter()) {
+                        remove_access(tree, address, *seq);
+                    }
+                }
+                self.invalidate_batch(tree, batch_seq, invalid_result)?;
+                self.send_batch_results()?;
+            }
+            None => {
+                self.txns.extend(scheduled_txns);
+                // A batch without transactions is complete as soon as it is scheduled
+                self.complete_batch_if_valid(batch_seq)?;
+                self.send_batch_results()?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the first pending transaction, in scheduling order, whose
+    /// conflicting transactions have all executed successfully and belong to
+    /// either the same batch or a batch which has completed.
+    fn find_ready_txn(&self) -> Option<u64> {
+        self.txns
+            .iter()
+            .filter(|(_, txn)| match txn.status {
+                TxnStatus::Pending(_) => true,
+                _ => false,
+            })
+            .find(|(_, txn)| {
+                txn.conflicts.iter().all(|seq| match self.txns.get(seq) {
+                    Some(conflict) => match conflict.status {
+                        TxnStatus::Valid { .. } => {
+                            conflict.batch_seq == txn.batch_seq || conflict.batch_complete
+                        }
+                        _ => false,
+                    },
+                    // The conflicting transaction's batch was invalidated
+                    None => true,
+                })
+            })
+            .map(|(seq, _)| *seq)
+    }
+
+    fn try_schedule_next(&mut self, tree: &RadixTree<AddressAccess>) -> Result<(), CoreError> {
+        if !self.next_ready {
+            return Ok(());
+        }
+
+        let seq = loop {
+            if let Some(seq) = self.find_ready_txn() {
+                break seq;
+            }
+
+            // Only take a new batch when nothing else can be executed, so that batches remain
+            // cancellable for as long as possible.
+            if !self.schedule_batch(tree)? {
+                self.send_done_if_finished()?;
+                return Ok(());
+            }
+        };
+
+        let txn = self.txns.get(&seq).ok_or_else(|| {
+            CoreError::Internal(format!("scheduled transaction {} does not exist", seq))
+        })?;
+
+        // Conflicting transactions that wrote to the same address depend on each other, so
+        // ordering the base contexts from latest to earliest ensures the latest value is read.
+        let base_contexts: Vec<ContextId> = txn
+            .conflicts
+            .iter()
+            .rev()
+            .filter_map(|conflict_seq| match self.txns.get(conflict_seq) {
+                Some(ScheduledTxn {
+                    status: TxnStatus::Valid { context_id, .. },
+                    ..
+                }) => Some(*context_id),
+                _ => None,
+            })
+            .collect();
+
+        let context_id = self
+            .context_lifecycle
+            .create_context(&base_contexts, &self.state_id);
+
+        let txn = self.txns.get_mut(&seq).ok_or_else(|| {
+            CoreError::Internal(format!("scheduled transaction {} does not exist", seq))
+        })?;
+        let transaction_pair =
+            match std::mem::replace(&mut txn.status, TxnStatus::Executing(context_id)) {
+                TxnStatus::Pending(pair) => pair,
+                _ => {
+                    return Err(CoreError::Internal(format!(
+                        "transaction {} is not pending",
+                        txn.transaction_id
+                    )))
+                }
+            };
+
+        self.execution_tx
+            .send(ExecutionTask::new(transaction_pair, context_id))?;
+        self.next_ready = false;
+
+        Ok(())
+    }
+
+    fn find_txn(&self, transaction_id: &str) -> Option<u64> {
+        self.txns
+            .iter()
+            .find(|(_, txn)| txn.transaction_id == transaction_id)
+            .map(|(seq, _)| *seq)
+    }
+
+    /// Invalidates the batches of all pending transactions which depend on a
+    /// transaction whose batch has been invalidated.
+    fn invalidate_dependent_batches(
+        &mut self,
+        tree: &RadixTree<AddressAccess>,
+    ) -> Result<(), CoreError> {
+        loop {
+            let invalid = self.txns.values().find_map(|txn| {
+                txn.dependencies
+                    .iter()
+                    .find(|(_, dependency)| self.invalid_txn_ids.contains(dependency))
+                    .map(|(_, dependency)| {
+                        (
+                            txn.batch_seq,
+                            InvalidTransactionResult {
+                                transaction_id: txn.transaction_id.clone(),
+                                error_message: format!("dependency {} is invalid", dependency),
+                                error_data: vec![],
+                            },
+                        )
+                    })
+            });
+
+            match invalid {
+                Some((batch_seq, invalid_result)) => {
+                    self.invalidate_batch(tree, batch_seq, invalid_result)?
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn find_executing_txn(&self, transaction_id: &str) -> Option<u64> {
+        self.txns
+            .iter()
+            .find(|(_, txn)| match txn.status {
+                TxnStatus::Executing(_) => txn.transaction_id == transaction_id,
+                _ => false,
+            })
+            .map(|(seq, _)| *seq)
+    }
+
+    /// Sets the results of the given batch to invalid and removes its
+    /// transactions, along with their accesses in the tree, from the set of
+    /// scheduled transactions, so that no other transaction will be executed
+    /// on top of them.
+    fn invalidate_batch(
+        &mut self,
+        tree: &RadixTree<AddressAccess>,
+        batch_seq: u64,
+        invalid_result: InvalidTransactionResult,
+    ) -> Result<(), CoreError> {
+        let batch = self
+            .batches
+            .iter_mut()
+            .find(|batch| batch.seq == batch_seq)
+            .ok_or_else(|| {
+                CoreError::Internal(
+                    "attemping to invalidate batch but the batch does not exist".into(),
+                )
+            })?;
+        let batch_id = batch.batch.batch().header_signature().to_string();
+
+        let results = batch
+            .batch
+            .batch()
+            .transactions()
+            .iter()
+            .map(|txn| {
+                if txn.header_signature() == invalid_result.transaction_id {
+                    TransactionExecutionResult::Invalid(invalid_result.clone())
+                } else {
+                    TransactionExecutionResult::Invalid(InvalidTransactionResult {
+                        transaction_id: txn.header_signature().into(),
+                        error_message: format!("containing batch ({}) is invalid", batch_id),
+                        error_data: vec![],
+                    })
+                }
+            })
+            .collect();
+        batch.results = Some(results);
+
+        self.invalid_txn_ids.extend(
+            batch
+                .batch
+                .batch()
+                .transactions()
+                .iter()
+                .map(|txn| txn.header_signature().to_string()),
+        );
+
+        // Transactions which are still executing keep their contexts until their results arrive
+        for seq in &batch.txn_seqs {
+            if let Some(txn) = self.txns.remove(seq) {
+                for address in txn.inputs.iter().chain(txn.outputs.iter()) {
+                    remove_access(tree, address, *seq);
+                }
+                match txn.status {
+                    TxnStatus::Executing(context_id) => {
+                        if txn.transaction_id == invalid_result.transaction_id {
+                            batch.invalid_contexts.push(context_id);
+                        } else {
+                            self.abandoned_txns.insert(txn.transaction_id);
+                        }
+                    }
+                    TxnStatus::Valid { context_id, .. } => batch.invalid_contexts.push(context_id),
+                    TxnStatus::Pending(_) => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// If every transaction in the given batch has executed successfully, sets
+    /// the batch's results.
+    fn complete_batch_if_valid(&mut self, batch_seq: u64) -> Result<(), CoreError> {
+        let batch = self
+            .batches
+            .iter_mut()
+            .find(|batch| batch.seq == batch_seq)
+            .ok_or_else(|| {
+                CoreError::Internal("attemping to complete batch but it does not exist".into())
+            })?;
+
+        let txns = &mut self.txns;
+        let all_valid = batch.txn_seqs.iter().all(|seq| match txns.get(seq) {
+            Some(ScheduledTxn {
+                status: TxnStatus::Valid { .. },
+                ..
+            }) => true,
+            _ => false,
+        });
+        if !all_valid {
+            return Ok(());
+        }
+
+        let mut results = vec![];
+        for seq in &batch.txn_seqs {
+            let txn = txns.get_mut(seq).ok_or_else(|| {
+                CoreError::Internal(format!("scheduled transaction {} does not exist", seq))
+            })?;
+            txn.batch_complete = true;
+            if let TxnStatus::Valid {
+                ref mut receipt, ..
+            } = txn.status
+            {
+                let receipt = receipt.take().ok_or_else(|| {
+                    CoreError::Internal(format!(
+                        "receipt for transaction {} already taken",
+                        txn.transaction_id
+                    ))
+                })?;
+                results.push(TransactionExecutionResult::Valid(receipt));
+            }
+        }
+        batch.results = Some(results);
+
+        Ok(())
+    }
+
+    /// Sends the results of all completed batches at the front of the queue,
+    /// so that results are always sent in the order the batches were added.
+    fn send_batch_results(&mut self) -> Result<(), CoreError> {
+        while self
+            .batches
+            .front()
+            .map(|batch| batch.results.is_some())
+            .unwrap_or(false)
+        {
+            let scheduled_batch = self.batches.pop_front().ok_or_else(|| {
+                CoreError::Internal("attempting to send batch result but no batch exists".into())
+            })?;
+
+            let batch_result = BatchExecutionResult {
+                batch: scheduled_batch.batch,
+                results: scheduled_batch.results.unwrap_or_else(Vec::new),
+            };
+
+            self.shared_lock.lock()?.result_callback()(Some(batch_result));
+
+            for context_id in scheduled_batch.invalid_contexts {
+                self.context_lifecycle.drop_context(context_id);
+            }
+
+            // The transactions of an invalid batch have already been removed
+            let txns = &self.txns;
+            self.sent_txns.extend(
+                scheduled_batch
+                    .txn_seqs
+                    .iter()
+                    .filter(|seq| txns.contains_key(seq)),
+            );
+            self.release_needed = true;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the contexts of transactions whose batch results have been sent,
+    /// once no transaction which is yet to be executed can need them as base
+    /// contexts.
+    ///
+    /// A transaction's context is no longer needed once every address it may
+    /// write to is covered by an output of a later transaction whose batch
+    /// result has also been sent, since any transaction accessing the address
+    /// will conflict with the later transaction, whose context is built on top
+    /// of the earlier one. Once the scheduler is finalized and every batch has
+    /// been scheduled, contexts are no longer needed as soon as no pending
+    /// transaction conflicts with them.
+    fn release_contexts(&mut self, tree: &RadixTree<AddressAccess>) -> Result<(), CoreError> {
+        if !self.release_needed {
+            return Ok(());
+        }
+        self.release_needed = false;
+
+        let all_scheduled = {
+            let shared = self.shared_lock.lock()?;
+            shared.finalized() && shared.unscheduled_batches_is_empty()
+        };
+        let pending_conflicts: HashSet<u64> = if all_scheduled {
+            self.txns
+                .values()
+                .filter(|txn| match txn.status {
+                    TxnStatus::Pending(_) => true,
+                    _ => false,
+                })
+                .flat_map(|txn| txn.conflicts.iter().cloned())
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        let (released, kept): (Vec<u64>, Vec<u64>) =
+            self.sent_txns
+                .iter()
+                .partition(|seq| match self.txns.get(seq) {
+                    Some(txn) => {
+                        (all_scheduled && !pending_conflicts.contains(seq))
+                            || txn.outputs.iter().all(|output| {
+                                writers(tree, output)
+                                    .into_iter()
+                                    .any(|writer| writer > **seq && self.is_sent(writer))
+                            })
+                    }
+                    None => true,
+                });
+        self.sent_txns = kept;
+
+        // Released transactions are removed, along with their accesses in the tree, so that
+        // later transactions no longer conflict with them
+        for seq in released {
+            if let Some(txn) = self.txns.remove(&seq) {
+                for address in &txn.inputs {
+                    remove_access(tree, address, seq);
+                }
+                for address in &txn.outputs {
+                    remove_access(tree, address, seq);
+                }
+                if let TxnStatus::Valid { context_id, .. } = txn.status {
+                    self.context_lifecycle.drop_context(context_id);
+                }
+                self.released_txn_ids.insert(txn.transaction_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the result of the given transaction's batch has been
+    /// sent, and the transaction has not been released yet.
+    fn is_sent(&self, seq: u64) -> bool {
+        let first_unsent_batch = self.batches.front().map(|batch| batch.seq);
+        match self.txns.get(&seq) {
+            Some(ScheduledTxn {
+                status: TxnStatus::Valid { .. },
+                batch_seq,
+                ..
+            }) => first_unsent_batch
+                .map(|first_unsent_batch| *batch_seq < first_unsent_batch)
+                .unwrap_or(true),
+            _ => false,
+        }
+    }
+
+    /// If the scheduler is finalized and all batches have been executed, sends
+    /// a `None` result to let the calling code know that all results have
+    /// been sent.
+    fn send_done_if_finished(&mut self) -> Result<(), CoreError> {
+        if self.done || !self.batches.is_empty() {
+            return Ok(());
+        }
+
+        let shared = self.shared_lock.lock()?;
+        if shared.finalized() && shared.unscheduled_batches_is_empty() {
+            shared.result_callback()(None);
+            self.done = true;
+        }
+
+        Ok(())
+    }
+
+    fn send_scheduler_error(&mut self, error: SchedulerError) -> Result<(), CoreError> {
+        self.shared_lock.lock()?.error_callback()(error);
+        Ok(())
+    }
+
+    fn handle_notification(
+        &mut self,
+        tree: &RadixTree<AddressAccess>,
+        notification: ExecutionTaskCompletionNotification,
+    ) -> Result<(), CoreError> {
+        match notification {
+            ExecutionTaskCompletionNotification::Valid(context_id, transaction_id) => {
+                let seq = match self.find_executing_txn(&transaction_id) {
+                    Some(seq) => seq,
+                    None => {
+                        if self.abandoned_txns.remove(&transaction_id) {
+                            self.context_lifecycle.drop_context(context_id);
+                        } else {
+                            self.send_scheduler_error(SchedulerError::UnexpectedNotification(
+                                transaction_id,
+                            ))?;
+                        }
+                        return Ok(());
+                    }
+                };
+
+                let receipt = self
+                    .context_lifecycle
+                    .get_transaction_receipt(&context_id, &hex::encode(transaction_id))?;
+
+                let txn = self.txns.get_mut(&seq).ok_or_else(|| {
+                    CoreError::Internal(format!("scheduled transaction {} does not exist", seq))
+                })?;
+                txn.status = TxnStatus::Valid {
+                    context_id,
+                    receipt: Some(receipt),
+                };
+                let batch_seq = txn.batch_seq;
+
+                self.complete_batch_if_valid(batch_seq)?;
+            }
+            ExecutionTaskCompletionNotification::Invalid(context_id, result) => {
+                let seq = match self.find_executing_txn(&result.transaction_id) {
+                    Some(seq) => seq,
+                    None => {
+                        if self.abandoned_txns.remove(&result.transaction_id) {
+                            self.context_lifecycle.drop_context(context_id);
+                        } else {
+                            self.send_scheduler_error(SchedulerError::UnexpectedNotification(
+                                result.transaction_id,
+                            ))?;
+                        }
+                        return Ok(());
+                    }
+                };
+
+                let batch_seq = self
+                    .txns
+                    .get(&seq)
+                    .map(|txn| txn.batch_seq)
+                    .ok_or_else(|| {
+                        CoreError::Internal(format!("scheduled transaction {} does not exist", seq))
+                    })?;
+
+                self.invalidate_batch(tree, batch_seq, result)?;
+                self.invalidate_dependent_batches(tree)?;
+            }
+        }
+
+        self.send_batch_results()
+    }
+
+    fn run(&mut self) -> Result<(), CoreError> {
+        // The tree is not thread-safe, so it is created by the core thread itself.
+        let tree = RadixTree::new();
+
+        loop {
+            match self.rx.recv() {
+                Ok(CoreMessage::BatchAdded) => {
+                    self.try_schedule_next(&tree)?;
+                }
+                Ok(CoreMessage::ExecutionResult(task_notification)) => {
+                    self.handle_notification(&tree, task_notification)?;
+                    self.send_done_if_finished()?;
+                    self.try_schedule_next(&tree)?;
+                }
+                Ok(CoreMessage::Next) => {
+                    self.next_ready = true;
+                    self.try_schedule_next(&tree)?;
+                }
+                Ok(CoreMessage::Finalized) => {
+                    // If there are no unscheduled batches and no batch is currently executing, the
+                    // scheduler is done; send a `None` result to let the calling code know that
+                    // all results have been sent.
+                    let shared = self.shared_lock.lock()?;
+                    if self.batches.is_empty() && shared.unscheduled_batches_is_empty() {
+                        if !self.done {
+                            shared.result_callback()(None);
+                        }
+                        break;
+                    }
+                    // Once every batch has been scheduled, more contexts may be released
+                    self.release_needed = true;
+                }
+                Ok(CoreMessage::Shutdown) => {
+                    break;
+                }
+                Err(err) => {
+                    // This is expected if the other side shuts down
+                    // before this end. However, it would be more
+                    // elegant to gracefully handle it by sending a
+                    // close message across.
+                    warn!("Thread-ParallelScheduler recv failed: {}", err);
+                    break;
+                }
+            }
+
+            self.release_contexts(&tree)?;
+        }
+
+        // Release the contexts of all transactions which have not been released yet
+        let mut context_ids: Vec<ContextId> = self
+            .txns
+            .values()
+            .filter_map(|txn| match txn.status {
+                TxnStatus::Executing(context_id) | TxnStatus::Valid { context_id, .. } => {
+                    Some(context_id)
+                }
+                TxnStatus::Pending(_) => None,
+            })
+            .collect();
+        for batch in self.batches.iter_mut() {
+            context_ids.append(&mut batch.invalid_contexts);
+        }
+        for context_id in context_ids {
+            self.context_lifecycle.drop_context(context_id);
+        }
+
+        Ok(())
+    }
+
+    pub fn start(mut self) -> Result<std::thread::JoinHandle<()>, SchedulerError> {
+        thread::Builder::new()
+            .name(String::from("Thread-ParallelScheduler"))
+            .spawn(move || {
+                if let Err(err) = self.run() {
+                    // Attempt to send notification using the error callback; if that fails, just
+                    // log it.
+                    let error = SchedulerError::Internal(format!(
+                        "parallel scheduler's internal thread ended due to error: {}",
+                        err
+                    ));
+                    self.send_scheduler_error(error.clone())
+                        .unwrap_or_else(|_| error!("{}", error));
+                }
+            })
+            .map_err(|err| {
+                SchedulerError::Internal(format!(
+                    "could not build a thread for the scheduler: {}",
+                    err
+                ))
+            })
+    }
+}
+
+/// Returns the transactions which have written to an address in the tree
+/// which is a prefix of the given address, and so to the whole address range
+/// the given address covers.
+fn writers(tree: &RadixTree<AddressAccess>, address: &str) -> Vec<u64> {
+    tree.walk(address)
+        .into_iter()
+        .filter(|(node_address, _)| address.starts_with(node_address.as_str()))
+        .filter_map(|(_, data)| data)
+        .flat_map(|access| access.writers)
+        .collect()
+}
+
+/// Removes the given transaction from the accesses recorded for the given
+/// address, removing the address from the tree once it has no accesses left.
+fn remove_access(tree: &RadixTree<AddressAccess>, address: &str, seq: u64) {
+    tree.update(
+        address,
+        &|data: Option<AddressAccess>| {
+            data.map(|mut access| {
+                access.readers.retain(|reader| *reader != seq);
+                access.writers.retain(|writer| *writer != seq);
+                access
+            })
+            .filter(|access| !access.readers.is_empty() || !access.writers.is_empty())
+        },
+        false,
+    );
+    tree.remove_if_empty(address);
+}
+
+/// Returns the recorded accesses of every address in the tree which is a
+/// prefix of, or is prefixed by, the given address.
+fn accesses(tree: &RadixTree<AddressAccess>, address: &str) -> Vec<AddressAccess> {
+    tree.walk(address)
+        .into_iter()
+        .filter(|(node_address, _)| {
+            node_address.starts_with(address) || address.starts_with(node_address.as_str())
+        })
+        .filter_map(|(_, data)| data)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    use crate::scheduler::tests::{make_batch, make_transaction, MockContextLifecycle};
+
+    /// Tests that the accesses of a batch's transactions are removed from the
+    /// tree when the batch is invalidated, whether it is found to be invalid
+    /// while it is scheduled or one of its transactions is executed and
+    /// found to be invalid.
+    #[test]
+    fn test_invalid_batch_accesses_removed() {
+        let shared_lock = Arc::new(Mutex::new(Shared::new()));
+        let (_core_tx, core_rx) = mpsc::channel();
+        let (execution_tx, execution_rx) = mpsc::channel();
+        let mut core = SchedulerCore::new(
+            shared_lock.clone(),
+            core_rx,
+            execution_tx,
+            Box::new(MockContextLifecycle::new()),
+            "state0".into(),
+        );
+        let tree = RadixTree::new();
+
+        // The second transaction depends on a transaction which does not exist
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["bb00"], &["bb00"], &["abcd"], "2");
+        shared_lock
+            .lock()
+            .unwrap()
+            .add_unscheduled_batch(make_batch(vec![txn1, txn2]));
+        assert!(core
+            .schedule_batch(&tree)
+            .expect("Failed to schedule batch"));
+        assert!(core.txns.is_empty());
+        assert!(is_empty(&tree));
+
+        let txn3 = make_transaction(&["aa00"], &["cc00"], &[], "3");
+        shared_lock
+            .lock()
+            .unwrap()
+            .add_unscheduled_batch(make_batch(vec![txn3.clone()]));
+        core.next_ready = true;
+        core.try_schedule_next(&tree)
+            .expect("Failed to schedule transaction");
+        let task = execution_rx.try_recv().expect("Task not available");
+        assert!(!is_empty(&tree));
+
+        core.handle_notification(
+            &tree,
+            ExecutionTaskCompletionNotification::Invalid(
+                *task.context_id(),
+                InvalidTransactionResult {
+                    transaction_id: txn3.header_signature().into(),
+                    error_message: "invalid".into(),
+                    error_data: vec![],
+                },
+            ),
+        )
+        .expect("Failed to handle notification");
+        assert!(core.txns.is_empty());
+        assert!(is_empty(&tree));
+    }
+
+    fn is_empty(tree: &RadixTree<AddressAccess>) -> bool {
+        tree.walk("").into_iter().all(|(_, data)| data.is_none())
+    }
+}
@@ -15,4 +15,527 @@
  * -----------------------------------------------------------------------------
  */
 
+//! A `Scheduler` which schedules transactions for execution in parallel.
+//!
+//! Transactions are checked for conflicts using the addresses declared in their headers' inputs
+//! and outputs: a transaction conflicts with an earlier transaction if either one writes to an
+//! address which the other reads from or writes to, where addresses are compared by prefix.
+//! Conflicting transactions are executed in the order in which they were added, each on top of
+//! the contexts of the transactions it conflicts with, while non-conflicting transactions may be
+//! executed concurrently.  Batch results are always returned in the order the batches were added.
+
+mod core;
+mod execution;
+mod shared;
 pub mod tree;
+
+use crate::context::ContextLifecycle;
+use crate::protocol::batch::BatchPair;
+use crate::scheduler::BatchExecutionResult;
+use crate::scheduler::ExecutionTask;
+use crate::scheduler::ExecutionTaskCompletionNotifier;
+use crate::scheduler::Scheduler;
+use crate::scheduler::SchedulerError;
+
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+// If the shared lock is poisoned, report an internal error since the scheduler cannot recover.
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, shared::Shared>>> for SchedulerError {
+    fn from(
+        error: std::sync::PoisonError<std::sync::MutexGuard<'_, shared::Shared>>,
+    ) -> SchedulerError {
+        SchedulerError::Internal(format!("scheduler shared lock is poisoned: {}", error))
+    }
+}
+
+// If the core `Receiver` disconnects, report an internal error since the scheduler can't operate
+// without the core thread.
+impl From<std::sync::mpsc::SendError<core::CoreMessage>> for SchedulerError {
+    fn from(error: std::sync::mpsc::SendError<core::CoreMessage>) -> SchedulerError {
+        SchedulerError::Internal(format!("scheduler's core thread disconnected: {}", error))
+    }
+}
+
+/// A `Scheduler` implementation which schedules non-conflicting transactions
+/// for concurrent execution.
+pub struct ParallelScheduler {
+    shared_lock: Arc<Mutex<shared::Shared>>,
+    core_handle: Option<std::thread::JoinHandle<()>>,
+    core_tx: Sender<core::CoreMessage>,
+    task_iterator: Option<Box<Iterator<Item = ExecutionTask> + Send>>,
+}
+
+impl ParallelScheduler {
+    /// Returns a newly created `ParallelScheduler`.
+    pub fn new(
+        context_lifecycle: Box<ContextLifecycle>,
+        state_id: String,
+    ) -> Result<ParallelScheduler, SchedulerError> {
+        let (execution_tx, execution_rx) = mpsc::channel();
+        let (core_tx, core_rx) = mpsc::channel();
+
+        let shared_lock = Arc::new(Mutex::new(shared::Shared::new()));
+
+        // Start the thread to accept and process CoreMessage messages
+        let core_handle = core::SchedulerCore::new(
+            shared_lock.clone(),
+            core_rx,
+            execution_tx,
+            context_lifecycle,
+            state_id,
+        )
+        .start()?;
+
+        Ok(ParallelScheduler {
+            shared_lock,
+            core_handle: Some(core_handle),
+            core_tx: core_tx.clone(),
+            task_iterator: Some(Box::new(execution::ParallelExecutionTaskIterator::new(
+                core_tx,
+                execution_rx,
+            ))),
+        })
+    }
+
+    pub fn shutdown(mut self) {
+        match self.core_tx.send(core::CoreMessage::Shutdown) {
+            Ok(_) => {
+                if let Some(join_handle) = self.core_handle.take() {
+                    join_handle.join().unwrap_or_else(|err| {
+                        // This should not never happen, because the core thread should never panic
+                        error!(
+                            "failed to join scheduler thread because it panicked: {:?}",
+                            err
+                        )
+                    });
+                }
+            }
+            Err(err) => {
+                warn!("failed to send to scheduler thread during drop: {}", err);
+            }
+        }
+    }
+}
+
+impl Scheduler for ParallelScheduler {
+    fn set_result_callback(
+        &mut self,
+        callback: Box<Fn(Option<BatchExecutionResult>) + Send>,
+    ) -> Result<(), SchedulerError> {
+        self.shared_lock.lock()?.set_result_callback(callback);
+        Ok(())
+    }
+
+    fn set_error_callback(
+        &mut self,
+        callback: Box<Fn(SchedulerError) + Send>,
+    ) -> Result<(), SchedulerError> {
+        self.shared_lock.lock()?.set_error_callback(callback);
+        Ok(())
+    }
+
+    fn set_committed_check(
+        &mut self,
+        check: Box<dyn Fn(&str) -> bool + Send>,
+    ) -> Result<(), SchedulerError> {
+        self.shared_lock.lock()?.set_committed_check(check);
+        Ok(())
+    }
+
+    fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError> {
+        let mut shared = self.shared_lock.lock()?;
+
+        if shared.finalized() {
+            return Err(SchedulerError::SchedulerFinalized);
+        }
+
+        if shared.batch_already_queued(&batch) {
+            return Err(SchedulerError::DuplicateBatch(
+                batch.batch().header_signature().into(),
+            ));
+        }
+
+        shared.add_unscheduled_batch(batch);
+
+        // Notify the core that a batch has been added. Note that the batch is
+        // not sent across the channel because the batch has already been added
+        // to the unscheduled queue above, where we hold a lock; adding a batch
+        // must be exclusive with finalize.
+        self.core_tx.send(core::CoreMessage::BatchAdded)?;
+
+        Ok(())
+    }
+
+    fn cancel(&mut self) -> Result<Vec<BatchPair>, SchedulerError> {
+        Ok(self.shared_lock.lock()?.drain_unscheduled_batches())
+    }
+
+    fn finalize(&mut self) -> Result<(), SchedulerError> {
+        self.shared_lock.lock()?.set_finalized(true);
+        self.core_tx.send(core::CoreMessage::Finalized)?;
+        Ok(())
+    }
+
+    fn take_task_iterator(
+        &mut self,
+    ) -> Result<Box<dyn Iterator<Item = ExecutionTask> + Send>, SchedulerError> {
+        self.task_iterator
+            .take()
+            .ok_or(SchedulerError::NoTaskIterator)
+    }
+
+    fn new_notifier(&mut self) -> Result<Box<dyn ExecutionTaskCompletionNotifier>, SchedulerError> {
+        Ok(Box::new(
+            execution::ParallelExecutionTaskCompletionNotifier::new(self.core_tx.clone()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::tests::*;
+    use crate::scheduler::ExecutionTaskCompletionNotification;
+    use crate::scheduler::InvalidTransactionResult;
+    use crate::scheduler::TransactionExecutionResult;
+
+    use std::time::Duration;
+
+    /// This test will hang if join() fails within the scheduler.
+    #[test]
+    fn test_scheduler_thread_cleanup() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler")
+            .shutdown();
+    }
+
+    #[test]
+    fn test_parallel_scheduler() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_parallel_scheduler_cancel() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler_cancel(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    pub fn test_parallel_scheduler_flow_with_one_transaction() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler_flow_with_one_transaction(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_parallel_scheduler_missing_dependency() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler_missing_dependency(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_parallel_scheduler_dependency_ordering() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler_dependency_ordering(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_parallel_scheduler_committed_dependency() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler_committed_dependency(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_parallel_scheduler_drop_contexts() {
+        let context_lifecycle = RecordingContextLifecycle::new();
+        let mut scheduler =
+            ParallelScheduler::new(Box::new(context_lifecycle.clone()), "state0".into())
+                .expect("Failed to create scheduler");
+        test_scheduler_drop_contexts(&mut scheduler);
+        scheduler.shutdown();
+        assert_all_contexts_dropped(&context_lifecycle);
+    }
+
+    #[test]
+    fn test_parallel_scheduler_invalid_dependency() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = ParallelScheduler::new(context_lifecycle, state_id)
+            .expect("Failed to create scheduler");
+        test_scheduler_invalid_dependency(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    /// Tests that transactions which do not conflict are all made available
+    /// for execution without waiting for any results.
+    #[test]
+    fn test_parallel_scheduler_non_conflicting_transactions() {
+        let context_lifecycle = RecordingContextLifecycle::new();
+        let mut scheduler =
+            ParallelScheduler::new(Box::new(context_lifecycle.clone()), "state0".into())
+                .expect("Failed to create scheduler");
+        let tasks = forward_tasks(&mut scheduler);
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["bb00"], &["bb00"], &[], "2");
+        scheduler
+            .add_batch(make_batch(vec![txn1.clone()]))
+            .expect("Failed to add batch");
+        scheduler
+            .add_batch(make_batch(vec![txn2.clone()]))
+            .expect("Failed to add batch");
+
+        let task1 = next_task(&tasks).expect("First task not available");
+        let task2 = next_task(&tasks).expect("Second task not available");
+        assert_eq!(
+            task1.pair().transaction().header_signature(),
+            txn1.header_signature()
+        );
+        assert_eq!(
+            task2.pair().transaction().header_signature(),
+            txn2.header_signature()
+        );
+        assert!(context_lifecycle
+            .base_contexts(task2.context_id())
+            .is_empty());
+
+        scheduler.shutdown();
+    }
+
+    /// Tests that a transaction which conflicts with an earlier transaction is
+    /// not made available until the earlier one has executed, and that it is
+    /// executed on top of the earlier transaction's context.
+    #[test]
+    fn test_parallel_scheduler_conflicting_transactions() {
+        let context_lifecycle = RecordingContextLifecycle::new();
+        let mut scheduler =
+            ParallelScheduler::new(Box::new(context_lifecycle.clone()), "state0".into())
+                .expect("Failed to create scheduler");
+        let tasks = forward_tasks(&mut scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        // Reads from a prefix of the address written by the first transaction
+        let txn2 = make_transaction(&["aa"], &["cc00"], &[], "2");
+        scheduler
+            .add_batch(make_batch(vec![txn1.clone(), txn2.clone()]))
+            .expect("Failed to add batch");
+
+        let task1 = next_task(&tasks).expect("First task not available");
+        assert_eq!(
+            task1.pair().transaction().header_signature(),
+            txn1.header_signature()
+        );
+        assert!(next_task(&tasks).is_none());
+
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            *task1.context_id(),
+            txn1.header_signature().into(),
+        ));
+
+        let task2 = next_task(&tasks).expect("Second task not available");
+        assert_eq!(
+            task2.pair().transaction().header_signature(),
+            txn2.header_signature()
+        );
+        assert_eq!(
+            context_lifecycle.base_contexts(task2.context_id()),
+            vec![*task1.context_id()]
+        );
+
+        scheduler.shutdown();
+    }
+
+    /// Tests that the context of a transaction is dropped once its batch
+    /// result has been sent and a later transaction, whose batch result has
+    /// also been sent, has written to the same addresses, and that the
+    /// context of a read-only transaction is dropped as soon as its batch
+    /// result has been sent. Released transactions are no longer used as
+    /// base contexts.
+    #[test]
+    fn test_parallel_scheduler_release_contexts() {
+        let context_lifecycle = RecordingContextLifecycle::new();
+        let mut scheduler =
+            ParallelScheduler::new(Box::new(context_lifecycle.clone()), "state0".into())
+                .expect("Failed to create scheduler");
+        let results = forward_results(&mut scheduler);
+        let tasks = forward_tasks(&mut scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["aa00"], &["aa"], &[], "2");
+        let txn3 = make_transaction(&["aa00"], &[], &[], "3");
+
+        let mut context_ids = vec![];
+        for txn in &[&txn1, &txn2, &txn3] {
+            scheduler
+                .add_batch(make_batch(vec![(*txn).clone()]))
+                .expect("Failed to add batch");
+            let task = next_task(&tasks).expect("Task not available");
+            notifier.notify(ExecutionTaskCompletionNotification::Valid(
+                *task.context_id(),
+                txn.header_signature().into(),
+            ));
+            next_result(&results);
+            context_ids.push(*task.context_id());
+        }
+
+        // The contexts are released by the core thread after the results are sent
+        let expected = vec![context_ids[0], context_ids[2]];
+        let mut dropped = context_lifecycle.dropped();
+        for _ in 0..100 {
+            if dropped.len() >= expected.len() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+            dropped = context_lifecycle.dropped();
+        }
+        dropped.sort();
+        assert_eq!(dropped, expected);
+
+        // A transaction may still depend on a released transaction, and is executed on top of
+        // the later transaction which wrote to the released transaction's outputs
+        let txn4 = make_transaction(&["aa00"], &[], &[txn1.header_signature()], "4");
+        scheduler
+            .add_batch(make_batch(vec![txn4.clone()]))
+            .expect("Failed to add batch");
+        let task4 = next_task(&tasks).expect("Task not available");
+        assert_eq!(
+            context_lifecycle.base_contexts(task4.context_id()),
+            vec![context_ids[1]]
+        );
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            *task4.context_id(),
+            txn4.header_signature().into(),
+        ));
+        match &next_result(&results).results[..] {
+            [TransactionExecutionResult::Valid(_)] => (),
+            results => panic!("Unexpected results: {:?}", results),
+        }
+
+        scheduler.shutdown();
+        assert_all_contexts_dropped(&context_lifecycle);
+    }
+
+    /// Tests that batch results are returned in the order the batches were
+    /// added, even if a later batch finishes executing first, and that an
+    /// invalid transaction invalidates its whole batch.
+    #[test]
+    fn test_parallel_scheduler_result_order() {
+        let context_lifecycle = RecordingContextLifecycle::new();
+        let mut scheduler = ParallelScheduler::new(Box::new(context_lifecycle), "state0".into())
+            .expect("Failed to create scheduler");
+
+        let (result_tx, result_rx) = mpsc::channel();
+        scheduler
+            .set_result_callback(Box::new(move |result| {
+                result_tx.send(result).expect("Failed to send result");
+            }))
+            .expect("Failed to set result callback");
+
+        let tasks = forward_tasks(&mut scheduler);
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let txn1 = make_transaction(&["aa00"], &["aa00"], &[], "1");
+        let txn2 = make_transaction(&["bb00"], &["bb00"], &[], "2");
+        let txn3 = make_transaction(&["cc00"], &["cc00"], &[], "3");
+        let batch1 = make_batch(vec![txn1.clone(), txn2.clone()]);
+        let batch2 = make_batch(vec![txn3.clone()]);
+        scheduler
+            .add_batch(batch1.clone())
+            .expect("Failed to add batch");
+        scheduler
+            .add_batch(batch2.clone())
+            .expect("Failed to add batch");
+        scheduler.finalize().expect("Failed to finalize");
+
+        let task1 = next_task(&tasks).expect("First task not available");
+        let task2 = next_task(&tasks).expect("Second task not available");
+        let task3 = next_task(&tasks).expect("Third task not available");
+
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            *task3.context_id(),
+            txn3.header_signature().into(),
+        ));
+        assert!(result_rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            *task1.context_id(),
+            txn1.header_signature().into(),
+        ));
+        notifier.notify(ExecutionTaskCompletionNotification::Invalid(
+            *task2.context_id(),
+            InvalidTransactionResult {
+                transaction_id: txn2.header_signature().into(),
+                error_message: "invalid".into(),
+                error_data: vec![],
+            },
+        ));
+
+        let result1 = result_rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("Failed to receive first result")
+            .expect("First result was None");
+        assert_eq!(result1.batch, batch1);
+        match &result1.results[..] {
+            [TransactionExecutionResult::Invalid(result1), TransactionExecutionResult::Invalid(result2)] =>
+            {
+                assert_eq!(result1.transaction_id, txn1.header_signature());
+                assert_eq!(result2.transaction_id, txn2.header_signature());
+                assert_eq!(result2.error_message, "invalid");
+            }
+            _ => panic!("Unexpected results: {:?}", result1.results),
+        }
+
+        let result2 = result_rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("Failed to receive second result")
+            .expect("Second result was None");
+        assert_eq!(result2.batch, batch2);
+        match &result2.results[..] {
+            [TransactionExecutionResult::Valid(_)] => (),
+            _ => panic!("Unexpected results: {:?}", result2.results),
+        }
+
+        assert!(result_rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("Failed to receive final result")
+            .is_none());
+
+        scheduler.shutdown();
+    }
+}
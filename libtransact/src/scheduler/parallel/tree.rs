@@ -245,6 +245,37 @@ impl<T: Clone> RadixTree<T> {
             node.borrow_mut().children.clear()
         }
     }
+
+    /// Remove the node at ADDRESS if it has no data, along with any of its
+    /// ancestors left with neither data nor multiple children. A removed node
+    /// with a single child is replaced by that child.
+    pub fn remove_if_empty(&self, address: &str) {
+        let accumulated_nodes = self.walk_to_address(address);
+        let mut node = match accumulated_nodes.last() {
+            Some(node) if node.borrow().address == address => Rc::clone(node),
+            _ => return,
+        };
+
+        // The root node is never removed
+        for parent in accumulated_nodes.iter().rev().skip(1) {
+            if node.borrow().data.is_some() || node.borrow().children.len() > 1 {
+                break;
+            }
+
+            let only_child = node.borrow().children.values().next().cloned();
+            let mut parent_node = parent.borrow_mut();
+            parent_node
+                .children
+                .retain(|_, child| !Rc::ptr_eq(child, &node));
+            if let Some(child) = only_child {
+                let child_address = child.borrow().address.clone();
+                parent_node.children.insert(child_address, child);
+            }
+            drop(parent_node);
+
+            node = Rc::clone(parent);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -379,6 +410,35 @@ mod tests {
         assert_eq!(updated_node.borrow().data, Some(2));
     }
 
+    #[test]
+    fn tree_remove_if_empty() {
+        let tree: RadixTree<i32> = RadixTree::new();
+        tree.update("radix", &update_data, false);
+        tree.update("radish", &update_data, false);
+        tree.update("radon", &update_data, false);
+
+        // A node with data is kept
+        tree.remove_if_empty("radix");
+        assert!(tree.walk("radix").contains(&("radix".to_string(), Some(1))));
+
+        // Removing "radix" leaves "radi" with a single child, so it is replaced by "radish"
+        tree.update("radix", &|_| None, false);
+        tree.remove_if_empty("radix");
+        let walk_results_rad: Vec<String> = tree
+            .walk("rad")
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect();
+        assert_eq!(walk_results_rad, vec!["", "rad", "radon", "radish"]);
+
+        // Removing the remaining nodes empties the tree
+        tree.update("radish", &|_| None, false);
+        tree.remove_if_empty("radish");
+        tree.update("radon", &|_| None, false);
+        tree.remove_if_empty("radon");
+        assert_eq!(tree.root.borrow().children.len(), 0);
+    }
+
     #[test]
     fn tree_prune() {
         // R
@@ -20,7 +20,7 @@
 use crate::context::manager::ContextManagerError;
 use crate::context::{ContextId, ContextLifecycle};
 use crate::protocol::batch::BatchPair;
-use crate::protocol::transaction::Transaction;
+use crate::protocol::transaction::{Transaction, TransactionPair};
 use crate::scheduler::BatchExecutionResult;
 use crate::scheduler::ExecutionTask;
 use crate::scheduler::ExecutionTaskCompletionNotification;
@@ -29,6 +29,7 @@ use crate::scheduler::SchedulerError;
 use crate::scheduler::TransactionExecutionResult;
 
 use hex;
+use std::collections::{HashSet, VecDeque};
 use std::error::Error;
 use std::sync::mpsc::{Receiver, SendError, Sender};
 use std::sync::{Arc, Mutex};
@@ -142,7 +143,7 @@ pub struct SchedulerCore {
     current_txn: Option<String>,
 
     /// A queue of the current batch's transactions that have not been exeucted yet.
-    txn_queue: Vec<Transaction>,
+    txn_queue: VecDeque<Transaction>,
 
     /// The results of the current batch's transactions that have already been executed.
     txn_results: Vec<TransactionExecutionResult>,
@@ -156,6 +157,19 @@ pub struct SchedulerCore {
 
     /// The context from the previously run transaction.
     previous_context: Option<ContextId>,
+
+    /// The value of `previous_context` when the current batch was started; it
+    /// is restored if the current batch is invalid.
+    batch_start_context: Option<ContextId>,
+
+    /// The contexts created for the current batch's transactions.
+    batch_contexts: Vec<ContextId>,
+
+    /// The IDs of all transactions from batches which executed successfully.
+    valid_txn_ids: HashSet<String>,
+
+    /// The IDs of all transactions from batches which were invalid.
+    invalid_txn_ids: HashSet<String>,
 }
 
 impl SchedulerCore {
@@ -173,11 +187,15 @@ impl SchedulerCore {
             next_ready: false,
             current_batch: None,
             current_txn: None,
-            txn_queue: vec![],
+            txn_queue: VecDeque::new(),
             txn_results: vec![],
             context_lifecycle,
             state_id,
             previous_context: None,
+            batch_start_context: None,
+            batch_contexts: vec![],
+            valid_txn_ids: HashSet::new(),
+            invalid_txn_ids: HashSet::new(),
         }
     }
 
@@ -194,8 +212,14 @@ impl SchedulerCore {
             let mut shared = self.shared_lock.lock()?;
             match shared.pop_unscheduled_batch() {
                 Some(unscheduled_batch) => {
-                    self.txn_queue = unscheduled_batch.batch().transactions().to_vec();
+                    self.txn_queue = unscheduled_batch
+                        .batch()
+                        .transactions()
+                        .iter()
+                        .cloned()
+                        .collect();
                     self.current_batch = Some(unscheduled_batch);
+                    self.batch_start_context = self.previous_context;
                 }
                 None => {
                     // If the scheduler is finalized, no more batches will be added; send a `None`
@@ -208,7 +232,9 @@ impl SchedulerCore {
             }
         }
 
-        let transaction = self.txn_queue.pop().ok_or_else(|| {
+        // Transactions are executed in the order they were added, so that the results are
+        // returned in that order
+        let transaction = self.txn_queue.pop_front().ok_or_else(|| {
             CoreError::Internal(format!(
                 "no transactions left in current batch ({})",
                 self.current_batch
@@ -217,7 +243,7 @@ impl SchedulerCore {
                     .unwrap_or("")
             ))
         })?;
-        let transaction_id = transaction.header_signature().into();
+        let transaction_id: String = transaction.header_signature().into();
         let transaction_pair = match transaction.into_pair() {
             Ok(pair) => pair,
             Err(err) => {
@@ -227,16 +253,28 @@ impl SchedulerCore {
                     error_data: vec![],
                 })?;
                 self.send_batch_result()?;
-                return Ok(());
+                // The executor is still waiting for a task, so the next batch is started
+                return self.try_schedule_next();
             }
         };
 
+        if let Some(error_message) = self.check_dependencies(&transaction_pair)? {
+            self.invalidate_current_batch(InvalidTransactionResult {
+                transaction_id,
+                error_message,
+                error_data: vec![],
+            })?;
+            self.send_batch_result()?;
+            return self.try_schedule_next();
+        }
+
         let context_id = match self.previous_context {
             Some(previous_context_id) => self
                 .context_lifecycle
                 .create_context(&[previous_context_id], &self.state_id),
             None => self.context_lifecycle.create_context(&[], &self.state_id),
         };
+        self.batch_contexts.push(context_id);
 
         self.current_txn = Some(transaction_pair.transaction().header_signature().into());
         self.execution_tx
@@ -246,6 +284,53 @@ impl SchedulerCore {
         Ok(())
     }
 
+    /// Returns true if the transaction with the given ID was part of the
+    /// current batch and has already been executed.
+    fn executed_in_current_batch(&self, transaction_id: &str) -> bool {
+        self.current_batch
+            .as_ref()
+            .map(|batch| {
+                batch
+                    .batch()
+                    .transactions()
+                    .iter()
+                    .any(|txn| txn.header_signature() == transaction_id)
+            })
+            .unwrap_or(false)
+            && !self
+                .txn_queue
+                .iter()
+                .any(|txn| txn.header_signature() == transaction_id)
+    }
+
+    /// Checks that all of the transaction's dependencies have either been
+    /// executed successfully or committed, returning an error message if they
+    /// have not.
+    fn check_dependencies(
+        &self,
+        transaction_pair: &TransactionPair,
+    ) -> Result<Option<String>, CoreError> {
+        let shared = self.shared_lock.lock()?;
+        Ok(transaction_pair
+            .header()
+            .dependencies()
+            .iter()
+            .map(hex::encode)
+            .filter_map(|dependency| {
+                if self.invalid_txn_ids.contains(&dependency) {
+                    Some(format!("dependency {} is invalid", dependency))
+                } else if self.valid_txn_ids.contains(&dependency)
+                    || self.executed_in_current_batch(&dependency)
+                    || shared.committed_check()(&dependency)
+                {
+                    None
+                } else {
+                    Some(format!("dependency {} is missing", dependency))
+                }
+            })
+            .next())
+    }
+
     fn invalidate_current_batch(
         &mut self,
         invalid_result: InvalidTransactionResult,
@@ -323,13 +408,57 @@ impl SchedulerCore {
         let mut results = vec![];
         std::mem::swap(&mut results, &mut self.txn_results);
 
+        let txn_ids = batch
+            .batch()
+            .transactions()
+            .iter()
+            .map(|txn| txn.header_signature().to_string());
+        let batch_is_valid = results.iter().all(|result| match result {
+            TransactionExecutionResult::Valid(_) => true,
+            TransactionExecutionResult::Invalid(_) => false,
+        });
+        if batch_is_valid {
+            self.valid_txn_ids.extend(txn_ids);
+        } else {
+            self.invalid_txn_ids.extend(txn_ids);
+        }
+
         let batch_result = BatchExecutionResult { batch, results };
 
         self.shared_lock.lock()?.result_callback()(Some(batch_result));
 
+        self.drop_batch_contexts(batch_is_valid);
+
         Ok(())
     }
 
+    /// Drops the contexts which are no longer needed after a batch's result
+    /// has been sent.
+    ///
+    /// The final context of a valid batch is kept as the base for the next
+    /// transaction, and the final context of the previous batch is dropped
+    /// since it is now referenced by the batch's contexts. If the batch is
+    /// invalid, all of its contexts are dropped and the next transaction will
+    /// use the final context of the previous batch instead.
+    fn drop_batch_contexts(&mut self, batch_is_valid: bool) {
+        let batch_start_context = self.batch_start_context.take();
+        if batch_is_valid {
+            if let Some(context_id) = batch_start_context {
+                if Some(context_id) != self.previous_context {
+                    self.context_lifecycle.drop_context(context_id);
+                }
+            }
+        } else {
+            self.previous_context = batch_start_context;
+        }
+
+        for context_id in self.batch_contexts.drain(..) {
+            if Some(context_id) != self.previous_context {
+                self.context_lifecycle.drop_context(context_id);
+            }
+        }
+    }
+
     fn send_scheduler_error(&mut self, error: SchedulerError) -> Result<(), CoreError> {
         self.shared_lock.lock()?.error_callback()(error);
         Ok(())
@@ -411,6 +540,16 @@ impl SchedulerCore {
             }
         }
 
+        // Release all of the contexts still held by the scheduler
+        let mut context_ids: Vec<ContextId> = self.batch_contexts.drain(..).collect();
+        context_ids.extend(self.batch_start_context.take());
+        context_ids.extend(self.previous_context.take());
+        context_ids.sort();
+        context_ids.dedup();
+        for context_id in context_ids {
+            self.context_lifecycle.drop_context(context_id);
+        }
+
         Ok(())
     }
 
@@ -128,6 +128,14 @@ impl Scheduler for SerialScheduler {
         Ok(())
     }
 
+    fn set_committed_check(
+        &mut self,
+        check: Box<dyn Fn(&str) -> bool + Send>,
+    ) -> Result<(), SchedulerError> {
+        self.shared_lock.lock()?.set_committed_check(check);
+        Ok(())
+    }
+
     fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError> {
         let mut shared = self.shared_lock.lock()?;
 
@@ -221,4 +229,55 @@ mod tests {
         test_scheduler_flow_with_one_transaction(&mut scheduler);
         scheduler.shutdown();
     }
+
+    #[test]
+    fn test_serial_scheduler_missing_dependency() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler =
+            SerialScheduler::new(context_lifecycle, state_id).expect("Failed to create scheduler");
+        test_scheduler_missing_dependency(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_serial_scheduler_dependency_ordering() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler =
+            SerialScheduler::new(context_lifecycle, state_id).expect("Failed to create scheduler");
+        test_scheduler_dependency_ordering(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_serial_scheduler_committed_dependency() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler =
+            SerialScheduler::new(context_lifecycle, state_id).expect("Failed to create scheduler");
+        test_scheduler_committed_dependency(&mut scheduler);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_serial_scheduler_drop_contexts() {
+        let context_lifecycle = RecordingContextLifecycle::new();
+        let mut scheduler =
+            SerialScheduler::new(Box::new(context_lifecycle.clone()), "state0".into())
+                .expect("Failed to create scheduler");
+        test_scheduler_drop_contexts(&mut scheduler);
+        scheduler.shutdown();
+        assert_all_contexts_dropped(&context_lifecycle);
+    }
+
+    #[test]
+    fn test_serial_scheduler_invalid_dependency() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler =
+            SerialScheduler::new(context_lifecycle, state_id).expect("Failed to create scheduler");
+        test_scheduler_invalid_dependency(&mut scheduler);
+        scheduler.shutdown();
+    }
 }
@@ -20,7 +20,7 @@
 use crate::protocol::batch::BatchPair;
 use crate::scheduler::BatchExecutionResult;
 use crate::scheduler::SchedulerError;
-use crate::scheduler::{default_error_callback, default_result_callback};
+use crate::scheduler::{default_committed_check, default_error_callback, default_result_callback};
 
 use std::collections::VecDeque;
 
@@ -29,6 +29,7 @@ pub struct Shared {
     finalized: bool,
     result_callback: Box<Fn(Option<BatchExecutionResult>) + Send>,
     error_callback: Box<Fn(SchedulerError) + Send>,
+    committed_check: Box<dyn Fn(&str) -> bool + Send>,
     unscheduled_batches: VecDeque<BatchPair>,
 }
 
@@ -44,6 +45,7 @@ impl Shared {
             finalized: false,
             result_callback: Box::new(default_result_callback),
             error_callback: Box::new(default_error_callback),
+            committed_check: Box::new(default_committed_check),
             unscheduled_batches: VecDeque::new(),
         }
     }
@@ -60,6 +62,10 @@ impl Shared {
         &*self.error_callback
     }
 
+    pub fn committed_check(&self) -> &(dyn Fn(&str) -> bool + Send) {
+        &*self.committed_check
+    }
+
     pub fn set_finalized(&mut self, finalized: bool) {
         self.finalized = finalized;
     }
@@ -72,6 +78,10 @@ impl Shared {
         self.error_callback = callback;
     }
 
+    pub fn set_committed_check(&mut self, check: Box<dyn Fn(&str) -> bool + Send>) {
+        self.committed_check = check;
+    }
+
     pub fn batch_already_queued(&self, batch: &BatchPair) -> bool {
         self.unscheduled_batches.contains(batch)
     }
@@ -0,0 +1,167 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! An Ed25519 Signer and Verifier
+//!
+//! Private keys are the 32-byte seeds defined by RFC 8032, public keys are 32 bytes, and
+//! signatures are 64 bytes.  Ed25519 signatures are deterministic: signing the same message with
+//! the same key always produces the same signature.
+
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sign;
+
+use crate::signing::Error;
+use crate::signing::{Signer, Verifier};
+
+pub struct Ed25519Signer {
+    private_key: PKey<Private>,
+    public_key: Vec<u8>,
+}
+
+impl Ed25519Signer {
+    /// Creates a signer with a newly generated private key.
+    pub fn generate() -> Result<Self, Error> {
+        Self::from_key(PKey::generate_ed25519().map_err(parse_error)?)
+    }
+
+    /// Creates a signer from a 32-byte private key.
+    pub fn from_bytes(private_key: &[u8]) -> Result<Self, Error> {
+        Self::from_key(
+            PKey::private_key_from_raw_bytes(private_key, Id::ED25519).map_err(parse_error)?,
+        )
+    }
+
+    /// Returns the 32-byte private key, which may be passed to `from_bytes` to load the same key.
+    pub fn private_key(&self) -> Result<Vec<u8>, Error> {
+        self.private_key.raw_private_key().map_err(parse_error)
+    }
+
+    fn from_key(private_key: PKey<Private>) -> Result<Self, Error> {
+        let public_key = private_key.raw_public_key().map_err(parse_error)?;
+        Ok(Ed25519Signer {
+            private_key,
+            public_key,
+        })
+    }
+}
+
+impl Signer for Ed25519Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        sign::Signer::new_without_digest(&self.private_key)
+            .and_then(|mut signer| signer.sign_oneshot_to_vec(message))
+            .map_err(|e| Error::SigningError(format!("{}", e)))
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+#[derive(Default)]
+pub struct Ed25519Verifier {}
+
+impl Ed25519Verifier {
+    pub fn new() -> Self {
+        Ed25519Verifier::default()
+    }
+}
+
+impl Verifier for Ed25519Verifier {
+    fn verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, Error> {
+        let public_key = PKey::public_key_from_raw_bytes(public_key, Id::ED25519)
+            .map_err(|e| Error::VerificationError(format!("Invalid public key: {}", e)))?;
+        let mut verifier = sign::Verifier::new_without_digest(&public_key)
+            .map_err(|e| Error::VerificationError(format!("{}", e)))?;
+        // A malformed signature is reported by OpenSSL as a failed verification
+        Ok(verifier.verify_oneshot(signature, message).unwrap_or(false))
+    }
+}
+
+fn parse_error(err: openssl::error::ErrorStack) -> Error {
+    Error::ParseError(format!("Unable to load private key: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+
+    // Test vector 1 from RFC 8032, which signs an empty message
+    static PRIVATE_KEY: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    static PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    static SIGNATURE: &str = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555\
+                              fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+
+    #[test]
+    fn sign_rfc8032_test_vector() {
+        let signer = Ed25519Signer::from_bytes(&hex::decode(PRIVATE_KEY).unwrap()).unwrap();
+        assert_eq!(PUBLIC_KEY, hex::encode(signer.public_key()));
+
+        let signature = signer.sign(&[]).unwrap();
+        assert_eq!(SIGNATURE, hex::encode(&signature));
+        assert!(Ed25519Verifier::new()
+            .verify(&[], &signature, signer.public_key())
+            .unwrap());
+    }
+
+    #[test]
+    fn generate_sign_and_verify() {
+        let signer = Ed25519Signer::generate().unwrap();
+        let verifier = Ed25519Verifier::new();
+
+        let signature = signer.sign(b"message").unwrap();
+        assert_eq!(signature, signer.sign(b"message").unwrap());
+        assert!(verifier
+            .verify(b"message", &signature, signer.public_key())
+            .unwrap());
+        assert!(!verifier
+            .verify(b"other message", &signature, signer.public_key())
+            .unwrap());
+        assert!(!verifier
+            .verify(b"message", &signature[1..], signer.public_key())
+            .unwrap());
+
+        let loaded = Ed25519Signer::from_bytes(&signer.private_key().unwrap()).unwrap();
+        assert_eq!(signer.public_key(), loaded.public_key());
+    }
+
+    #[test]
+    fn invalid_private_key() {
+        assert!(Ed25519Signer::from_bytes(&[0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn verify_built_batch() {
+        let signer = Ed25519Signer::generate().unwrap();
+        let transaction = TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![])
+            .with_outputs(vec![])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build(&signer)
+            .unwrap();
+        let pair = BatchBuilder::new()
+            .with_transactions(vec![transaction])
+            .build_pair(&signer)
+            .unwrap();
+
+        assert!(pair.verify(&Ed25519Verifier::new()).is_ok());
+    }
+}
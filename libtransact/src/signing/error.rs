@@ -22,18 +22,24 @@ use std::error::Error as StdError;
 #[derive(Debug)]
 pub enum Error {
     SigningError(String),
+    VerificationError(String),
+    ParseError(String),
 }
 
 impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
             Error::SigningError(ref msg) => msg,
+            Error::VerificationError(ref msg) => msg,
+            Error::ParseError(ref msg) => msg,
         }
     }
 
     fn cause(&self) -> Option<&StdError> {
         match *self {
             Error::SigningError(_) => None,
+            Error::VerificationError(_) => None,
+            Error::ParseError(_) => None,
         }
     }
 }
@@ -42,6 +48,8 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
             Error::SigningError(ref s) => write!(f, "SigningError: {}", s),
+            Error::VerificationError(ref s) => write!(f, "VerificationError: {}", s),
+            Error::ParseError(ref s) => write!(f, "ParseError: {}", s),
         }
     }
 }
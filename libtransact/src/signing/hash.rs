@@ -20,11 +20,14 @@
 //! The HashSigner provides a simple implementation of the Signer trait, by simply producing a
 //! SHA-512 hash of the message bytes.  This implementation allows for the use of transact without
 //! the need of a cryptographic library for public-private key signing.
+//!
+//! The HashSigner also implements the Verifier trait, by checking that a signature is the SHA-512
+//! hash of the message bytes; the public key is ignored.
 
 use sha2::{Digest, Sha512};
 
 use crate::signing::Error;
-use crate::signing::Signer;
+use crate::signing::{Signer, Verifier};
 
 pub struct HashSigner {
     dummy_public_key: Vec<u8>,
@@ -55,3 +58,9 @@ impl Signer for HashSigner {
         &self.dummy_public_key
     }
 }
+
+impl Verifier for HashSigner {
+    fn verify(&self, message: &[u8], signature: &[u8], _public_key: &[u8]) -> Result<bool, Error> {
+        Ok(self.sign(message)? == signature)
+    }
+}
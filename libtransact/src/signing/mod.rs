@@ -15,10 +15,12 @@
  * -----------------------------------------------------------------------------
  */
 
-//! Simple traits for signing transactions.
+//! Simple traits for signing transactions and verifying their signatures.
 
+pub mod ed25519;
 pub mod error;
 pub mod hash;
+pub mod secp256k1;
 
 pub use crate::signing::error::Error;
 
@@ -26,3 +28,12 @@ pub trait Signer {
     fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
     fn public_key(&self) -> &[u8];
 }
+
+pub trait Verifier {
+    /// Returns whether the signature is a valid signature of the message, made with the private
+    /// key that corresponds to the given public key.
+    ///
+    /// An error is returned if the signature or public key could not be used for verification,
+    /// such as when they are malformed.
+    fn verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, Error>;
+}
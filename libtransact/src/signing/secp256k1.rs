@@ -0,0 +1,293 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A secp256k1 Signer and Verifier
+//!
+//! The keys and signatures are compatible with those used by Sawtooth: private keys are 32-byte
+//! secp256k1 scalars, public keys are 33-byte compressed curve points, and signatures are the
+//! compact 64-byte concatenation of the ECDSA `r` and `s` values, computed over the SHA-256 digest
+//! of the message.  As in Sawtooth, `s` is always in the lower half of the curve order, and
+//! signatures with a high `s` value are rejected by the verifier.
+
+use std::cmp::Ordering;
+
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use openssl::pkey::Private;
+use openssl::sha::sha256;
+
+use crate::signing::Error;
+use crate::signing::{Signer, Verifier};
+
+const PRIVATE_KEY_SIZE: usize = 32;
+const SIGNATURE_VALUE_SIZE: usize = 32;
+
+pub struct Secp256k1Signer {
+    private_key: EcKey<Private>,
+    public_key: Vec<u8>,
+}
+
+impl Secp256k1Signer {
+    /// Creates a signer from a hex-encoded, 32-byte private key, as stored in Sawtooth's `.priv`
+    /// key files.
+    pub fn from_hex(private_key: &str) -> Result<Self, Error> {
+        let private_key = hex::decode(private_key.trim())
+            .map_err(|e| Error::ParseError(format!("Private key is not valid hex: {}", e)))?;
+        Self::from_bytes(&private_key)
+    }
+
+    /// Creates a signer from a 32-byte private key.
+    pub fn from_bytes(private_key: &[u8]) -> Result<Self, Error> {
+        if private_key.len() != PRIVATE_KEY_SIZE {
+            return Err(Error::ParseError(format!(
+                "Private key must be {} bytes, but was {} bytes",
+                PRIVATE_KEY_SIZE,
+                private_key.len()
+            )));
+        }
+
+        let group = secp256k1_group().map_err(parse_error)?;
+        let mut ctx = BigNumContext::new().map_err(parse_error)?;
+        let private_number = BigNum::from_slice(private_key).map_err(parse_error)?;
+        let mut public_point = EcPoint::new(&group).map_err(parse_error)?;
+        public_point
+            .mul_generator2(&group, &private_number, &mut ctx)
+            .map_err(parse_error)?;
+        let key = EcKey::from_private_components(&group, &private_number, &public_point)
+            .map_err(parse_error)?;
+        key.check_key().map_err(parse_error)?;
+
+        Self::from_key(key)
+    }
+
+    /// Creates a signer from a PEM-encoded secp256k1 private key.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, Error> {
+        Self::from_key(EcKey::private_key_from_pem(pem).map_err(parse_error)?)
+    }
+
+    /// Creates a signer from a PEM-encoded secp256k1 private key, which is encrypted with the
+    /// given password.
+    pub fn from_pem_with_password(pem: &[u8], password: &str) -> Result<Self, Error> {
+        Self::from_key(
+            EcKey::private_key_from_pem_passphrase(pem, password.as_bytes())
+                .map_err(parse_error)?,
+        )
+    }
+
+    fn from_key(private_key: EcKey<Private>) -> Result<Self, Error> {
+        if private_key.group().curve_name() != Some(Nid::SECP256K1) {
+            return Err(Error::ParseError(
+                "Private key is not a secp256k1 key".to_string(),
+            ));
+        }
+
+        let mut ctx = BigNumContext::new().map_err(parse_error)?;
+        let public_key = private_key
+            .public_key()
+            .to_bytes(
+                private_key.group(),
+                PointConversionForm::COMPRESSED,
+                &mut ctx,
+            )
+            .map_err(parse_error)?;
+
+        Ok(Secp256k1Signer {
+            private_key,
+            public_key,
+        })
+    }
+}
+
+impl Signer for Secp256k1Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let signing_error = |e| Error::SigningError(format!("{}", e));
+
+        let signature =
+            EcdsaSig::sign(&sha256(message), &self.private_key).map_err(signing_error)?;
+
+        let (order, half_order) = curve_order(self.private_key.group()).map_err(signing_error)?;
+        let mut s = BigNum::new().map_err(signing_error)?;
+        if signature.s().ucmp(&half_order) == Ordering::Greater {
+            s.checked_sub(&order, signature.s())
+                .map_err(signing_error)?;
+        } else {
+            s = signature.s().to_owned().map_err(signing_error)?;
+        }
+
+        let mut bytes = signature
+            .r()
+            .to_vec_padded(SIGNATURE_VALUE_SIZE as i32)
+            .map_err(signing_error)?;
+        bytes.extend(
+            s.to_vec_padded(SIGNATURE_VALUE_SIZE as i32)
+                .map_err(signing_error)?,
+        );
+        Ok(bytes)
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+#[derive(Default)]
+pub struct Secp256k1Verifier {}
+
+impl Secp256k1Verifier {
+    pub fn new() -> Self {
+        Secp256k1Verifier::default()
+    }
+}
+
+impl Verifier for Secp256k1Verifier {
+    fn verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, Error> {
+        let verification_error = |e| Error::VerificationError(format!("{}", e));
+
+        if signature.len() != 2 * SIGNATURE_VALUE_SIZE {
+            return Err(Error::VerificationError(format!(
+                "Signature must be {} bytes, but was {} bytes",
+                2 * SIGNATURE_VALUE_SIZE,
+                signature.len()
+            )));
+        }
+
+        let group = secp256k1_group().map_err(verification_error)?;
+        let mut ctx = BigNumContext::new().map_err(verification_error)?;
+        let public_point =
+            EcPoint::from_bytes(&group, public_key, &mut ctx).map_err(verification_error)?;
+        let key = EcKey::from_public_key(&group, &public_point).map_err(verification_error)?;
+
+        let r =
+            BigNum::from_slice(&signature[..SIGNATURE_VALUE_SIZE]).map_err(verification_error)?;
+        let s =
+            BigNum::from_slice(&signature[SIGNATURE_VALUE_SIZE..]).map_err(verification_error)?;
+
+        let (_, half_order) = curve_order(&group).map_err(verification_error)?;
+        if s.ucmp(&half_order) == Ordering::Greater {
+            return Ok(false);
+        }
+
+        let signature = EcdsaSig::from_private_components(r, s).map_err(verification_error)?;
+        signature
+            .verify(&sha256(message), &key)
+            .map_err(verification_error)
+    }
+}
+
+fn secp256k1_group() -> Result<EcGroup, openssl::error::ErrorStack> {
+    EcGroup::from_curve_name(Nid::SECP256K1)
+}
+
+/// Returns the order of the curve, and half of the order.
+fn curve_order(
+    group: &openssl::ec::EcGroupRef,
+) -> Result<(BigNum, BigNum), openssl::error::ErrorStack> {
+    let mut ctx = BigNumContext::new()?;
+    let mut order = BigNum::new()?;
+    group.order(&mut order, &mut ctx)?;
+    let mut half_order = BigNum::new()?;
+    half_order.rshift1(&order)?;
+    Ok((order, half_order))
+}
+
+fn parse_error(err: openssl::error::ErrorStack) -> Error {
+    Error::ParseError(format!("Unable to load private key: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+
+    // Key and signature generated by the Sawtooth signing library
+    static PRIVATE_KEY: &str = "2f1e7b7a130d7ba9da0068b3bb0ba1d79e7e77110302c9f746c3c2a63fe40088";
+    static PUBLIC_KEY: &str = "026a2c795a9776f75464aa3bda3534c3154a6e91b357b1181d3f515110f84b67c5";
+    static MESSAGE: &str = "test";
+    static SIGNATURE: &str = "5195115d9be2547b720ee74c23dd841842875db6eae1f5da8605b050a49e702b\
+                              4aa83be72ab7e3cb20f17c657011b49f4c8632be2745ba4de79e6aa05da57b35";
+
+    #[test]
+    fn public_key_from_private_key() {
+        let signer = Secp256k1Signer::from_hex(PRIVATE_KEY).unwrap();
+        assert_eq!(PUBLIC_KEY, hex::encode(signer.public_key()));
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let signer = Secp256k1Signer::from_hex(PRIVATE_KEY).unwrap();
+        let verifier = Secp256k1Verifier::new();
+
+        let signature = signer.sign(MESSAGE.as_bytes()).unwrap();
+        assert_eq!(64, signature.len());
+        assert!(verifier
+            .verify(MESSAGE.as_bytes(), &signature, signer.public_key())
+            .unwrap());
+        assert!(!verifier
+            .verify(b"other message", &signature, signer.public_key())
+            .unwrap());
+    }
+
+    #[test]
+    fn verify_sawtooth_signature() {
+        let verifier = Secp256k1Verifier::new();
+        assert!(verifier
+            .verify(
+                MESSAGE.as_bytes(),
+                &hex::decode(SIGNATURE).unwrap(),
+                &hex::decode(PUBLIC_KEY).unwrap()
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn load_from_pem() {
+        let group = secp256k1_group().unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let pem = key.private_key_to_pem().unwrap();
+
+        let signer = Secp256k1Signer::from_pem(&pem).unwrap();
+        let signature = signer.sign(MESSAGE.as_bytes()).unwrap();
+        assert!(Secp256k1Verifier::new()
+            .verify(MESSAGE.as_bytes(), &signature, signer.public_key())
+            .unwrap());
+    }
+
+    #[test]
+    fn verify_built_transaction() {
+        let signer = Secp256k1Signer::from_hex(PRIVATE_KEY).unwrap();
+        let pair = TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![])
+            .with_outputs(vec![])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(MESSAGE.as_bytes().to_vec())
+            .build_pair(&signer)
+            .unwrap();
+
+        assert!(pair.verify(&Secp256k1Verifier::new()).is_ok());
+    }
+
+    #[test]
+    fn invalid_private_keys() {
+        assert!(Secp256k1Signer::from_hex("not hex").is_err());
+        assert!(Secp256k1Signer::from_hex("0102").is_err());
+        assert!(Secp256k1Signer::from_bytes(&[0u8; 32]).is_err());
+    }
+}
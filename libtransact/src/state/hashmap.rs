@@ -18,7 +18,7 @@
 //! Provides a simple, in-memory implementation of backed by `std::collections::HashMap`.
 
 use super::error::{StateReadError, StateWriteError};
-use super::{Read, StateChange, Write};
+use super::{Read, StateChange, ValueIter, Write};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
@@ -134,6 +134,27 @@ impl Read for HashMapState {
             .collect())
     }
 
+    fn iter_prefix(
+        &self,
+        state_id: &Self::StateId,
+        prefix: Option<&Self::Key>,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        let states = self.states.lock().expect("Couldn't lock states mutex!");
+        let state = states.get(state_id).ok_or_else(|| {
+            StateReadError::InvalidStateId(format!("Unknown state id {}", state_id))
+        })?;
+
+        let prefix = prefix.map(String::as_str).unwrap_or("");
+        let mut entries = state
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+
     fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
         Box::new(Clone::clone(self))
     }
@@ -202,4 +223,39 @@ mod tests {
         assert_eq!(expected_state, found_state);
         assert_eq!(2, found_state.len());
     }
+
+    #[test]
+    fn test_iter_prefix() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let state_changes = make_state_changes(
+            vec![("ab02", &BYTES1), ("ab01", &BYTES2), ("cd01", &BYTES3)],
+            vec![],
+        );
+        let state_id = state.commit(&state_id, &state_changes).unwrap();
+
+        let entries = state
+            .iter_prefix(&state_id, Some(&"ab".to_string()))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("ab01".to_string(), BYTES2.to_vec()),
+                ("ab02".to_string(), BYTES1.to_vec()),
+            ],
+            entries
+        );
+
+        assert_eq!(3, state.iter_prefix(&state_id, None).unwrap().count());
+        assert_eq!(
+            0,
+            state
+                .iter_prefix(&state_id, Some(&"ef".to_string()))
+                .unwrap()
+                .count()
+        );
+        assert!(state.iter_prefix(&"unknown".to_string(), None).is_err());
+    }
 }
@@ -16,9 +16,11 @@
  * ------------------------------------------------------------------------------
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
 use std::io::Cursor;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use cbor;
 use cbor::decoder::GenericDecoder;
@@ -32,15 +34,30 @@ use crate::database::{Database, DatabaseReader, DatabaseWriter};
 
 use super::change_log::{ChangeLogEntry, Successor};
 use super::error::{StatePruneError, StateReadError, StateWriteError};
-use super::{Prune, Read, StateChange, Write};
+use super::merkle_history;
+use super::merkle_snapshot::{SnapshotReader, SnapshotWriter};
+use super::{Prune, Read, StateChange, ValueIter, Write};
 
 pub use super::merkle_error::StateDatabaseError;
+pub use super::merkle_history::AddressChange;
+pub use super::merkle_pruning::{BackgroundPruner, PruningManager, PruningPolicy};
 
 const TOKEN_SIZE: usize = 2;
 
 pub const CHANGE_LOG_INDEX: &str = "change_log";
 pub const DUPLICATE_LOG_INDEX: &str = "duplicate_log";
-pub const INDEXES: [&str; 2] = [CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX];
+pub const METADATA_INDEX: &str = "metadata";
+/// The index of the roots at which each address was changed.  Databases created without it record
+/// no address history.
+pub const ADDRESS_HISTORY_INDEX: &str = "address_history";
+pub const INDEXES: [&str; 4] = [
+    CHANGE_LOG_INDEX,
+    DUPLICATE_LOG_INDEX,
+    METADATA_INDEX,
+    ADDRESS_HISTORY_INDEX,
+];
+
+const HASH_ALGORITHM_KEY: &[u8] = b"hash_algorithm";
 
 type StateIter = Iterator<Item = Result<(String, Vec<u8>), StateDatabaseError>>;
 type StateHash = Vec<u8>;
@@ -48,11 +65,106 @@ type StateHash = Vec<u8>;
 #[derive(Clone)]
 pub struct MerkleState {
     db: Box<dyn Database>,
+    cache: Option<NodeCache>,
+    /// The hash algorithm recorded in the database, unless it could not be read when this
+    /// MerkleState was constructed, in which case it is read for each tree.
+    hash_algorithm: Option<HashAlgorithm>,
 }
 
 impl MerkleState {
     pub fn new(db: Box<dyn Database>) -> Self {
-        MerkleState { db }
+        let hash_algorithm = resolve_hash_algorithm(&*db, None).ok();
+        MerkleState {
+            db,
+            cache: None,
+            hash_algorithm,
+        }
+    }
+
+    /// Constructs a MerkleState whose nodes are addressed by the given hash algorithm.
+    ///
+    /// The hash algorithm is recorded in the database, if it is not already; an error is returned
+    /// if the database uses a different one.  A MerkleState constructed with `new` uses whichever
+    /// hash algorithm is recorded in its database.
+    pub fn new_with_hash_algorithm(
+        db: Box<dyn Database>,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, StateWriteError> {
+        let hash_algorithm = resolve_hash_algorithm(&*db, Some(hash_algorithm))
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+        Ok(MerkleState {
+            db,
+            cache: None,
+            hash_algorithm: Some(hash_algorithm),
+        })
+    }
+
+    /// Constructs a MerkleState which reads nodes through the given cache.
+    ///
+    /// The cache is shared by all clones of this MerkleState, and may be shared with other
+    /// MerkleStates backed by the same database.
+    pub fn new_with_cache(db: Box<dyn Database>, cache: NodeCache) -> Self {
+        let hash_algorithm = resolve_hash_algorithm(&*db, None).ok();
+        MerkleState {
+            db,
+            cache: Some(cache),
+            hash_algorithm,
+        }
+    }
+
+    pub(super) fn db(&self) -> &dyn Database {
+        &*self.db
+    }
+
+    /// Returns the changes which transform the state at `from_state_id` into the state at
+    /// `to_state_id`, ordered by key.
+    pub fn diff(
+        &self,
+        from_state_id: &str,
+        to_state_id: &str,
+    ) -> Result<Vec<StateChange>, StateReadError> {
+        MerkleRadixTree::diff(&*self.db, from_state_id, to_state_id).map_err(map_read_error)
+    }
+
+    /// Returns the values of an address at each of the roots, up to and including `state_id`, at
+    /// which it was changed, oldest first.
+    pub fn address_history(
+        &self,
+        state_id: &str,
+        address: &str,
+    ) -> Result<Vec<AddressChange>, StateReadError> {
+        MerkleRadixTree::address_history(&*self.db, state_id, address).map_err(map_read_error)
+    }
+
+    /// Returns the last change made to an address at or before `state_id`, if any.
+    pub fn last_change(
+        &self,
+        state_id: &str,
+        address: &str,
+    ) -> Result<Option<AddressChange>, StateReadError> {
+        MerkleRadixTree::last_change(&*self.db, state_id, address).map_err(map_read_error)
+    }
+
+    /// Writes a snapshot of the state at `state_id`, returning the number of entries written.
+    pub fn export_snapshot(
+        &self,
+        state_id: &str,
+        writer: &mut dyn io::Write,
+    ) -> Result<u64, StateReadError> {
+        MerkleRadixTree::export_snapshot(&*self.db, state_id, writer).map_err(map_read_error)
+    }
+
+    /// Rebuilds the state from a snapshot, returning its state id.
+    pub fn import_snapshot(&self, reader: &mut dyn io::Read) -> Result<String, StateWriteError> {
+        MerkleRadixTree::import_snapshot(self.db.clone(), reader)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))
+    }
+}
+
+fn map_read_error(err: StateDatabaseError) -> StateReadError {
+    match err {
+        StateDatabaseError::NotFound(msg) => StateReadError::InvalidStateId(msg),
+        _ => StateReadError::StorageError(Box::new(err)),
     }
 }
 
@@ -66,8 +178,13 @@ impl Write for MerkleState {
         state_id: &Self::StateId,
         state_changes: &[StateChange],
     ) -> Result<Self::StateId, StateWriteError> {
-        let mut merkle_tree = MerkleRadixTree::new(self.db.clone(), Some(state_id))
-            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+        let mut merkle_tree = MerkleRadixTree::with_optional_cache(
+            self.db.clone(),
+            Some(state_id),
+            self.cache.clone(),
+            self.hash_algorithm,
+        )
+        .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
         merkle_tree
             .set_merkle_root(state_id.to_string())
             .map_err(|err| match err {
@@ -84,8 +201,13 @@ impl Write for MerkleState {
         state_id: &Self::StateId,
         state_changes: &[StateChange],
     ) -> Result<Self::StateId, StateWriteError> {
-        let mut merkle_tree = MerkleRadixTree::new(self.db.clone(), Some(state_id))
-            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+        let mut merkle_tree = MerkleRadixTree::with_optional_cache(
+            self.db.clone(),
+            Some(state_id),
+            self.cache.clone(),
+            self.hash_algorithm,
+        )
+        .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
 
         merkle_tree
             .set_merkle_root(state_id.to_string())
@@ -109,8 +231,13 @@ impl Read for MerkleState {
         state_id: &Self::StateId,
         keys: &[Self::Key],
     ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError> {
-        let mut merkle_tree = MerkleRadixTree::new(self.db.clone(), Some(state_id))
-            .map_err(|err| StateReadError::StorageError(Box::new(err)))?;
+        let mut merkle_tree = MerkleRadixTree::with_optional_cache(
+            self.db.clone(),
+            Some(state_id),
+            self.cache.clone(),
+            self.hash_algorithm,
+        )
+        .map_err(|err| StateReadError::StorageError(Box::new(err)))?;
 
         merkle_tree
             .set_merkle_root(state_id.to_string())
@@ -133,6 +260,47 @@ impl Read for MerkleState {
         })
     }
 
+    fn iter_prefix(
+        &self,
+        state_id: &Self::StateId,
+        prefix: Option<&Self::Key>,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        let merkle_tree = MerkleRadixTree::with_optional_cache(
+            self.db.clone(),
+            Some(state_id),
+            self.cache.clone(),
+            self.hash_algorithm,
+        )
+        .map_err(map_read_error)?;
+
+        // The tree can only be walked from a node, so a prefix ending part way through a node's
+        // token is walked from that node's parent
+        let prefix = prefix.cloned().unwrap_or_default();
+        if !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(StateReadError::InvalidKey(format!(
+                "{} is not a hex prefix",
+                prefix
+            )));
+        }
+        let node_path = &prefix[..prefix.len() - prefix.len() % TOKEN_SIZE];
+        let leaves = match merkle_tree.leaves(Some(node_path)) {
+            Ok(leaves) => leaves,
+            Err(StateDatabaseError::NotFound(_)) => return Ok(Box::new(std::iter::empty())),
+            Err(err) => return Err(StateReadError::StorageError(Box::new(err))),
+        };
+
+        Ok(Box::new(leaves.filter_map(move |leaf| match leaf {
+            Ok((address, value)) => {
+                if address.starts_with(&prefix) {
+                    Some(Ok((address, value)))
+                } else {
+                    None
+                }
+            }
+            Err(err) => Some(Err(StateReadError::StorageError(Box::new(err)))),
+        })))
+    }
+
     fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
         Box::new(Clone::clone(self))
     }
@@ -147,12 +315,18 @@ impl Prune for MerkleState {
         state_ids
             .iter()
             .try_fold(Vec::new(), |mut result, state_id| {
-                result.extend(MerkleRadixTree::prune(&*self.db, state_id).map_err(
-                    |err| match err {
+                let removed =
+                    MerkleRadixTree::prune(&*self.db, state_id).map_err(|err| match err {
                         StateDatabaseError::NotFound(msg) => StatePruneError::InvalidStateId(msg),
                         _ => StatePruneError::StorageError(Box::new(err)),
-                    },
-                )?);
+                    })?;
+                // Nodes removed from the database must not remain readable from the cache
+                if let Some(cache) = self.cache.as_ref() {
+                    for hash in &removed {
+                        cache.remove(hash);
+                    }
+                }
+                result.extend(removed);
                 Ok(result)
             })
     }
@@ -164,6 +338,8 @@ pub struct MerkleRadixTree {
     root_hash: String,
     db: Box<dyn Database>,
     root_node: Node,
+    cache: Option<NodeCache>,
+    hash_algorithm: HashAlgorithm,
 }
 
 impl MerkleRadixTree {
@@ -174,13 +350,60 @@ impl MerkleRadixTree {
         db: Box<dyn Database>,
         merkle_root: Option<&str>,
     ) -> Result<Self, StateDatabaseError> {
-        let root_hash = merkle_root.map_or_else(|| initialize_db(&*db), |s| Ok(s.into()))?;
-        let root_node = get_node_by_hash(&*db, &root_hash)?;
+        MerkleRadixTree::with_optional_cache(db, merkle_root, None, None)
+    }
+
+    /// Constructs a new MerkleRadixTree, backed by a given Database, whose nodes are addressed by
+    /// the given hash algorithm.
+    ///
+    /// The hash algorithm is recorded in the database, if it is not already; an error is returned
+    /// if the database uses a different one.  Trees constructed with `new` use whichever hash
+    /// algorithm is recorded in the database, or the default one for a new database.
+    ///
+    /// An optional starting merkle root may be provided.
+    pub fn new_with_hash_algorithm(
+        db: Box<dyn Database>,
+        merkle_root: Option<&str>,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, StateDatabaseError> {
+        let hash_algorithm = resolve_hash_algorithm(&*db, Some(hash_algorithm))?;
+        MerkleRadixTree::with_optional_cache(db, merkle_root, None, Some(hash_algorithm))
+    }
+
+    /// Constructs a new MerkleRadixTree, backed by a given Database, which reads nodes through the
+    /// given cache.
+    ///
+    /// An optional starting merkle root may be provided.
+    pub fn new_with_cache(
+        db: Box<dyn Database>,
+        merkle_root: Option<&str>,
+        cache: NodeCache,
+    ) -> Result<Self, StateDatabaseError> {
+        MerkleRadixTree::with_optional_cache(db, merkle_root, Some(cache), None)
+    }
+
+    /// Constructs a tree using the given hash algorithm, if it has already been resolved, or
+    /// otherwise the one recorded in the database.
+    fn with_optional_cache(
+        db: Box<dyn Database>,
+        merkle_root: Option<&str>,
+        cache: Option<NodeCache>,
+        hash_algorithm: Option<HashAlgorithm>,
+    ) -> Result<Self, StateDatabaseError> {
+        let hash_algorithm = match hash_algorithm {
+            Some(hash_algorithm) => hash_algorithm,
+            None => resolve_hash_algorithm(&*db, None)?,
+        };
+        let root_hash =
+            merkle_root.map_or_else(|| initialize_db(&*db, hash_algorithm), |s| Ok(s.into()))?;
+        let root_node = get_cached_node_by_hash(&*db, cache.as_ref(), &root_hash)?;
 
         Ok(MerkleRadixTree {
             root_hash,
             db,
             root_node,
+            cache,
+            hash_algorithm,
         })
     }
 
@@ -235,6 +458,7 @@ impl MerkleRadixTree {
 
                 write_change_log(&mut *db_writer, parent_root_bytes, &parent_change_log)?;
             }
+            merkle_history::remove_root(&mut *db_writer, &root_bytes)?;
 
             deletion_candidates.into_iter().collect()
         } else {
@@ -258,6 +482,7 @@ impl MerkleRadixTree {
             }
 
             db_writer.index_delete(CHANGE_LOG_INDEX, &root_bytes)?;
+            merkle_history::remove_root(&mut *db_writer, &root_bytes)?;
 
             deletion_candidates.into_iter().collect()
         };
@@ -266,6 +491,444 @@ impl MerkleRadixTree {
         Ok(removed_addresses.iter().map(::hex::encode).collect())
     }
 
+    /// Returns the values of an address at each of the roots, up to and including `merkle_root`,
+    /// at which it was changed, oldest first.
+    ///
+    /// The roots are ordered by following the change log back from `merkle_root`, so changes made
+    /// on other forks are not included.  Only changes committed by `update`, and the leaves of
+    /// trees built by `from_sorted_leaves`, are recorded, and none are if the database has no
+    /// history index.  The history stops at the first root which has been pruned.
+    pub fn address_history(
+        db: &dyn Database,
+        merkle_root: &str,
+        address: &str,
+    ) -> Result<Vec<AddressChange>, StateDatabaseError> {
+        let root_bytes = ::hex::decode(merkle_root).map_err(|_| {
+            StateDatabaseError::InvalidHash(format!("{} is not a valid hash", merkle_root))
+        })?;
+        // Ensure the root exists
+        get_node_by_hash(db, merkle_root)?;
+
+        let changed_roots =
+            merkle_history::changed_roots(&*db.get_reader()?, address, &root_bytes)?;
+        changed_roots
+            .into_iter()
+            .map(|root| {
+                let state_id = ::hex::encode(root);
+                let value =
+                    MerkleRadixTree::new(db.clone_box(), Some(&state_id))?.get_value(address)?;
+                Ok(AddressChange { state_id, value })
+            })
+            .collect()
+    }
+
+    /// Returns the last change made to an address at or before `merkle_root`, if any.
+    pub fn last_change(
+        db: &dyn Database,
+        merkle_root: &str,
+        address: &str,
+    ) -> Result<Option<AddressChange>, StateDatabaseError> {
+        Ok(MerkleRadixTree::address_history(db, merkle_root, address)?.pop())
+    }
+
+    /// Returns the changes which transform the tree at `from_root` into the tree at `to_root`,
+    /// ordered by address.
+    ///
+    /// Subtrees with the same hash under both roots are identical, so they are skipped without
+    /// being read.
+    pub fn diff(
+        db: &dyn Database,
+        from_root: &str,
+        to_root: &str,
+    ) -> Result<Vec<StateChange>, StateDatabaseError> {
+        let mut changes = vec![];
+
+        let mut pending = vec![(
+            String::new(),
+            Some(from_root.to_string()),
+            Some(to_root.to_string()),
+        )];
+        while let Some((path, from_hash, to_hash)) = pending.pop() {
+            if from_hash == to_hash {
+                continue;
+            }
+
+            let from_node = match from_hash {
+                Some(hash) => get_node_by_hash(db, &hash)?,
+                None => Node::default(),
+            };
+            let to_node = match to_hash {
+                Some(hash) => get_node_by_hash(db, &hash)?,
+                None => Node::default(),
+            };
+
+            if from_node.value != to_node.value {
+                changes.push(match to_node.value {
+                    Some(value) => StateChange::Set {
+                        key: path.clone(),
+                        value,
+                    },
+                    None => StateChange::Delete { key: path.clone() },
+                });
+            }
+
+            let branches: BTreeSet<&String> = from_node
+                .children
+                .keys()
+                .chain(to_node.children.keys())
+                .collect();
+            // Push in reverse, so that children are visited in address order
+            for branch in branches.into_iter().rev() {
+                pending.push((
+                    format!("{}{}", path, branch),
+                    from_node.children.get(branch).cloned(),
+                    to_node.children.get(branch).cloned(),
+                ));
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Writes a snapshot of the tree at `merkle_root`, with its leaves in address order.
+    ///
+    /// Returns the number of leaves written.
+    pub fn export_snapshot(
+        db: &dyn Database,
+        merkle_root: &str,
+        writer: &mut dyn io::Write,
+    ) -> Result<u64, StateDatabaseError> {
+        let merkle_tree = MerkleRadixTree::new(db.clone_box(), Some(merkle_root))?;
+        let mut snapshot_writer = SnapshotWriter::new(writer, merkle_root)?;
+        for leaf in merkle_tree.leaves(None)? {
+            let (address, value) = leaf?;
+            snapshot_writer.write_entry(&address, &value)?;
+        }
+        snapshot_writer.finish()
+    }
+
+    /// Rebuilds the tree written to a snapshot in the given database, returning its merkle root.
+    ///
+    /// The tree is written in a single database transaction, so nothing is written if the
+    /// snapshot is malformed or its checksum does not match.  An error is returned if the rebuilt
+    /// merkle root does not match the one recorded in the snapshot.
+    pub fn import_snapshot(
+        db: Box<dyn Database>,
+        reader: &mut dyn io::Read,
+    ) -> Result<String, StateDatabaseError> {
+        let mut snapshot_reader = SnapshotReader::new(reader)?;
+        let merkle_root =
+            MerkleRadixTree::from_sorted_leaves(db, &mut snapshot_reader)?.get_merkle_root();
+
+        if merkle_root != snapshot_reader.merkle_root() {
+            return Err(StateDatabaseError::InvalidSnapshot(format!(
+                "rebuilt merkle root {} does not match the snapshot's merkle root {}",
+                merkle_root,
+                snapshot_reader.merkle_root()
+            )));
+        }
+
+        Ok(merkle_root)
+    }
+
+    /// Builds a tree from leaves sorted by address, returning the tree at its merkle root.
+    ///
+    /// Unlike `update`, which rewrites the path from each changed leaf to the root, each node is
+    /// written once, as soon as all of the leaves below it have been added.  The merkle root is the
+    /// same as the one produced by setting the leaves on an empty tree with `update`, and a single
+    /// change log entry is written for it, as a successor of the empty tree.  Each leaf is recorded
+    /// in the address history as set at the merkle root.  If there are no leaves, nothing is
+    /// written, and the tree is at the empty root.
+    ///
+    /// An error is returned if the addresses are not in strictly increasing order, in which case
+    /// nothing is written.
+    pub fn from_sorted_leaves<I>(
+        db: Box<dyn Database>,
+        leaves: I,
+    ) -> Result<Self, StateDatabaseError>
+    where
+        I: IntoIterator<Item = Result<(String, Vec<u8>), StateDatabaseError>>,
+    {
+        let hash_algorithm = resolve_hash_algorithm(&*db, None)?;
+        let empty_root = initialize_db(&*db, hash_algorithm)?;
+
+        let merkle_root = {
+            let mut db_writer = db.get_writer()?;
+            let mut additions = vec![];
+
+            // The nodes on the path to the last leaf added, starting with the root
+            let mut open_nodes = vec![(String::new(), Node::default())];
+            let mut addresses: Vec<String> = vec![];
+            for leaf in leaves {
+                let (address, value) = leaf?;
+                if address.len() % TOKEN_SIZE != 0 {
+                    return Err(StateDatabaseError::InvalidLeaf(format!(
+                        "{} is not a valid address",
+                        address
+                    )));
+                }
+                if let Some(last_address) = addresses.last() {
+                    if address <= *last_address {
+                        return Err(StateDatabaseError::InvalidLeaf(format!(
+                            "{} is not sorted after {}",
+                            address, last_address
+                        )));
+                    }
+                }
+
+                // Any node which is not on the path to this leaf is complete
+                while !address.starts_with(&open_nodes[open_nodes.len() - 1].0) {
+                    close_node(
+                        &mut *db_writer,
+                        &mut open_nodes,
+                        &mut additions,
+                        hash_algorithm,
+                    )?;
+                }
+
+                let mut path = open_nodes[open_nodes.len() - 1].0.clone();
+                for token in tokenize_address(&address[path.len()..]).iter() {
+                    path.push_str(token);
+                    open_nodes.push((path.clone(), Node::default()));
+                }
+                let last = open_nodes.len() - 1;
+                open_nodes[last].1.value = Some(value);
+
+                addresses.push(address);
+            }
+
+            // The empty tree was written when the database was initialized, and is not its own
+            // successor
+            if addresses.is_empty() {
+                drop(db_writer);
+                return MerkleRadixTree::new(db, Some(&empty_root));
+            }
+
+            let mut root_hash = vec![];
+            while !open_nodes.is_empty() {
+                root_hash = close_node(
+                    &mut *db_writer,
+                    &mut open_nodes,
+                    &mut additions,
+                    hash_algorithm,
+                )?;
+            }
+
+            // We expect this to be hex, since we generated it
+            let empty_root_bytes = ::hex::decode(&empty_root).expect("Improper hex");
+            write_successor_change_log(
+                &mut *db_writer,
+                &empty_root_bytes,
+                &root_hash,
+                additions,
+                &[],
+            )?;
+            merkle_history::record_changes(
+                &mut *db_writer,
+                &root_hash,
+                addresses.iter().map(String::as_str),
+            )?;
+            db_writer.commit()?;
+
+            ::hex::encode(root_hash)
+        };
+
+        MerkleRadixTree::new(db, Some(&merkle_root))
+    }
+
+    /// Checks the integrity of the trees in a database, returning a report of the problems found.
+    ///
+    /// The tree at the given merkle root is checked, or, if no root is given, the trees at every
+    /// root in the change log.  Every reachable node is fetched and its hash recomputed, and the
+    /// ref counts in the duplicate log are checked against the change log.  A ref count lower than
+    /// the number of change log entries which added a node, less one, would allow pruning to
+    /// delete a node which is still in use; a higher one only delays its deletion.  Orphaned
+    /// nodes, which are not reachable from any root, are only looked for when all roots are
+    /// checked.
+    ///
+    /// If `repair` is set, the ref counts are corrected and orphaned nodes are deleted.  Corrupt
+    /// and missing nodes cannot be repaired, and as the nodes below them are still needed if the
+    /// tree is restored, orphaned nodes are only deleted if no nodes are corrupt or missing.
+    pub fn check_integrity(
+        db: &dyn Database,
+        merkle_root: Option<&str>,
+        repair: bool,
+    ) -> Result<IntegrityReport, StateDatabaseError> {
+        let hash_algorithm = resolve_hash_algorithm(db, None)?;
+        let mut report = IntegrityReport::default();
+        // The orphaned nodes which have a ref count
+        let mut orphaned_ref_counts = HashSet::new();
+
+        {
+            let db_reader = db.get_reader()?;
+
+            let mut change_logs = vec![];
+            for (root_hash, bytes) in db_reader.index_cursor(CHANGE_LOG_INDEX)? {
+                change_logs.push((
+                    ::hex::encode(root_hash),
+                    ChangeLogEntry::from_bytes(&bytes)?,
+                ));
+            }
+
+            report.roots = match merkle_root {
+                Some(merkle_root) => vec![merkle_root.to_string()],
+                None => {
+                    let mut roots: Vec<String> =
+                        change_logs.iter().map(|(root, _)| root.clone()).collect();
+                    // The empty tree has no change log entry of its own
+                    let (empty_hash, _) = encode_and_hash(Node::default(), hash_algorithm)?;
+                    let empty_root = ::hex::encode(empty_hash);
+                    if db_reader.get(empty_root.as_bytes()).is_some() {
+                        roots.push(empty_root);
+                    }
+                    roots
+                }
+            };
+
+            let mut visited = HashSet::new();
+            let mut pending: Vec<(Option<String>, String)> = report
+                .roots
+                .iter()
+                .map(|root| (None, root.clone()))
+                .collect();
+            while let Some((parent, node_hash)) = pending.pop() {
+                if !visited.insert(node_hash.clone()) {
+                    continue;
+                }
+
+                let bytes = match db_reader.get(node_hash.as_bytes()) {
+                    Some(bytes) => bytes,
+                    None => {
+                        report.missing_nodes.push(MissingNode {
+                            parent,
+                            hash: node_hash,
+                        });
+                        continue;
+                    }
+                };
+                report.reachable_nodes += 1;
+
+                if ::hex::encode(hash_algorithm.hash(&bytes)?) != node_hash {
+                    report.corrupt_nodes.push(node_hash);
+                    continue;
+                }
+                match Node::from_bytes(&bytes) {
+                    Ok(node) => pending.extend(
+                        node.children
+                            .values()
+                            .map(|child| (Some(node_hash.clone()), child.clone())),
+                    ),
+                    Err(_) => report.corrupt_nodes.push(node_hash),
+                }
+            }
+
+            // The number of change log entries which added each node
+            let mut additions: HashMap<Vec<u8>, u64> = HashMap::new();
+            for (_, change_log) in &change_logs {
+                let added: HashSet<&Vec<u8>> = change_log.additions.iter().collect();
+                for node_hash in added {
+                    *additions.entry(node_hash.clone()).or_insert(0) += 1;
+                }
+            }
+            for (node_hash, count) in &additions {
+                let recorded = db_reader
+                    .index_get(DUPLICATE_LOG_INDEX, node_hash)?
+                    .map_or(Some(0), |bytes| ref_count_from_bytes(&bytes));
+                let under_counted = match recorded {
+                    Some(recorded) => recorded < count - 1,
+                    None => true,
+                };
+                if under_counted {
+                    report.ref_count_errors.push(RefCountError {
+                        hash: ::hex::encode(node_hash),
+                        recorded,
+                        expected: count - 1,
+                    });
+                }
+            }
+            for (node_hash, bytes) in db_reader.index_cursor(DUPLICATE_LOG_INDEX)? {
+                if db_reader
+                    .get(::hex::encode(&node_hash).as_bytes())
+                    .is_none()
+                {
+                    report.dangling_ref_counts.push(::hex::encode(&node_hash));
+                } else if ref_count_from_bytes(&bytes).is_none()
+                    && !additions.contains_key(&node_hash)
+                {
+                    report.ref_count_errors.push(RefCountError {
+                        hash: ::hex::encode(&node_hash),
+                        recorded: None,
+                        expected: 0,
+                    });
+                }
+            }
+
+            if merkle_root.is_none() {
+                for (key, _) in db_reader.cursor()? {
+                    let node_hash = String::from_utf8_lossy(&key).into_owned();
+                    if !visited.contains(&node_hash) {
+                        if let Ok(hash_bytes) = ::hex::decode(&node_hash) {
+                            if db_reader
+                                .index_get(DUPLICATE_LOG_INDEX, &hash_bytes)?
+                                .is_some()
+                            {
+                                orphaned_ref_counts.insert(hash_bytes);
+                            }
+                        }
+                        report.orphaned_nodes.push(node_hash);
+                    }
+                }
+            }
+        }
+
+        report.corrupt_nodes.sort();
+        report.missing_nodes.sort_by(|a, b| a.hash.cmp(&b.hash));
+        report.orphaned_nodes.sort();
+        report.ref_count_errors.sort_by(|a, b| a.hash.cmp(&b.hash));
+        report.dangling_ref_counts.sort();
+
+        if repair {
+            let mut db_writer = db.get_writer()?;
+
+            let mut deleted = HashSet::new();
+            if report.corrupt_nodes.is_empty() && report.missing_nodes.is_empty() {
+                for node_hash in &report.orphaned_nodes {
+                    delete_ignore_missing(&mut *db_writer, node_hash.as_bytes())?;
+                    deleted.insert(node_hash.clone());
+                }
+                for node_hash in &orphaned_ref_counts {
+                    db_writer.index_delete(DUPLICATE_LOG_INDEX, node_hash)?;
+                }
+            }
+
+            for error in &report.ref_count_errors {
+                if deleted.contains(&error.hash) {
+                    continue;
+                }
+                // We expect this to be hex, since we generated it
+                let node_hash = ::hex::decode(&error.hash).expect("Improper hex");
+                if error.expected == 0 {
+                    db_writer.index_delete(DUPLICATE_LOG_INDEX, &node_hash)?;
+                } else {
+                    db_writer.index_put(
+                        DUPLICATE_LOG_INDEX,
+                        &node_hash,
+                        &to_bytes(error.expected),
+                    )?;
+                }
+            }
+            for node_hash in &report.dangling_ref_counts {
+                let node_hash = ::hex::decode(node_hash).expect("Improper hex");
+                db_writer.index_delete(DUPLICATE_LOG_INDEX, &node_hash)?;
+            }
+
+            db_writer.commit()?;
+            report.repaired = true;
+        }
+
+        Ok(report)
+    }
+
     fn remove_duplicate_hashes(
         db_reader: &dyn DatabaseReader,
         deletions: Vec<Vec<u8>>,
@@ -278,6 +941,11 @@ impl MerkleRadixTree {
             }
         }))
     }
+    /// Returns the hash algorithm used to address the nodes of this MerkleRadixTree
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
     /// Returns the current merkle root for this MerkleRadixTree
     pub fn get_merkle_root(&self) -> String {
         self.root_hash.clone()
@@ -289,7 +957,7 @@ impl MerkleRadixTree {
         merkle_root: S,
     ) -> Result<(), StateDatabaseError> {
         let new_root = merkle_root.into();
-        self.root_node = get_node_by_hash(&*self.db, &new_root)?;
+        self.root_node = self.get_node(&new_root)?;
         self.root_hash = new_root;
         Ok(())
     }
@@ -390,7 +1058,7 @@ impl MerkleRadixTree {
             let node = path_map
                 .remove(&path)
                 .expect("Path map keys are out of sink");
-            let (hash_key, packed) = encode_and_hash(node)?;
+            let (hash_key, packed) = encode_and_hash(node, self.hash_algorithm)?;
             key_hash = hash_key.clone();
 
             if path != "" {
@@ -415,18 +1083,20 @@ impl MerkleRadixTree {
                 // We expect this to be hex, since we generated it
                 .map(|s| ::hex::decode(s).expect("Improper hex"))
                 .collect();
-            self.store_changes(&key_hash, &batch, &deletions)?;
+            self.store_changes(&key_hash, &batch, &deletions, state_changes)?;
         }
 
         Ok(::hex::encode(key_hash))
     }
 
-    /// Puts all the items into the database.
+    /// Puts all the items into the database, and records the state changes in the address
+    /// history.
     fn store_changes(
         &self,
         successor_root_hash: &[u8],
         batch: &[(Vec<u8>, Vec<u8>)],
         deletions: &[Vec<u8>],
+        state_changes: &[StateChange],
     ) -> Result<(), StateDatabaseError> {
         let mut db_writer = self.db.get_writer()?;
 
@@ -443,32 +1113,24 @@ impl MerkleRadixTree {
             }
         }
 
-        let mut current_change_log = get_change_log(db_writer.as_reader(), &root_hash_bytes)?;
-        if let Some(change_log) = current_change_log.as_mut() {
-            let successor = Successor {
-                successor: Vec::from(successor_root_hash),
-                deletions: deletions.to_vec(),
-            };
-            change_log.successors.push(successor);
-        }
-
-        let next_change_log = ChangeLogEntry {
-            parent: root_hash_bytes.clone(),
-            additions: batch
-                .iter()
-                .map(|&(ref hash, _)| hash.clone())
-                .collect::<Vec<Vec<u8>>>(),
-            successors: vec![],
-        };
-
-        if current_change_log.is_some() {
-            write_change_log(
-                &mut *db_writer,
-                &root_hash_bytes,
-                &current_change_log.unwrap(),
-            )?;
-        }
-        write_change_log(&mut *db_writer, successor_root_hash, &next_change_log)?;
+        let additions = batch
+            .iter()
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<Vec<u8>>>();
+        write_successor_change_log(
+            &mut *db_writer,
+            &root_hash_bytes,
+            successor_root_hash,
+            additions,
+            deletions,
+        )?;
+        merkle_history::record_changes(
+            &mut *db_writer,
+            successor_root_hash,
+            state_changes.iter().map(|state_change| match state_change {
+                StateChange::Set { key, .. } | StateChange::Delete { key } => key.as_str(),
+            }),
+        )?;
 
         db_writer.commit()?;
         Ok(())
@@ -482,6 +1144,10 @@ impl MerkleRadixTree {
         }
     }
 
+    fn get_node(&self, hash: &str) -> Result<Node, StateDatabaseError> {
+        get_cached_node_by_hash(&*self.db, self.cache.as_ref(), hash)
+    }
+
     fn get_by_address(&self, address: &str) -> Result<Node, StateDatabaseError> {
         let tokens = tokenize_address(address);
 
@@ -497,12 +1163,35 @@ impl MerkleRadixTree {
                         self.root_hash.clone()
                     )));
                 }
-                Some(child_hash) => get_node_by_hash(&*self.db, child_hash)?,
+                Some(child_hash) => self.get_node(child_hash)?,
             }
         }
         Ok(node)
     }
 
+    /// Returns a proof of the value at the given address, which may be checked against the
+    /// current merkle root with `verify_proof`.
+    ///
+    /// The proof is the list of CBOR-encoded nodes on the path from the root to the node at the
+    /// address.  If there is no node at the address, the proof ends with the deepest node on the
+    /// path, which proves that the address is not set.
+    pub fn get_proof(&self, address: &str) -> Result<Vec<Vec<u8>>, StateDatabaseError> {
+        let mut proof = vec![get_node_bytes_by_hash(&*self.db, &self.root_hash)?];
+        let mut node = self.root_node.clone();
+
+        for token in tokenize_address(address).iter() {
+            let child_hash = match node.children.get(*token) {
+                Some(child_hash) => child_hash.clone(),
+                None => break,
+            };
+            let child_bytes = get_node_bytes_by_hash(&*self.db, &child_hash)?;
+            node = Node::from_bytes(&child_bytes)?;
+            proof.push(child_bytes);
+        }
+
+        Ok(proof)
+    }
+
     pub fn contains(&self, address: &str) -> Result<bool, StateDatabaseError> {
         match self.get_by_address(address) {
             Ok(_) => Ok(true),
@@ -533,7 +1222,7 @@ impl MerkleRadixTree {
                 let child_address = &nodes[&path].children.get(&token.to_string());
 
                 match (!new_branch && child_address.is_some(), strict) {
-                    (true, _) => get_node_by_hash(&*self.db, child_address.unwrap())?,
+                    (true, _) => self.get_node(child_address.unwrap())?,
                     (false, true) => {
                         return Err(StateDatabaseError::NotFound(format!(
                             "invalid address {} from root {}",
@@ -591,7 +1280,7 @@ impl Iterator for MerkleLeafIterator {
                 // Reverse the list, such that we have an in-order traversal of the
                 // children, based on the natural path order.
                 for (child_path, hash_key) in node.children.iter().rev() {
-                    let child = match get_node_by_hash(&*self.merkle_db.db, hash_key) {
+                    let child = match self.merkle_db.get_node(hash_key) {
                         Ok(node) => node,
                         Err(err) => return Some(Err(err)),
                     };
@@ -606,9 +1295,278 @@ impl Iterator for MerkleLeafIterator {
     }
 }
 
+/// The result of `MerkleRadixTree::check_integrity`.
+#[derive(Debug, Default, PartialEq)]
+pub struct IntegrityReport {
+    /// The merkle roots which were checked
+    pub roots: Vec<String>,
+    /// The number of nodes reachable from the roots, which are in the database
+    pub reachable_nodes: usize,
+    /// The hashes of nodes whose contents do not match their hash, or cannot be decoded
+    pub corrupt_nodes: Vec<String>,
+    /// Nodes which are reachable from the roots, but missing from the database
+    pub missing_nodes: Vec<MissingNode>,
+    /// The hashes of nodes which are not reachable from any root
+    pub orphaned_nodes: Vec<String>,
+    /// Ref counts which are lower than the change log requires, or malformed
+    pub ref_count_errors: Vec<RefCountError>,
+    /// The hashes of nodes which have a ref count, but are missing from the database
+    pub dangling_ref_counts: Vec<String>,
+    /// Whether the problems found were repaired, as far as possible
+    pub repaired: bool,
+}
+
+impl IntegrityReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_nodes.is_empty()
+            && self.missing_nodes.is_empty()
+            && self.orphaned_nodes.is_empty()
+            && self.ref_count_errors.is_empty()
+            && self.dangling_ref_counts.is_empty()
+    }
+}
+
+/// A node which is reachable from a merkle root, but missing from the database.
+#[derive(Debug, PartialEq)]
+pub struct MissingNode {
+    /// The hash of the node which references the missing node, or None if it is a root
+    pub parent: Option<String>,
+    pub hash: String,
+}
+
+/// A node whose ref count is lower than the change log requires, or malformed.
+#[derive(Debug, PartialEq)]
+pub struct RefCountError {
+    pub hash: String,
+    /// The ref count in the duplicate log, or None if it is malformed
+    pub recorded: Option<u64>,
+    /// The lowest ref count which is consistent with the change log
+    pub expected: u64,
+}
+
+/// Verifies a proof returned by `MerkleRadixTree::get_proof`, without access to the database.
+///
+/// Returns the value at the address, if the proof shows that the address is set in the tree with
+/// the given merkle root, or `None` if the proof shows that it is not set.  An error is returned
+/// if the proof is not valid for the root and address.  The hash algorithm must be the one used
+/// by the tree's database.
+pub fn verify_proof(
+    merkle_root: &str,
+    address: &str,
+    proof: &[Vec<u8>],
+    hash_algorithm: HashAlgorithm,
+) -> Result<Option<Vec<u8>>, StateDatabaseError> {
+    let tokens = tokenize_address(address);
+    if proof.is_empty() || proof.len() > tokens.len() + 1 {
+        return Err(StateDatabaseError::InvalidProof(format!(
+            "a proof for {} must have between 1 and {} nodes, but has {}",
+            address,
+            tokens.len() + 1,
+            proof.len()
+        )));
+    }
+
+    let mut expected_hash = merkle_root.to_string();
+    let mut node = Node::default();
+    for (depth, node_bytes) in proof.iter().enumerate() {
+        if ::hex::encode(hash_algorithm.hash(node_bytes)?) != expected_hash {
+            return Err(StateDatabaseError::InvalidProof(format!(
+                "node {} of the proof for {} does not match its expected hash {}",
+                depth, address, expected_hash
+            )));
+        }
+        node = Node::from_bytes(node_bytes)?;
+
+        if let Some(token) = tokens.get(depth) {
+            match node.children.get(*token) {
+                Some(child_hash) => expected_hash = child_hash.clone(),
+                None if depth == proof.len() - 1 => return Ok(None),
+                None => {
+                    return Err(StateDatabaseError::InvalidProof(format!(
+                        "node {} of the proof for {} has no child {}",
+                        depth, address, token
+                    )))
+                }
+            }
+        }
+    }
+
+    if proof.len() <= tokens.len() {
+        return Err(StateDatabaseError::InvalidProof(format!(
+            "the proof for {} ends before the deepest node on its path",
+            address
+        )));
+    }
+
+    Ok(node.value)
+}
+
+/// The hash function used to address the nodes of a tree.
+///
+/// Every tree in a database uses the same hash algorithm, which is recorded in the database's
+/// metadata index when one is requested.  Databases without a recorded hash algorithm, including
+/// those written before it was recorded and those without a metadata index, use the default,
+/// `Sha512Half`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    /// The first 32 bytes of a SHA-512 digest
+    Sha512Half,
+    Sha256,
+    Sha512,
+    /// BLAKE2b, with a 64-byte digest
+    Blake2b512,
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        HashAlgorithm::Sha512Half
+    }
+}
+
+impl HashAlgorithm {
+    /// Returns the name under which the hash algorithm is recorded in a database.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha512Half => "sha512-half",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Blake2b512 => "blake2b512",
+        }
+    }
+
+    /// Returns the hash algorithm with the given name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha512-half" => Some(HashAlgorithm::Sha512Half),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            "blake2b512" => Some(HashAlgorithm::Blake2b512),
+            _ => None,
+        }
+    }
+
+    /// Creates a hash of the given bytes
+    fn hash(self, input: &[u8]) -> Result<Vec<u8>, StateDatabaseError> {
+        Ok(match self {
+            HashAlgorithm::Sha512Half => openssl::sha::sha512(input)[..32].to_vec(),
+            HashAlgorithm::Sha256 => openssl::sha::sha256(input).to_vec(),
+            HashAlgorithm::Sha512 => openssl::sha::sha512(input).to_vec(),
+            HashAlgorithm::Blake2b512 => {
+                let digest =
+                    openssl::hash::MessageDigest::from_name("BLAKE2b512").ok_or_else(|| {
+                        StateDatabaseError::InvalidHashAlgorithm(
+                            "BLAKE2b is not supported by this version of OpenSSL".into(),
+                        )
+                    })?;
+                openssl::hash::hash(digest, input)
+                    .map_err(|err| {
+                        StateDatabaseError::InvalidHashAlgorithm(format!(
+                            "Unable to compute BLAKE2b hash: {}",
+                            err
+                        ))
+                    })?
+                    .to_vec()
+            }
+        })
+    }
+}
+
+/// Returns the hash algorithm recorded in the database, or the default one if none is recorded.
+///
+/// A requested hash algorithm is recorded if none has been, but nothing is written if none is
+/// requested, so that trees which are only read do not write to the database.  An error is
+/// returned if the requested hash algorithm is not the one the database uses.
+fn resolve_hash_algorithm(
+    db: &dyn Database,
+    requested: Option<HashAlgorithm>,
+) -> Result<HashAlgorithm, StateDatabaseError> {
+    let requested = match (read_hash_algorithm(&*db.get_reader()?)?, requested) {
+        (Some(recorded), requested) => return check_hash_algorithm(recorded, requested),
+        (None, None) => return Ok(HashAlgorithm::default()),
+        (None, Some(requested)) => requested,
+    };
+
+    let mut db_writer = db.get_writer()?;
+    // The database may have been written to since it was read
+    if let Some(recorded) = read_hash_algorithm(db_writer.as_reader())? {
+        return check_hash_algorithm(recorded, Some(requested));
+    }
+    if requested != HashAlgorithm::default() && db_writer.as_reader().count()? > 0 {
+        return Err(StateDatabaseError::InvalidHashAlgorithm(format!(
+            "the database already has nodes using {}",
+            HashAlgorithm::default().name()
+        )));
+    }
+    match db_writer.index_put(
+        METADATA_INDEX,
+        HASH_ALGORITHM_KEY,
+        requested.name().as_bytes(),
+    ) {
+        Ok(()) => db_writer.commit()?,
+        // A database without a metadata index can only use the default
+        Err(ref err)
+            if is_missing_index(err, METADATA_INDEX) && requested == HashAlgorithm::default() => {}
+        Err(ref err) if is_missing_index(err, METADATA_INDEX) => {
+            return Err(StateDatabaseError::InvalidHashAlgorithm(format!(
+                "the database has no {} index in which to record {}",
+                METADATA_INDEX,
+                requested.name()
+            )));
+        }
+        Err(err) => return Err(StateDatabaseError::from(err)),
+    }
+
+    Ok(requested)
+}
+
+/// Returns the recorded hash algorithm, or an error if a different one was requested.
+fn check_hash_algorithm(
+    recorded: HashAlgorithm,
+    requested: Option<HashAlgorithm>,
+) -> Result<HashAlgorithm, StateDatabaseError> {
+    match requested {
+        Some(requested) if requested != recorded => {
+            Err(StateDatabaseError::InvalidHashAlgorithm(format!(
+                "the database uses {}, not {}",
+                recorded.name(),
+                requested.name()
+            )))
+        }
+        _ => Ok(recorded),
+    }
+}
+
+fn read_hash_algorithm(
+    db_reader: &dyn DatabaseReader,
+) -> Result<Option<HashAlgorithm>, StateDatabaseError> {
+    let bytes = match db_reader.index_get(METADATA_INDEX, HASH_ALGORITHM_KEY) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return Ok(None),
+        Err(ref err) if is_missing_index(err, METADATA_INDEX) => return Ok(None),
+        Err(err) => return Err(StateDatabaseError::from(err)),
+    };
+    let name = String::from_utf8_lossy(&bytes);
+    HashAlgorithm::from_name(&name).map(Some).ok_or_else(|| {
+        StateDatabaseError::InvalidHashAlgorithm(format!("unknown hash algorithm {}", name))
+    })
+}
+
+/// Returns true if the error was returned because the database was not opened with the given
+/// index, as databases created before the index was added are not.
+pub(super) fn is_missing_index(err: &DatabaseError, index: &str) -> bool {
+    match err {
+        DatabaseError::MissingIndex(missing) => missing == index,
+        _ => false,
+    }
+}
+
 /// Initializes a database with an empty Trie
-fn initialize_db(db: &dyn Database) -> Result<String, StateDatabaseError> {
-    let (hash, packed) = encode_and_hash(Node::default())?;
+fn initialize_db(
+    db: &dyn Database,
+    hash_algorithm: HashAlgorithm,
+) -> Result<String, StateDatabaseError> {
+    let (hash, packed) = encode_and_hash(Node::default(), hash_algorithm)?;
 
     let mut db_writer = db.get_writer()?;
     let hex_hash = ::hex::encode(hash);
@@ -619,6 +1577,36 @@ fn initialize_db(db: &dyn Database) -> Result<String, StateDatabaseError> {
     Ok(hex_hash)
 }
 
+/// Writes the change log entry for a successor of the given root, and adds the successor to the
+/// root's change log entry, if it has one.
+fn write_successor_change_log(
+    db_writer: &mut dyn DatabaseWriter,
+    root_hash: &[u8],
+    successor_root_hash: &[u8],
+    additions: Vec<Vec<u8>>,
+    deletions: &[Vec<u8>],
+) -> Result<(), StateDatabaseError> {
+    let mut current_change_log = get_change_log(db_writer.as_reader(), root_hash)?;
+    if let Some(change_log) = current_change_log.as_mut() {
+        let successor = Successor {
+            successor: Vec::from(successor_root_hash),
+            deletions: deletions.to_vec(),
+        };
+        change_log.successors.push(successor);
+    }
+
+    let next_change_log = ChangeLogEntry {
+        parent: root_hash.to_vec(),
+        additions,
+        successors: vec![],
+    };
+
+    if let Some(change_log) = current_change_log {
+        write_change_log(db_writer, root_hash, &change_log)?;
+    }
+    write_change_log(db_writer, successor_root_hash, &next_change_log)
+}
+
 /// Returns the change log entry for a given root hash.
 fn get_change_log(
     db_reader: &dyn DatabaseReader,
@@ -689,6 +1677,15 @@ fn from_bytes(bytes: &[u8]) -> u64 {
     u64::from_le(unsafe { ::std::mem::transmute(num_bytes) })
 }
 
+/// Converts a ref count from the duplicate log, returning None if it is malformed
+fn ref_count_from_bytes(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() == 8 {
+        Some(from_bytes(bytes))
+    } else {
+        None
+    }
+}
+
 /// This delete ignores any MDB_NOTFOUND errors
 fn delete_ignore_missing(
     db_writer: &mut dyn DatabaseWriter,
@@ -710,10 +1707,43 @@ fn delete_ignore_missing(
     }
 }
 
+/// Writes the last of the open nodes, which must have all of its children, and adds it to its
+/// parent.  Returns the node's hash.
+fn close_node(
+    db_writer: &mut dyn DatabaseWriter,
+    open_nodes: &mut Vec<(String, Node)>,
+    additions: &mut Vec<Vec<u8>>,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<u8>, StateDatabaseError> {
+    let (path, node) = open_nodes.pop().expect("No open node to close");
+    let (hash_key, packed) = encode_and_hash(node, hash_algorithm)?;
+
+    match db_writer.put(::hex::encode(&hash_key).as_bytes(), &packed) {
+        Ok(_) => (),
+        Err(DatabaseError::DuplicateEntry) => {
+            increment_ref_count(db_writer, &hash_key)?;
+        }
+        Err(err) => return Err(StateDatabaseError::from(err)),
+    }
+    additions.push(hash_key.clone());
+
+    if let Some((_, parent)) = open_nodes.last_mut() {
+        let (_, path_branch) = parent_and_branch(&path);
+        parent
+            .children
+            .insert(path_branch.to_string(), ::hex::encode(&hash_key));
+    }
+
+    Ok(hash_key)
+}
+
 /// Encodes the given node, and returns the hash of the bytes.
-fn encode_and_hash(node: Node) -> Result<(Vec<u8>, Vec<u8>), StateDatabaseError> {
+fn encode_and_hash(
+    node: Node,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(Vec<u8>, Vec<u8>), StateDatabaseError> {
     let packed = node.into_bytes()?;
-    let hash = hash(&packed);
+    let hash = hash_algorithm.hash(&packed)?;
     Ok((hash, packed))
 }
 
@@ -746,19 +1776,130 @@ fn tokenize_address(address: &str) -> Box<[&str]> {
     tokens.into_boxed_slice()
 }
 
-/// Fetch a node by its hash
-fn get_node_by_hash(db: &dyn Database, hash: &str) -> Result<Node, StateDatabaseError> {
-    match db.get_reader()?.get(hash.as_bytes()) {
-        Some(bytes) => Node::from_bytes(&bytes),
-        None => Err(StateDatabaseError::NotFound(hash.to_string())),
+/// Fetch a node by its hash
+fn get_node_by_hash(db: &dyn Database, hash: &str) -> Result<Node, StateDatabaseError> {
+    match db.get_reader()?.get(hash.as_bytes()) {
+        Some(bytes) => Node::from_bytes(&bytes),
+        None => Err(StateDatabaseError::NotFound(hash.to_string())),
+    }
+}
+
+/// Fetch a node by its hash, checking the cache, if any, before the database
+fn get_cached_node_by_hash(
+    db: &dyn Database,
+    cache: Option<&NodeCache>,
+    hash: &str,
+) -> Result<Node, StateDatabaseError> {
+    match cache {
+        Some(cache) => {
+            if let Some(node) = cache.get(hash) {
+                return Ok(node);
+            }
+            let node = get_node_by_hash(db, hash)?;
+            cache.insert(hash, node.clone());
+            Ok(node)
+        }
+        None => get_node_by_hash(db, hash),
+    }
+}
+
+/// Fetch the encoded bytes of a node by its hash
+fn get_node_bytes_by_hash(db: &dyn Database, hash: &str) -> Result<Vec<u8>, StateDatabaseError> {
+    db.get_reader()?
+        .get(hash.as_bytes())
+        .ok_or_else(|| StateDatabaseError::NotFound(hash.to_string()))
+}
+
+/// Internal Node structure of the Radix tree
+#[derive(Default, Debug, PartialEq, Clone)]
+struct Node {
+    value: Option<Vec<u8>>,
+    children: BTreeMap<String, String>,
+}
+
+/// A bounded cache of decoded nodes, keyed by their hashes.
+///
+/// Nodes are addressed by the hash of their contents, so a cached node never becomes stale; once
+/// the cache is full, the least recently used node is evicted.  Clones of a cache share the same
+/// cached nodes, so a cache may be shared by many trees or MerkleStates, across threads.
+#[derive(Clone)]
+pub struct NodeCache {
+    inner: Arc<Mutex<NodeCacheInner>>,
+}
+
+struct NodeCacheInner {
+    capacity: usize,
+    // The cached nodes, with the tick at which they were last used
+    nodes: HashMap<String, (Node, u64)>,
+    // The hashes of the cached nodes, ordered from least to most recently used
+    usage: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl NodeCache {
+    /// Constructs a cache which holds at most `capacity` nodes.
+    pub fn new(capacity: usize) -> Self {
+        NodeCache {
+            inner: Arc::new(Mutex::new(NodeCacheInner {
+                capacity,
+                nodes: HashMap::new(),
+                usage: BTreeMap::new(),
+                tick: 0,
+            })),
+        }
+    }
+
+    /// Returns the number of cached nodes.
+    pub fn len(&self) -> usize {
+        self.lock().nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, hash: &str) -> Option<Node> {
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let last_used = match inner.nodes.get_mut(hash) {
+            Some((_, last_used)) => std::mem::replace(last_used, tick),
+            None => return None,
+        };
+        inner.usage.remove(&last_used);
+        inner.usage.insert(tick, hash.to_string());
+        inner.nodes.get(hash).map(|(node, _)| node.clone())
+    }
+
+    fn insert(&self, hash: &str, node: Node) {
+        let mut inner = self.lock();
+        if inner.capacity == 0 {
+            return;
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some((_, last_used)) = inner.nodes.insert(hash.to_string(), (node, tick)) {
+            inner.usage.remove(&last_used);
+        }
+        inner.usage.insert(tick, hash.to_string());
+
+        while inner.nodes.len() > inner.capacity {
+            let least_used = *inner.usage.keys().next().expect("Usage out of sync");
+            let evicted = inner.usage.remove(&least_used).expect("Usage out of sync");
+            inner.nodes.remove(&evicted);
+        }
+    }
+
+    fn remove(&self, hash: &str) {
+        let mut inner = self.lock();
+        if let Some((_, last_used)) = inner.nodes.remove(hash) {
+            inner.usage.remove(&last_used);
+        }
     }
-}
 
-/// Internal Node structure of the Radix tree
-#[derive(Default, Debug, PartialEq, Clone)]
-struct Node {
-    value: Option<Vec<u8>>,
-    children: BTreeMap<String, String>,
+    fn lock(&self) -> MutexGuard<'_, NodeCacheInner> {
+        self.inner.lock().expect("Node cache lock was poisoned")
+    }
 }
 
 impl Node {
@@ -844,14 +1985,6 @@ fn text_to_string(text_val: Value) -> Result<String, StateDatabaseError> {
     }
 }
 
-/// Creates a hash of the given bytes
-fn hash(input: &[u8]) -> Vec<u8> {
-    let mut bytes: Vec<u8> = Vec::new();
-    bytes.extend(openssl::sha::sha512(input).iter());
-    let (hash, _rest) = bytes.split_at(bytes.len() / 2);
-    hash.to_vec()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -937,7 +2070,7 @@ mod tests {
     fn merkle_trie_root_advance() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let orig_root = merkle_db.get_merkle_root();
@@ -988,7 +2121,7 @@ mod tests {
     fn merkle_trie_delete() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let state_change_set = StateChange::Set {
@@ -1030,7 +2163,7 @@ mod tests {
     fn merkle_trie_update() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let init_root = merkle_db.get_merkle_root();
@@ -1121,7 +2254,7 @@ mod tests {
     fn merkle_trie_update_same_address_space() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let init_root = merkle_db.get_merkle_root();
@@ -1242,7 +2375,7 @@ mod tests {
     fn merkle_trie_update_same_address_space_with_no_children() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let init_root = merkle_db.get_merkle_root();
@@ -1368,7 +2501,7 @@ mod tests {
     fn merkle_trie_pruning_parent() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
 
@@ -1471,7 +2604,7 @@ mod tests {
     fn merkle_trie_pruning_successors() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
 
@@ -1554,7 +2687,7 @@ mod tests {
     fn merkle_trie_pruning_duplicate_leaves() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
             updates.push(StateChange::Set {
@@ -1630,7 +2763,7 @@ mod tests {
     fn merkle_trie_pruning_successor_duplicate_leaves() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
 
@@ -1745,6 +2878,852 @@ mod tests {
         })
     }
 
+    #[test]
+    fn merkle_proofs() {
+        let mut merkle_db =
+            MerkleRadixTree::new(Box::new(BTreeDatabase::new(&INDEXES)), None).unwrap();
+        let updates = vec![
+            StateChange::Set {
+                key: "ab0000".to_string(),
+                value: "0001".as_bytes().to_vec(),
+            },
+            StateChange::Set {
+                key: "ab0a01".to_string(),
+                value: "0002".as_bytes().to_vec(),
+            },
+            StateChange::Set {
+                key: "abff00".to_string(),
+                value: "0003".as_bytes().to_vec(),
+            },
+        ];
+        let root = merkle_db.update(&updates, false).unwrap();
+        merkle_db.set_merkle_root(root.clone()).unwrap();
+        let hash_algorithm = merkle_db.hash_algorithm();
+
+        // Inclusion
+        let proof = merkle_db.get_proof("ab0a01").unwrap();
+        assert_eq!(4, proof.len());
+        assert_eq!(
+            Some("0002".as_bytes().to_vec()),
+            verify_proof(&root, "ab0a01", &proof, hash_algorithm).unwrap()
+        );
+
+        // Exclusion, where the path ends at an existing intermediate node
+        let proof = merkle_db.get_proof("ab0a02").unwrap();
+        assert_eq!(3, proof.len());
+        assert_eq!(
+            None,
+            verify_proof(&root, "ab0a02", &proof, hash_algorithm).unwrap()
+        );
+
+        // Exclusion, where only the root is on the path
+        let proof = merkle_db.get_proof("cd0000").unwrap();
+        assert_eq!(1, proof.len());
+        assert_eq!(
+            None,
+            verify_proof(&root, "cd0000", &proof, hash_algorithm).unwrap()
+        );
+
+        // A proof is only valid for its own root and address
+        let proof = merkle_db.get_proof("ab0a01").unwrap();
+        assert!(verify_proof(&merkle_db_empty_root(), "ab0a01", &proof, hash_algorithm).is_err());
+        assert!(verify_proof(&root, "ab0000", &proof, hash_algorithm).is_err());
+
+        // A truncated proof can't be used to claim an address is not set
+        assert!(verify_proof(&root, "ab0a01", &proof[..2], hash_algorithm).is_err());
+
+        // A proof with a modified value does not match the root
+        let mut forged_proof = proof.clone();
+        let mut leaf = Node::from_bytes(&forged_proof[3]).unwrap();
+        leaf.value = Some("9999".as_bytes().to_vec());
+        forged_proof[3] = leaf.into_bytes().unwrap();
+        assert!(verify_proof(&root, "ab0a01", &forged_proof, hash_algorithm).is_err());
+    }
+
+    #[test]
+    fn merkle_hash_algorithms() {
+        // The default hash algorithm produces the same roots as before it was selectable
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let merkle_db =
+            MerkleRadixTree::new_with_hash_algorithm(db.clone(), None, HashAlgorithm::Sha512Half)
+                .unwrap();
+        assert_eq!(merkle_db_empty_root(), merkle_db.get_merkle_root());
+        assert_eq!(64, merkle_db.get_merkle_root().len());
+
+        let updates = vec![StateChange::Set {
+            key: "ab0000".to_string(),
+            value: "0001".as_bytes().to_vec(),
+        }];
+        let mut roots = HashSet::new();
+        for (hash_algorithm, hash_len) in &[
+            (HashAlgorithm::Sha512Half, 64),
+            (HashAlgorithm::Sha256, 64),
+            (HashAlgorithm::Sha512, 128),
+            (HashAlgorithm::Blake2b512, 128),
+        ] {
+            let db = Box::new(BTreeDatabase::new(&INDEXES));
+            let mut merkle_db =
+                MerkleRadixTree::new_with_hash_algorithm(db.clone(), None, *hash_algorithm)
+                    .unwrap();
+            let root = merkle_db.update(&updates, false).unwrap();
+            assert_eq!(*hash_len, root.len());
+            assert!(roots.insert(root.clone()), "Duplicate root {}", root);
+
+            // The recorded hash algorithm is used when none is given
+            merkle_db = MerkleRadixTree::new(db.clone(), Some(&root)).unwrap();
+            assert_eq!(*hash_algorithm, merkle_db.hash_algorithm());
+            assert_eq!(
+                Some("0001".as_bytes().to_vec()),
+                merkle_db.get_value("ab0000").unwrap()
+            );
+            let proof = merkle_db.get_proof("ab0000").unwrap();
+            assert_eq!(
+                Some("0001".as_bytes().to_vec()),
+                verify_proof(&root, "ab0000", &proof, *hash_algorithm).unwrap()
+            );
+
+            let report = MerkleRadixTree::check_integrity(&*db, None, false).unwrap();
+            assert!(report.is_ok(), "Unexpected problems: {:?}", report);
+        }
+
+        // A database can't be used with a different hash algorithm than the one it records
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        MerkleState::new_with_hash_algorithm(db.clone(), HashAlgorithm::Sha256).unwrap();
+        match MerkleRadixTree::new_with_hash_algorithm(db.clone(), None, HashAlgorithm::Sha512) {
+            Err(StateDatabaseError::InvalidHashAlgorithm(_)) => (),
+            Err(err) => panic!("Expected InvalidHashAlgorithm, got {:?}", err),
+            Ok(_) => panic!("Expected InvalidHashAlgorithm"),
+        }
+        match MerkleState::new_with_hash_algorithm(db.clone(), HashAlgorithm::Sha512Half) {
+            Err(StateWriteError::StorageError(_)) => (),
+            Err(err) => panic!("Expected StorageError, got {:?}", err),
+            Ok(_) => panic!("Expected StorageError"),
+        }
+
+        // Nor can a database written before hash algorithms were recorded
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        {
+            let mut writer = db.get_writer().unwrap();
+            let (hash, packed) =
+                encode_and_hash(Node::default(), HashAlgorithm::default()).unwrap();
+            writer.put(::hex::encode(hash).as_bytes(), &packed).unwrap();
+            writer.commit().unwrap();
+        }
+        assert!(
+            MerkleRadixTree::new_with_hash_algorithm(db.clone(), None, HashAlgorithm::Sha256)
+                .is_err()
+        );
+        assert!(MerkleRadixTree::new_with_hash_algorithm(
+            db.clone(),
+            None,
+            HashAlgorithm::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn merkle_without_metadata_index() {
+        // Reading and writing state records no hash algorithm
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let state = MerkleState::new(db.clone());
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let root = state
+            .commit(
+                &empty_root,
+                &[StateChange::Set {
+                    key: "ab0000".to_string(),
+                    value: b"0".to_vec(),
+                }],
+            )
+            .unwrap();
+        assert!(state.get(&root, &["ab0000".to_string()]).is_ok());
+        assert_eq!(
+            0,
+            db.get_reader()
+                .unwrap()
+                .index_count(METADATA_INDEX)
+                .unwrap()
+        );
+
+        // A database created without the metadata index uses the default hash algorithm
+        let db = Box::new(BTreeDatabase::new(&[
+            CHANGE_LOG_INDEX,
+            DUPLICATE_LOG_INDEX,
+            ADDRESS_HISTORY_INDEX,
+        ]));
+        let merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
+        assert_eq!(HashAlgorithm::Sha512Half, merkle_db.hash_algorithm());
+        assert_eq!(merkle_db_empty_root(), merkle_db.get_merkle_root());
+
+        let state = MerkleState::new(db.clone());
+        let root = state
+            .commit(
+                &merkle_db.get_merkle_root(),
+                &[StateChange::Set {
+                    key: "ab0000".to_string(),
+                    value: b"0".to_vec(),
+                }],
+            )
+            .unwrap();
+        assert_eq!(
+            Some(&b"0".to_vec()),
+            state
+                .get(&root, &["ab0000".to_string()])
+                .unwrap()
+                .get("ab0000")
+        );
+
+        assert!(
+            MerkleState::new_with_hash_algorithm(db.clone(), HashAlgorithm::Sha512Half).is_ok()
+        );
+        match MerkleState::new_with_hash_algorithm(
+            Box::new(BTreeDatabase::new(&[CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX])),
+            HashAlgorithm::Sha256,
+        ) {
+            Err(StateWriteError::StorageError(_)) => (),
+            Err(err) => panic!("Expected StorageError, got {:?}", err),
+            Ok(_) => panic!("Expected StorageError"),
+        }
+    }
+
+    #[test]
+    fn merkle_state_iter_prefix() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+        let state_changes = ["ab0a01", "ab0000", "abff00", "cd0000"]
+            .iter()
+            .map(|key| StateChange::Set {
+                key: key.to_string(),
+                value: key.as_bytes().to_vec(),
+            })
+            .collect::<Vec<_>>();
+        let root = state.commit(&empty_root, &state_changes).unwrap();
+
+        let keys = |prefix: Option<&str>| {
+            state
+                .iter_prefix(&root, prefix.map(String::from).as_ref())
+                .unwrap()
+                .map(|entry| entry.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["ab0000", "ab0a01", "abff00", "cd0000"], keys(None));
+        assert_eq!(vec!["ab0000", "ab0a01", "abff00"], keys(Some("ab")));
+        // Prefixes need not end on a node boundary
+        assert_eq!(vec!["ab0000", "ab0a01"], keys(Some("ab0")));
+        assert_eq!(vec!["ab0a01"], keys(Some("ab0a01")));
+        assert!(keys(Some("ef")).is_empty());
+        assert!(keys(Some("ab0b")).is_empty());
+
+        // The trait is usable without knowing the backend
+        let reader: Box<dyn Read<StateId = String, Key = String, Value = Vec<u8>>> =
+            Box::new(state.clone());
+        assert_eq!(
+            Some(("cd0000".to_string(), b"cd0000".to_vec())),
+            reader
+                .iter_prefix(&root, Some(&"cd".to_string()))
+                .unwrap()
+                .next()
+                .map(Result::unwrap)
+        );
+        match reader.iter_prefix(&"00".repeat(32), None) {
+            Err(StateReadError::InvalidStateId(_)) => (),
+            Err(err) => panic!("Expected InvalidStateId, got {:?}", err),
+            Ok(_) => panic!("Expected InvalidStateId"),
+        }
+
+        // Non-hex prefixes, which could split a multi-byte character, are rejected
+        for prefix in &["é", "aé", "xy"] {
+            match reader.iter_prefix(&root, Some(&prefix.to_string())) {
+                Err(StateReadError::InvalidKey(_)) => (),
+                Err(err) => panic!("Expected InvalidKey, got {:?}", err),
+                Ok(_) => panic!("Expected InvalidKey"),
+            }
+        }
+    }
+
+    #[test]
+    fn merkle_address_history() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+        let set = |key: &str, value: &str| StateChange::Set {
+            key: key.to_string(),
+            value: value.as_bytes().to_vec(),
+        };
+        let change = |state_id: &String, value: Option<&str>| AddressChange {
+            state_id: state_id.clone(),
+            value: value.map(|v| v.as_bytes().to_vec()),
+        };
+
+        let first_root = state.commit(&empty_root, &[set("ab0000", "0001")]).unwrap();
+        let second_root = state.commit(&first_root, &[set("cd0000", "0002")]).unwrap();
+        let third_root = state
+            .commit(
+                &second_root,
+                &[set("ab0000", "0003"), set("ab0000", "0004")],
+            )
+            .unwrap();
+        let fourth_root = state
+            .commit(
+                &third_root,
+                &[StateChange::Delete {
+                    key: "ab0000".to_string(),
+                }],
+            )
+            .unwrap();
+        let fork_root = state
+            .commit(&second_root, &[set("ab0000", "0005")])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                change(&first_root, Some("0001")),
+                change(&third_root, Some("0004")),
+                change(&fourth_root, None),
+            ],
+            state.address_history(&fourth_root, "ab0000").unwrap()
+        );
+        assert_eq!(
+            vec![
+                change(&first_root, Some("0001")),
+                change(&fork_root, Some("0005")),
+            ],
+            state.address_history(&fork_root, "ab0000").unwrap()
+        );
+        assert_eq!(
+            Some(change(&first_root, Some("0001"))),
+            state.last_change(&second_root, "ab0000").unwrap()
+        );
+        assert_eq!(None, state.last_change(&fourth_root, "ef0000").unwrap());
+        assert_eq!(None, state.last_change(&empty_root, "ab0000").unwrap());
+
+        match state.address_history(&"00".repeat(32), "ab0000") {
+            Err(StateReadError::InvalidStateId(_)) => (),
+            res => panic!("Expected InvalidStateId, got {:?}", res),
+        }
+
+        // Once a root is pruned, the history stops before it, and its records are deleted
+        let record_count = || {
+            db.get_reader()
+                .unwrap()
+                .index_count(ADDRESS_HISTORY_INDEX)
+                .unwrap()
+        };
+        let before_prune = record_count();
+        state.prune(vec![first_root.clone()]).unwrap();
+        assert_eq!(
+            vec![
+                change(&third_root, Some("0004")),
+                change(&fourth_root, None),
+            ],
+            state.address_history(&fourth_root, "ab0000").unwrap()
+        );
+        assert_eq!(before_prune - 2, record_count());
+
+        // The leaves of a tree built from sorted leaves are recorded at its root
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let leaves = vec![
+            ("ab0000".to_string(), b"0001".to_vec()),
+            ("cd0000".to_string(), b"0002".to_vec()),
+        ];
+        let bulk_root = MerkleRadixTree::from_sorted_leaves(db.clone(), leaves.into_iter().map(Ok))
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db);
+        let next_root = state.commit(&bulk_root, &[set("ab0000", "0003")]).unwrap();
+        assert_eq!(
+            vec![
+                change(&bulk_root, Some("0001")),
+                change(&next_root, Some("0003")),
+            ],
+            state.address_history(&next_root, "ab0000").unwrap()
+        );
+        assert_eq!(
+            Some(change(&bulk_root, Some("0002"))),
+            state.last_change(&next_root, "cd0000").unwrap()
+        );
+    }
+
+    #[test]
+    fn merkle_without_history_index() {
+        let db = Box::new(BTreeDatabase::new(&[CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX]));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+
+        let root = state
+            .commit(
+                &empty_root,
+                &[StateChange::Set {
+                    key: "ab0000".to_string(),
+                    value: b"0001".to_vec(),
+                }],
+            )
+            .unwrap();
+        assert_eq!(
+            Some(&b"0001".to_vec()),
+            state
+                .get(&root, &["ab0000".to_string()])
+                .unwrap()
+                .get("ab0000")
+        );
+        assert!(state.address_history(&root, "ab0000").unwrap().is_empty());
+
+        let leaves = vec![("ab0000".to_string(), b"0001".to_vec())];
+        assert_eq!(
+            root,
+            MerkleRadixTree::from_sorted_leaves(
+                Box::new(BTreeDatabase::new(&[CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX])),
+                leaves.into_iter().map(Ok),
+            )
+            .unwrap()
+            .get_merkle_root()
+        );
+
+        let next_root = state
+            .commit(
+                &root,
+                &[StateChange::Delete {
+                    key: "ab0000".to_string(),
+                }],
+            )
+            .unwrap();
+        state.prune(vec![root]).unwrap();
+        assert!(state
+            .get(&next_root, &["ab0000".to_string()])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn merkle_diff() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
+        let empty_root = merkle_db.get_merkle_root();
+
+        let first_root = merkle_db
+            .update(
+                &[
+                    StateChange::Set {
+                        key: "ab0000".to_string(),
+                        value: "0001".as_bytes().to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "ab0a01".to_string(),
+                        value: "0002".as_bytes().to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "abff00".to_string(),
+                        value: "0003".as_bytes().to_vec(),
+                    },
+                ],
+                false,
+            )
+            .unwrap();
+        merkle_db.set_merkle_root(first_root.clone()).unwrap();
+
+        let changes = vec![
+            StateChange::Set {
+                key: "ab0a01".to_string(),
+                value: "0004".as_bytes().to_vec(),
+            },
+            StateChange::Delete {
+                key: "abff00".to_string(),
+            },
+            StateChange::Set {
+                key: "cd0000".to_string(),
+                value: "0005".as_bytes().to_vec(),
+            },
+        ];
+        let second_root = merkle_db.update(&changes, false).unwrap();
+
+        assert!(MerkleRadixTree::diff(&*db, &first_root, &first_root)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            changes,
+            MerkleRadixTree::diff(&*db, &first_root, &second_root).unwrap()
+        );
+        assert_eq!(
+            vec![
+                StateChange::Set {
+                    key: "ab0a01".to_string(),
+                    value: "0002".as_bytes().to_vec(),
+                },
+                StateChange::Set {
+                    key: "abff00".to_string(),
+                    value: "0003".as_bytes().to_vec(),
+                },
+                StateChange::Delete {
+                    key: "cd0000".to_string(),
+                },
+            ],
+            MerkleRadixTree::diff(&*db, &second_root, &first_root).unwrap()
+        );
+
+        // Applying the diff from the empty tree rebuilds the whole tree
+        let rebuild = MerkleState::new(db.clone())
+            .diff(&empty_root, &second_root)
+            .unwrap();
+        assert_eq!(3, rebuild.len());
+        let mut empty_db = MerkleRadixTree::new(db.clone(), None).unwrap();
+        empty_db.set_merkle_root(empty_root).unwrap();
+        assert_eq!(second_root, empty_db.update(&rebuild, true).unwrap());
+    }
+
+    #[test]
+    fn merkle_snapshot() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let merkle_state = MerkleState::new(db.clone());
+        let merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
+
+        let changes: Vec<StateChange> = (0..2500)
+            .map(|i| StateChange::Set {
+                key: format!("ab{:04x}", i),
+                value: format!("{}", i).into_bytes(),
+            })
+            .collect();
+        let merkle_root = merkle_db.update(&changes, false).unwrap();
+
+        let mut snapshot = vec![];
+        assert_eq!(
+            2500,
+            merkle_state
+                .export_snapshot(&merkle_root, &mut snapshot)
+                .unwrap()
+        );
+
+        // The snapshot rebuilds the same tree in a fresh database
+        let new_db = Box::new(BTreeDatabase::new(&INDEXES));
+        let new_merkle_state = MerkleState::new(new_db.clone());
+        assert_eq!(
+            merkle_root,
+            new_merkle_state
+                .import_snapshot(&mut &snapshot[..])
+                .unwrap()
+        );
+        let new_merkle_db = MerkleRadixTree::new(new_db.clone(), Some(&merkle_root)).unwrap();
+        assert_eq!(
+            Some(b"1234".to_vec()),
+            new_merkle_db.get_value("ab04d2").unwrap()
+        );
+
+        // A snapshot of an unknown root cannot be exported
+        match merkle_state.export_snapshot(&"ff".repeat(32), &mut vec![]) {
+            Err(StateReadError::InvalidStateId(_)) => (),
+            res => panic!("Expected InvalidStateId, got {:?}", res),
+        }
+
+        // A corrupted snapshot is rejected
+        let mut corrupted = snapshot.clone();
+        let index = corrupted.len() / 2;
+        corrupted[index] ^= 0xff;
+        assert!(MerkleRadixTree::import_snapshot(
+            Box::new(BTreeDatabase::new(&INDEXES)),
+            &mut &corrupted[..]
+        )
+        .is_err());
+
+        // A snapshot whose leaves do not match its merkle root is rejected
+        let mut mismatched = vec![];
+        {
+            let mut writer = SnapshotWriter::new(&mut mismatched, &merkle_root).unwrap();
+            writer.write_entry("ab0000", b"0").unwrap();
+            writer.finish().unwrap();
+        }
+        match MerkleRadixTree::import_snapshot(
+            Box::new(BTreeDatabase::new(&INDEXES)),
+            &mut &mismatched[..],
+        ) {
+            Err(StateDatabaseError::InvalidSnapshot(_)) => (),
+            Err(err) => panic!("Expected InvalidSnapshot, got {:?}", err),
+            Ok(root) => panic!("Expected InvalidSnapshot, got root {}", root),
+        }
+    }
+
+    #[test]
+    fn merkle_from_sorted_leaves() {
+        let mut leaves: Vec<(String, Vec<u8>)> = (0..500)
+            .map(|i| {
+                (
+                    format!("ab{:04x}", i * 7),
+                    format!("{}", i % 10).into_bytes(),
+                )
+            })
+            .collect();
+        leaves.push(("cd00000000".to_string(), b"deep".to_vec()));
+        leaves.sort();
+
+        let update_db = MerkleRadixTree::new(Box::new(BTreeDatabase::new(&INDEXES)), None).unwrap();
+        let changes: Vec<StateChange> = leaves
+            .iter()
+            .map(|(key, value)| StateChange::Set {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        let expected_root = update_db.update(&changes, false).unwrap();
+
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let merkle_db =
+            MerkleRadixTree::from_sorted_leaves(db.clone(), leaves.clone().into_iter().map(Ok))
+                .unwrap();
+        assert_eq!(expected_root, merkle_db.get_merkle_root());
+        assert_eq!(
+            Some(b"deep".to_vec()),
+            merkle_db.get_value("cd00000000").unwrap()
+        );
+        assert_eq!(
+            leaves,
+            merkle_db
+                .leaves(None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        );
+
+        // A single change log entry is written, as a successor of the empty tree
+        let reader = db.get_reader().unwrap();
+        let change_log = get_change_log(&*reader, &::hex::decode(&expected_root).unwrap())
+            .unwrap()
+            .expect("No change log entry");
+        assert_eq!(merkle_db_empty_root(), ::hex::encode(&change_log.parent));
+        assert!(change_log.successors.is_empty());
+        assert_eq!(1, reader.index_count(CHANGE_LOG_INDEX).unwrap());
+
+        // No change log entry is written for an empty tree
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let merkle_db = MerkleRadixTree::from_sorted_leaves(db.clone(), vec![]).unwrap();
+        assert_eq!(merkle_db_empty_root(), merkle_db.get_merkle_root());
+        assert_eq!(
+            0,
+            db.get_reader()
+                .unwrap()
+                .index_count(CHANGE_LOG_INDEX)
+                .unwrap()
+        );
+
+        // Leaves which are out of order are rejected
+        let mut unsorted = leaves.clone();
+        unsorted.swap(10, 11);
+        match MerkleRadixTree::from_sorted_leaves(
+            Box::new(BTreeDatabase::new(&INDEXES)),
+            unsorted.into_iter().map(Ok),
+        ) {
+            Err(StateDatabaseError::InvalidLeaf(_)) => (),
+            Err(err) => panic!("Expected InvalidLeaf, got {:?}", err),
+            Ok(_) => panic!("Expected InvalidLeaf"),
+        }
+    }
+
+    #[test]
+    fn node_cache_eviction() {
+        let cache = NodeCache::new(2);
+        let node = |value: &str| Node {
+            value: Some(value.as_bytes().to_vec()),
+            children: BTreeMap::new(),
+        };
+
+        cache.insert("aa", node("a"));
+        cache.insert("bb", node("b"));
+        // Using "aa" makes "bb" the least recently used node
+        assert_eq!(Some(node("a")), cache.get("aa"));
+        cache.insert("cc", node("c"));
+
+        assert_eq!(2, cache.len());
+        assert_eq!(None, cache.get("bb"));
+        assert_eq!(Some(node("a")), cache.get("aa"));
+        assert_eq!(Some(node("c")), cache.clone().get("cc"));
+
+        cache.remove("aa");
+        assert_eq!(None, cache.get("aa"));
+        assert_eq!(1, cache.len());
+
+        let empty_cache = NodeCache::new(0);
+        empty_cache.insert("aa", node("a"));
+        assert!(empty_cache.is_empty());
+    }
+
+    #[test]
+    fn merkle_state_node_cache() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let cache = NodeCache::new(100);
+        let merkle_state = MerkleState::new_with_cache(db.clone(), cache.clone());
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+
+        let changes = vec![
+            StateChange::Set {
+                key: "ab0000".to_string(),
+                value: b"0001".to_vec(),
+            },
+            StateChange::Set {
+                key: "ab0a01".to_string(),
+                value: b"0002".to_vec(),
+            },
+        ];
+        let state_id = merkle_state.commit(&empty_root, &changes).unwrap();
+
+        // Reads through a clone fill the shared cache
+        let keys = vec!["ab0000".to_string(), "ab0a01".to_string()];
+        let values = merkle_state.clone().get(&state_id, &keys).unwrap();
+        assert_eq!(Some(&b"0002".to_vec()), values.get("ab0a01"));
+        let cached = cache.len();
+        assert!(cached > 1);
+
+        // Reading the same nodes again does not grow the cache
+        assert_eq!(values, merkle_state.get(&state_id, &keys).unwrap());
+        assert_eq!(cached, cache.len());
+
+        // Pruned nodes are removed from the cache
+        let next_state_id = merkle_state
+            .commit(
+                &state_id,
+                &[StateChange::Delete {
+                    key: "ab0000".to_string(),
+                }],
+            )
+            .unwrap();
+        merkle_state.prune(vec![state_id.clone()]).unwrap();
+        assert!(merkle_state.get(&state_id, &keys).is_err());
+        assert_eq!(
+            Some(&b"0002".to_vec()),
+            merkle_state
+                .get(&next_state_id, &keys)
+                .unwrap()
+                .get("ab0a01")
+        );
+    }
+
+    #[test]
+    fn merkle_check_integrity() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
+
+        let first_root = merkle_db
+            .update(
+                &[StateChange::Set {
+                    key: "ab0000".to_string(),
+                    value: b"value".to_vec(),
+                }],
+                false,
+            )
+            .unwrap();
+        merkle_db.set_merkle_root(first_root.clone()).unwrap();
+        // The same leaf node is added again, under another address
+        let second_root = merkle_db
+            .update(
+                &[StateChange::Set {
+                    key: "cd0100".to_string(),
+                    value: b"value".to_vec(),
+                }],
+                false,
+            )
+            .unwrap();
+
+        let report = MerkleRadixTree::check_integrity(&*db, None, false).unwrap();
+        assert!(report.is_ok(), "Unexpected problems: {:?}", report);
+        assert_eq!(3, report.roots.len());
+        let report = MerkleRadixTree::check_integrity(&*db, Some(&second_root), false).unwrap();
+        assert!(report.is_ok(), "Unexpected problems: {:?}", report);
+        assert_eq!(vec![second_root.clone()], report.roots);
+
+        // Break the ref counts, and add an orphaned node
+        let (leaf_hash, _) = encode_and_hash(
+            Node {
+                value: Some(b"value".to_vec()),
+                children: BTreeMap::new(),
+            },
+            HashAlgorithm::default(),
+        )
+        .unwrap();
+        let (orphan_hash, orphan) = encode_and_hash(
+            Node {
+                value: Some(b"orphan".to_vec()),
+                children: BTreeMap::new(),
+            },
+            HashAlgorithm::default(),
+        )
+        .unwrap();
+        {
+            let mut writer = db.get_writer().unwrap();
+            writer
+                .index_delete(DUPLICATE_LOG_INDEX, &leaf_hash)
+                .unwrap();
+            writer
+                .index_put(DUPLICATE_LOG_INDEX, &[0xff; 32], &to_bytes(1))
+                .unwrap();
+            writer
+                .put(::hex::encode(&orphan_hash).as_bytes(), &orphan)
+                .unwrap();
+            writer.commit().unwrap();
+        }
+
+        let report = MerkleRadixTree::check_integrity(&*db, None, true).unwrap();
+        assert!(report.repaired);
+        assert!(report.corrupt_nodes.is_empty());
+        assert!(report.missing_nodes.is_empty());
+        assert_eq!(vec![::hex::encode(&orphan_hash)], report.orphaned_nodes);
+        assert_eq!(
+            vec![RefCountError {
+                hash: ::hex::encode(&leaf_hash),
+                recorded: Some(0),
+                expected: 1,
+            }],
+            report.ref_count_errors
+        );
+        assert_eq!(vec!["ff".repeat(32)], report.dangling_ref_counts);
+
+        let report = MerkleRadixTree::check_integrity(&*db, None, false).unwrap();
+        assert!(report.is_ok(), "Unexpected problems: {:?}", report);
+        assert!(!report.repaired);
+
+        // Delete one subtree and corrupt another
+        let root_node = get_node_by_hash(&*db, &second_root).unwrap();
+        let ab_hash = root_node.children["ab"].clone();
+        let cd_hash = root_node.children["cd"].clone();
+        {
+            let mut writer = db.get_writer().unwrap();
+            writer.delete(ab_hash.as_bytes()).unwrap();
+            writer.overwrite(cd_hash.as_bytes(), b"corrupt").unwrap();
+            writer.commit().unwrap();
+        }
+
+        let report = MerkleRadixTree::check_integrity(&*db, None, true).unwrap();
+        assert_eq!(vec![cd_hash.clone()], report.corrupt_nodes);
+        assert_eq!(1, report.missing_nodes.len());
+        assert_eq!(ab_hash, report.missing_nodes[0].hash);
+        // The nodes below the missing and corrupt nodes are orphaned, but not deleted
+        assert_eq!(2, report.orphaned_nodes.len());
+        let report = MerkleRadixTree::check_integrity(&*db, None, false).unwrap();
+        assert_eq!(2, report.orphaned_nodes.len());
+
+        let report = MerkleRadixTree::check_integrity(&*db, Some(&first_root), false).unwrap();
+        assert_eq!(
+            vec![MissingNode {
+                parent: Some(first_root.clone()),
+                hash: ab_hash,
+            }],
+            report.missing_nodes
+        );
+        assert!(report.orphaned_nodes.is_empty());
+    }
+
+    fn merkle_db_empty_root() -> String {
+        MerkleRadixTree::new(Box::new(BTreeDatabase::new(&INDEXES)), None)
+            .unwrap()
+            .get_merkle_root()
+    }
+
     /// Verifies that a state tree backed by lmdb and btree give the same root hashes
     #[test]
     fn test_btree_lmdb() {
@@ -1894,7 +3873,6 @@ mod tests {
     }
 
     fn hex_hash(b: &[u8]) -> String {
-        ::hex::encode(hash(b))
+        ::hex::encode(HashAlgorithm::default().hash(b).unwrap())
     }
-
 }
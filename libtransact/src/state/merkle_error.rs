@@ -33,6 +33,10 @@ pub enum StateDatabaseError {
     InvalidRecord,
     InvalidHash(String),
     InvalidChangeLogIndex(String),
+    InvalidProof(String),
+    InvalidSnapshot(String),
+    InvalidLeaf(String),
+    InvalidHashAlgorithm(String),
     DatabaseError(DatabaseError),
     ProtobufConversionError(ProtoConversionError),
     UnknownError,
@@ -58,6 +62,14 @@ impl fmt::Display for StateDatabaseError {
             StateDatabaseError::InvalidChangeLogIndex(ref msg) => {
                 write!(f, "A change log entry was missing or malformed: {}", msg)
             }
+            StateDatabaseError::InvalidProof(ref msg) => write!(f, "The proof is invalid: {}", msg),
+            StateDatabaseError::InvalidSnapshot(ref msg) => {
+                write!(f, "The snapshot is invalid: {}", msg)
+            }
+            StateDatabaseError::InvalidLeaf(ref msg) => write!(f, "A leaf is invalid: {}", msg),
+            StateDatabaseError::InvalidHashAlgorithm(ref msg) => {
+                write!(f, "The hash algorithm is invalid: {}", msg)
+            }
             StateDatabaseError::DatabaseError(ref err) => {
                 write!(f, "A database error occurred: {}", err)
             }
@@ -79,6 +91,10 @@ impl Error for StateDatabaseError {
             StateDatabaseError::InvalidRecord => "Invalid record",
             StateDatabaseError::InvalidHash(ref msg) => &msg,
             StateDatabaseError::InvalidChangeLogIndex(ref msg) => &msg,
+            StateDatabaseError::InvalidProof(ref msg) => &msg,
+            StateDatabaseError::InvalidSnapshot(ref msg) => &msg,
+            StateDatabaseError::InvalidLeaf(ref msg) => &msg,
+            StateDatabaseError::InvalidHashAlgorithm(ref msg) => &msg,
             StateDatabaseError::DatabaseError(ref err) => err.description(),
             StateDatabaseError::ProtobufConversionError(ref err) => err.description(),
             StateDatabaseError::UnknownError => "Unknown Error",
@@ -94,6 +110,10 @@ impl Error for StateDatabaseError {
             StateDatabaseError::InvalidRecord => None,
             StateDatabaseError::InvalidHash(_) => None,
             StateDatabaseError::InvalidChangeLogIndex(_) => None,
+            StateDatabaseError::InvalidProof(_) => None,
+            StateDatabaseError::InvalidSnapshot(_) => None,
+            StateDatabaseError::InvalidLeaf(_) => None,
+            StateDatabaseError::InvalidHashAlgorithm(_) => None,
             StateDatabaseError::DatabaseError(ref err) => Some(err),
             StateDatabaseError::ProtobufConversionError(ref err) => Some(err),
             StateDatabaseError::UnknownError => None,
@@ -0,0 +1,200 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The per-address history index of a Merkle database.
+//!
+//! For each root committed by `update` or built from sorted leaves, the index holds a record for
+//! every address which was set or deleted at that root, keyed by the address and the root, along
+//! with a record, keyed by the root alone, listing those addresses.  A root may belong to any
+//! lineage in the database; the history of an address as seen from a given root is found by
+//! walking back through the root's ancestors in the change log, and looking up the address's
+//! record at each of them.  The value of the address at each of those roots is read from the tree
+//! itself.  The records of a root are deleted when it is pruned.
+//!
+//! The index is optional: databases which were created without it record no history.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::Cursor;
+
+use cbor::decoder::GenericDecoder;
+use cbor::encoder::GenericEncoder;
+use cbor::value::{Text, Value};
+
+use crate::database::{DatabaseReader, DatabaseWriter};
+
+use super::change_log::ChangeLogEntry;
+use super::merkle::{
+    is_missing_index, StateDatabaseError, ADDRESS_HISTORY_INDEX, CHANGE_LOG_INDEX,
+};
+
+/// A change made to an address at a committed root.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressChange {
+    /// The root at which the change was committed
+    pub state_id: String,
+    /// The value of the address at the root, or `None` if it was deleted
+    pub value: Option<Vec<u8>>,
+}
+
+/// Records that the given addresses were changed at the given root.
+///
+/// Nothing is recorded if the database has no history index.
+pub(super) fn record_changes<'a, I>(
+    db_writer: &mut dyn DatabaseWriter,
+    root_hash: &[u8],
+    addresses: I,
+) -> Result<(), StateDatabaseError>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // The root may have been committed before, from another parent
+    let mut changed = match db_writer
+        .as_reader()
+        .index_get(ADDRESS_HISTORY_INDEX, root_hash)
+    {
+        Ok(Some(bytes)) => decode_addresses(&bytes)?,
+        Ok(None) => BTreeSet::new(),
+        Err(ref err) if is_missing_index(err, ADDRESS_HISTORY_INDEX) => return Ok(()),
+        Err(err) => return Err(StateDatabaseError::from(err)),
+    };
+
+    for address in addresses {
+        if changed.insert(address.to_string()) {
+            db_writer.index_put(ADDRESS_HISTORY_INDEX, &change_key(address, root_hash), &[])?;
+        }
+    }
+    db_writer.index_put(
+        ADDRESS_HISTORY_INDEX,
+        root_hash,
+        &encode_addresses(&changed)?,
+    )?;
+
+    Ok(())
+}
+
+/// Deletes the records of the changes made at a root which is being pruned.
+pub(super) fn remove_root(
+    db_writer: &mut dyn DatabaseWriter,
+    root_hash: &[u8],
+) -> Result<(), StateDatabaseError> {
+    let changed = match db_writer
+        .as_reader()
+        .index_get(ADDRESS_HISTORY_INDEX, root_hash)
+    {
+        Ok(Some(bytes)) => decode_addresses(&bytes)?,
+        Ok(None) => return Ok(()),
+        Err(ref err) if is_missing_index(err, ADDRESS_HISTORY_INDEX) => return Ok(()),
+        Err(err) => return Err(StateDatabaseError::from(err)),
+    };
+
+    for address in &changed {
+        db_writer.index_delete(ADDRESS_HISTORY_INDEX, &change_key(address, root_hash))?;
+    }
+    db_writer.index_delete(ADDRESS_HISTORY_INDEX, root_hash)?;
+
+    Ok(())
+}
+
+/// Returns the roots, out of the given root and its ancestors, at which an address was changed,
+/// oldest first.
+pub(super) fn changed_roots(
+    db_reader: &dyn DatabaseReader,
+    address: &str,
+    root_hash: &[u8],
+) -> Result<Vec<Vec<u8>>, StateDatabaseError> {
+    // Walk back from the root, newest first; a root's hash may recur in its own lineage, if the
+    // state was returned to an earlier one, so stop at the first repeated root
+    let mut roots = vec![];
+    let mut visited = HashSet::new();
+    let mut root = root_hash.to_vec();
+    while visited.insert(root.clone()) {
+        let change_log = match db_reader.index_get(CHANGE_LOG_INDEX, &root)? {
+            Some(bytes) => ChangeLogEntry::from_bytes(&bytes)?,
+            None => break,
+        };
+        match db_reader.index_get(ADDRESS_HISTORY_INDEX, &change_key(address, &root)) {
+            Ok(Some(_)) => roots.push(root),
+            Ok(None) => (),
+            Err(ref err) if is_missing_index(err, ADDRESS_HISTORY_INDEX) => return Ok(vec![]),
+            Err(err) => return Err(StateDatabaseError::from(err)),
+        }
+        root = change_log.parent;
+    }
+
+    roots.reverse();
+    Ok(roots)
+}
+
+/// Returns the key of the record of a change to an address at a root.  Addresses are hex, so the
+/// zero byte separating the address from the root cannot appear in the address.
+fn change_key(address: &str, root_hash: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(address.len() + 1 + root_hash.len());
+    key.extend_from_slice(address.as_bytes());
+    key.push(0);
+    key.extend_from_slice(root_hash);
+    key
+}
+
+/// Encodes the addresses changed at a root as a CBOR array of strings.
+fn encode_addresses(addresses: &BTreeSet<String>) -> Result<Vec<u8>, StateDatabaseError> {
+    let mut e = GenericEncoder::new(Cursor::new(Vec::new()));
+
+    let addresses = addresses
+        .iter()
+        .map(|address| Value::Text(Text::Text(address.clone())))
+        .collect();
+    e.value(&Value::Array(addresses))?;
+
+    Ok(e.into_inner().into_writer().into_inner())
+}
+
+fn decode_addresses(bytes: &[u8]) -> Result<BTreeSet<String>, StateDatabaseError> {
+    let input = Cursor::new(bytes);
+    let mut decoder = GenericDecoder::new(cbor::Config::default(), input);
+    let addresses = match decoder.value()? {
+        Value::Array(addresses) => addresses,
+        _ => return Err(StateDatabaseError::InvalidRecord),
+    };
+
+    addresses
+        .into_iter()
+        .map(|address| match address {
+            Value::Text(Text::Text(address)) => Ok(address),
+            _ => Err(StateDatabaseError::InvalidRecord),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_roundtrip() {
+        let addresses: BTreeSet<String> = vec!["ab0000".to_string(), "cd".to_string()]
+            .into_iter()
+            .collect();
+        let bytes = encode_addresses(&addresses).unwrap();
+        assert_eq!(addresses, decode_addresses(&bytes).unwrap());
+
+        assert!(
+            decode_addresses(&encode_addresses(&BTreeSet::new()).unwrap())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,386 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Automatic pruning of the roots recorded in a MerkleState's change log.
+//!
+//! The change log records, for each committed root, its parent and its successors.  Starting from
+//! the current root, the roots to keep are chosen by walking back through its ancestors, and every
+//! root descended from a kept root is kept as well; every other root in the change log is either
+//! an older ancestor, or belongs to a fork from an older ancestor, which has been abandoned.  Forks
+//! are pruned first, from their tips back, as a root can only be pruned once it has at most one
+//! successor; the old ancestors are then pruned, oldest first.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use log::{debug, error};
+
+use super::change_log::ChangeLogEntry;
+use super::error::StatePruneError;
+use super::merkle::{MerkleState, CHANGE_LOG_INDEX};
+use super::Prune;
+
+/// Which of the current root's ancestors are kept when pruning.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PruningPolicy {
+    /// Keeps the given number of the most recent roots, including the current root.
+    KeepLatest(usize),
+    /// Keeps the given root, and every root after it up to the current root.
+    KeepSince(String),
+}
+
+/// Prunes the roots of a MerkleState which are no longer needed under a `PruningPolicy`.
+///
+/// Roots descended from a kept root, including those after the current root, are always kept.
+#[derive(Clone)]
+pub struct PruningManager {
+    state: MerkleState,
+    policy: PruningPolicy,
+}
+
+impl PruningManager {
+    pub fn new(state: MerkleState, policy: PruningPolicy) -> Self {
+        PruningManager { state, policy }
+    }
+
+    /// Returns the roots which would be pruned, relative to the given current root, in the order
+    /// in which they would be pruned.
+    pub fn prunable_roots(&self, current_root: &str) -> Result<Vec<String>, StatePruneError> {
+        let change_logs = self.read_change_logs()?;
+        if !change_logs.contains_key(current_root) {
+            return Ok(vec![]);
+        }
+
+        // The current root and its ancestors, newest first.  A root may be committed again after
+        // its successors, in which case the change log has a cycle, so the walk stops at the first
+        // root it has already seen.
+        let mut chain = vec![current_root.to_string()];
+        let mut chain_set: HashSet<String> = chain.iter().cloned().collect();
+        while let Some(change_log) = change_logs.get(&chain[chain.len() - 1]) {
+            let parent = ::hex::encode(&change_log.parent);
+            if !change_logs.contains_key(&parent) || !chain_set.insert(parent.clone()) {
+                break;
+            }
+            chain.push(parent);
+        }
+
+        let kept_count = match self.policy {
+            PruningPolicy::KeepLatest(count) => count.max(1).min(chain.len()),
+            PruningPolicy::KeepSince(ref root) => {
+                chain.iter().position(|r| r == root).ok_or_else(|| {
+                    StatePruneError::InvalidStateId(format!(
+                        "{} is not an ancestor of {}",
+                        root, current_root
+                    ))
+                })? + 1
+            }
+        };
+        let ancestors = chain.split_off(kept_count);
+
+        // Every root descended from a kept root is kept too
+        let mut kept: HashSet<String> = chain.iter().cloned().collect();
+        let mut pending = chain;
+        while let Some(root) = pending.pop() {
+            if let Some(change_log) = change_logs.get(&root) {
+                for successor in &change_log.successors {
+                    let successor = ::hex::encode(&successor.successor);
+                    if kept.insert(successor.clone()) {
+                        pending.push(successor);
+                    }
+                }
+            }
+        }
+
+        let ancestor_set: HashSet<&String> = ancestors.iter().collect();
+        let mut forks: Vec<&String> = change_logs
+            .keys()
+            .filter(|root| !kept.contains(*root) && !ancestor_set.contains(root))
+            .collect();
+        forks.sort();
+
+        // Prune each fork's successors before the fork itself
+        let mut prunable = vec![];
+        let mut visited = HashSet::new();
+        for fork in forks {
+            let mut pending = vec![(fork.clone(), false)];
+            while let Some((root, successors_visited)) = pending.pop() {
+                if successors_visited {
+                    prunable.push(root);
+                    continue;
+                }
+                if !visited.insert(root.clone()) {
+                    continue;
+                }
+                pending.push((root.clone(), true));
+                for successor in &change_logs[&root].successors {
+                    let successor = ::hex::encode(&successor.successor);
+                    if change_logs.contains_key(&successor) && !kept.contains(&successor) {
+                        pending.push((successor, false));
+                    }
+                }
+            }
+        }
+
+        prunable.extend(ancestors.into_iter().rev());
+        Ok(prunable)
+    }
+
+    /// Prunes the roots which are no longer needed, relative to the given current root.
+    ///
+    /// Returns the roots which were pruned.
+    pub fn prune(&self, current_root: &str) -> Result<Vec<String>, StatePruneError> {
+        let prunable = self.prunable_roots(current_root)?;
+        if !prunable.is_empty() {
+            self.state.prune(prunable.clone())?;
+        }
+        Ok(prunable)
+    }
+
+    /// Starts a background thread, which prunes relative to each current root it is given.
+    pub fn start(self) -> Result<BackgroundPruner, std::io::Error> {
+        let (sender, receiver) = channel();
+        let join_handle = thread::Builder::new()
+            .name("PruningManager".into())
+            .spawn(move || self.run(receiver))?;
+
+        Ok(BackgroundPruner {
+            sender,
+            join_handle,
+        })
+    }
+
+    fn run(&self, receiver: Receiver<PrunerMessage>) {
+        while let Ok(PrunerMessage::Prune(mut current_root)) = receiver.recv() {
+            // Only the latest of any queued roots needs to be pruned against
+            let mut stop = false;
+            while let Ok(message) = receiver.try_recv() {
+                match message {
+                    PrunerMessage::Prune(root) => current_root = root,
+                    PrunerMessage::Stop => {
+                        stop = true;
+                        break;
+                    }
+                }
+            }
+
+            match self.prune(&current_root) {
+                Ok(pruned) => debug!("Pruned {} roots before {}", pruned.len(), current_root),
+                Err(err) => error!("Unable to prune roots before {}: {}", current_root, err),
+            }
+
+            if stop {
+                break;
+            }
+        }
+    }
+
+    fn read_change_logs(&self) -> Result<HashMap<String, ChangeLogEntry>, StatePruneError> {
+        let storage_error = |err| StatePruneError::StorageError(Box::new(err));
+
+        let db_reader = self.state.db().get_reader().map_err(storage_error)?;
+        let mut change_logs = HashMap::new();
+        for (root, bytes) in db_reader
+            .index_cursor(CHANGE_LOG_INDEX)
+            .map_err(storage_error)?
+        {
+            let change_log = ChangeLogEntry::from_bytes(&bytes)
+                .map_err(|err| StatePruneError::StorageError(Box::new(err)))?;
+            change_logs.insert(::hex::encode(root), change_log);
+        }
+        Ok(change_logs)
+    }
+}
+
+enum PrunerMessage {
+    Prune(String),
+    Stop,
+}
+
+/// A handle to a `PruningManager` running on a background thread.
+pub struct BackgroundPruner {
+    sender: Sender<PrunerMessage>,
+    join_handle: thread::JoinHandle<()>,
+}
+
+impl BackgroundPruner {
+    /// Requests that the roots which are no longer needed, relative to the given current root, be
+    /// pruned.
+    pub fn prune(&self, current_root: String) {
+        if self
+            .sender
+            .send(PrunerMessage::Prune(current_root))
+            .is_err()
+        {
+            error!("Unable to send to the PruningManager thread, which has stopped");
+        }
+    }
+
+    /// Stops the background thread, once any requested pruning is complete.
+    pub fn stop(self) {
+        // The thread may have already stopped, in which case there is nothing to do
+        let _ = self.sender.send(PrunerMessage::Stop);
+        if let Err(err) = self.join_handle.join() {
+            error!("Error joining with the PruningManager thread: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::{Read, StateChange, Write};
+
+    /// Commits a change setting `key` to `value` on top of the given root.
+    fn commit(state: &MerkleState, root: &str, key: &str, value: &str) -> String {
+        state
+            .commit(
+                &root.to_string(),
+                &[StateChange::Set {
+                    key: key.to_string(),
+                    value: value.as_bytes().to_vec(),
+                }],
+            )
+            .unwrap()
+    }
+
+    /// Builds the roots r0 <- r1 <- r2 <- r3, with the fork r1 <- f1 <- f2.
+    fn build_roots(state: &MerkleState, empty_root: &str) -> Vec<String> {
+        let r0 = commit(state, empty_root, "ab0000", "0");
+        let r1 = commit(state, &r0, "ab0001", "1");
+        let r2 = commit(state, &r1, "ab0000", "2");
+        let r3 = commit(state, &r2, "cd0000", "3");
+        let f1 = commit(state, &r1, "ab0001", "f1");
+        let f2 = commit(state, &f1, "ef0000", "f2");
+        vec![r0, r1, r2, r3, f1, f2]
+    }
+
+    #[test]
+    fn prune_keep_latest() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+        let roots = build_roots(&state, &empty_root);
+        let (r0, r1, r2, r3, f1, f2) = (
+            &roots[0], &roots[1], &roots[2], &roots[3], &roots[4], &roots[5],
+        );
+
+        let manager = PruningManager::new(state.clone(), PruningPolicy::KeepLatest(2));
+        let expected = vec![f2.clone(), f1.clone(), r0.clone(), r1.clone()];
+        assert_eq!(expected, manager.prunable_roots(r3).unwrap());
+        assert_eq!(expected, manager.prune(r3).unwrap());
+        assert!(manager.prunable_roots(r3).unwrap().is_empty());
+
+        let values = state
+            .get(r3, &["ab0000".to_string(), "cd0000".to_string()])
+            .unwrap();
+        assert_eq!(Some(&b"2".to_vec()), values.get("ab0000"));
+        assert!(state.get(r2, &["ab0001".to_string()]).is_ok());
+        assert!(state.get(r1, &["ab0001".to_string()]).is_err());
+
+        let report = MerkleRadixTree::check_integrity(&*db, None, false).unwrap();
+        assert!(report.is_ok(), "Unexpected problems: {:?}", report);
+        assert_eq!(3, report.roots.len());
+    }
+
+    #[test]
+    fn prune_keep_since() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+        let roots = build_roots(&state, &empty_root);
+        let (r0, r1, r2, r3, f1, f2) = (
+            &roots[0], &roots[1], &roots[2], &roots[3], &roots[4], &roots[5],
+        );
+
+        // The fork is kept, as it starts at a root which is kept
+        let manager = PruningManager::new(state.clone(), PruningPolicy::KeepSince(r1.clone()));
+        assert_eq!(vec![r0.clone()], manager.prunable_roots(r3).unwrap());
+        // Roots after the current root are kept
+        assert_eq!(vec![r0.clone()], manager.prunable_roots(r1).unwrap());
+        assert!(manager.prunable_roots(&empty_root).unwrap().is_empty());
+
+        match PruningManager::new(state.clone(), PruningPolicy::KeepSince(f1.clone()))
+            .prunable_roots(r2)
+        {
+            Err(StatePruneError::InvalidStateId(_)) => (),
+            res => panic!("Expected InvalidStateId, got {:?}", res),
+        }
+
+        // The fork is abandoned once the root it starts at is pruned
+        let manager = PruningManager::new(state.clone(), PruningPolicy::KeepSince(r2.clone()));
+        assert_eq!(
+            vec![f2.clone(), f1.clone(), r0.clone(), r1.clone()],
+            manager.prunable_roots(r3).unwrap()
+        );
+    }
+
+    #[test]
+    fn prune_cyclic_change_log() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+
+        // Deleting the key set by r1 commits r0 again, as a successor of r1
+        let r0 = commit(&state, &empty_root, "ab0000", "0");
+        let r1 = commit(&state, &r0, "ab0001", "1");
+        let r2 = state
+            .commit(
+                &r1,
+                &[StateChange::Delete {
+                    key: "ab0001".to_string(),
+                }],
+            )
+            .unwrap();
+        assert_eq!(r0, r2);
+
+        let manager = PruningManager::new(state.clone(), PruningPolicy::KeepLatest(1));
+        assert_eq!(vec![r1.clone()], manager.prunable_roots(&r0).unwrap());
+        let manager = PruningManager::new(state, PruningPolicy::KeepLatest(5));
+        assert!(manager.prunable_roots(&r0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn prune_in_background() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let empty_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(db.clone());
+        let roots = build_roots(&state, &empty_root);
+
+        let pruner = PruningManager::new(state.clone(), PruningPolicy::KeepLatest(1))
+            .start()
+            .unwrap();
+        pruner.prune(roots[2].clone());
+        pruner.prune(roots[3].clone());
+        pruner.stop();
+
+        let report = MerkleRadixTree::check_integrity(&*db, None, false).unwrap();
+        assert!(report.is_ok(), "Unexpected problems: {:?}", report);
+        assert_eq!(vec![roots[3].clone(), empty_root], report.roots);
+    }
+}
@@ -0,0 +1,339 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The snapshot file format used to export and import Merkle state.
+//!
+//! A snapshot starts with a header holding the format's magic bytes, its version and the merkle
+//! root of the exported state.  It is followed by the leaves of the state, in address order, each
+//! prefixed with an entry marker, and ends with an end marker, the number of leaves, and a
+//! SHA-256 checksum of all of the preceding bytes.  Lengths and counts are big-endian.
+
+use std::io;
+
+use openssl::sha::Sha256;
+
+use super::merkle_error::StateDatabaseError;
+
+const MAGIC: &[u8] = b"TRANSACT-SNAPSHOT";
+const FORMAT_VERSION: u8 = 1;
+const ENTRY_MARKER: u8 = 1;
+const END_MARKER: u8 = 0;
+const CHECKSUM_SIZE: usize = 32;
+
+/// The longest merkle root or address which is accepted from a snapshot.
+const MAX_ADDRESS_SIZE: usize = 1024;
+/// The largest value which is accepted from a snapshot.
+const MAX_VALUE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Writes a snapshot, one leaf at a time.
+pub struct SnapshotWriter<'a> {
+    writer: &'a mut dyn io::Write,
+    hasher: Sha256,
+    entry_count: u64,
+}
+
+impl<'a> SnapshotWriter<'a> {
+    /// Creates a writer, and writes the snapshot header for the given merkle root.
+    pub fn new(
+        writer: &'a mut dyn io::Write,
+        merkle_root: &str,
+    ) -> Result<Self, StateDatabaseError> {
+        let mut snapshot_writer = SnapshotWriter {
+            writer,
+            hasher: Sha256::new(),
+            entry_count: 0,
+        };
+        snapshot_writer.write(MAGIC)?;
+        snapshot_writer.write(&[FORMAT_VERSION])?;
+        snapshot_writer.write_bytes(merkle_root.as_bytes())?;
+        Ok(snapshot_writer)
+    }
+
+    pub fn write_entry(&mut self, address: &str, value: &[u8]) -> Result<(), StateDatabaseError> {
+        self.write(&[ENTRY_MARKER])?;
+        self.write_bytes(address.as_bytes())?;
+        self.write_bytes(value)?;
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    /// Writes the end of the snapshot, returning the number of leaves written.
+    pub fn finish(mut self) -> Result<u64, StateDatabaseError> {
+        self.write(&[END_MARKER])?;
+        let entry_count = self.entry_count;
+        self.write(&entry_count.to_be_bytes())?;
+        let checksum = self.hasher.finish();
+        self.writer.write_all(&checksum).map_err(io_error)?;
+        self.writer.flush().map_err(io_error)?;
+        Ok(entry_count)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), StateDatabaseError> {
+        self.write(&(bytes.len() as u32).to_be_bytes())?;
+        self.write(bytes)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), StateDatabaseError> {
+        self.hasher.update(bytes);
+        self.writer.write_all(bytes).map_err(io_error)
+    }
+}
+
+/// Reads a snapshot, iterating over its leaves.
+///
+/// The iterator returns an error if the snapshot is malformed, or if its entry count or checksum
+/// do not match its contents; the checksum can only be checked once every leaf has been read.
+pub struct SnapshotReader<'a> {
+    reader: &'a mut dyn io::Read,
+    hasher: Option<Sha256>,
+    merkle_root: String,
+    entry_count: u64,
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Creates a reader, and reads the snapshot header.
+    pub fn new(reader: &'a mut dyn io::Read) -> Result<Self, StateDatabaseError> {
+        let mut snapshot_reader = SnapshotReader {
+            reader,
+            hasher: Some(Sha256::new()),
+            merkle_root: String::new(),
+            entry_count: 0,
+        };
+
+        if snapshot_reader.read(MAGIC.len())? != MAGIC {
+            return Err(invalid_snapshot("not a snapshot file"));
+        }
+        let version = snapshot_reader.read(1)?[0];
+        if version != FORMAT_VERSION {
+            return Err(invalid_snapshot(&format!(
+                "unsupported format version {}",
+                version
+            )));
+        }
+        snapshot_reader.merkle_root =
+            String::from_utf8(snapshot_reader.read_bytes(MAX_ADDRESS_SIZE)?)
+                .map_err(|_| invalid_snapshot("merkle root is not valid UTF-8"))?;
+
+        Ok(snapshot_reader)
+    }
+
+    /// Returns the merkle root of the exported state.
+    pub fn merkle_root(&self) -> &str {
+        &self.merkle_root
+    }
+
+    fn read_entry(&mut self) -> Result<Option<(String, Vec<u8>)>, StateDatabaseError> {
+        match self.read(1)?[0] {
+            ENTRY_MARKER => {
+                let address = String::from_utf8(self.read_bytes(MAX_ADDRESS_SIZE)?)
+                    .map_err(|_| invalid_snapshot("address is not valid UTF-8"))?;
+                let value = self.read_bytes(MAX_VALUE_SIZE)?;
+                self.entry_count += 1;
+                Ok(Some((address, value)))
+            }
+            END_MARKER => {
+                let mut count_bytes = [0u8; 8];
+                count_bytes.copy_from_slice(&self.read(8)?);
+                if u64::from_be_bytes(count_bytes) != self.entry_count {
+                    return Err(invalid_snapshot("entry count does not match"));
+                }
+
+                let checksum = self
+                    .hasher
+                    .take()
+                    .expect("Snapshot was already read")
+                    .finish();
+                let mut expected_checksum = [0u8; CHECKSUM_SIZE];
+                self.reader
+                    .read_exact(&mut expected_checksum)
+                    .map_err(io_error)?;
+                if checksum != expected_checksum {
+                    return Err(invalid_snapshot("checksum does not match"));
+                }
+                Ok(None)
+            }
+            marker => Err(invalid_snapshot(&format!("unknown marker {}", marker))),
+        }
+    }
+
+    /// Reads a length-prefixed field, which may be at most `max_len` bytes long.
+    fn read_bytes(&mut self, max_len: usize) -> Result<Vec<u8>, StateDatabaseError> {
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&self.read(4)?);
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        if len > max_len {
+            return Err(invalid_snapshot(&format!(
+                "field of {} bytes exceeds the limit",
+                len
+            )));
+        }
+        self.read(len)
+    }
+
+    fn read(&mut self, len: usize) -> Result<Vec<u8>, StateDatabaseError> {
+        let mut bytes = vec![0u8; len];
+        self.reader.read_exact(&mut bytes).map_err(io_error)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&bytes);
+        }
+        Ok(bytes)
+    }
+}
+
+impl<'a> Iterator for SnapshotReader<'a> {
+    type Item = Result<(String, Vec<u8>), StateDatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The hasher is taken once the end of the snapshot, or an error, has been reached
+        self.hasher.as_ref()?;
+
+        match self.read_entry() {
+            Ok(entry) => entry.map(Ok),
+            Err(err) => {
+                // Stop reading after an error
+                self.hasher = None;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+fn invalid_snapshot(msg: &str) -> StateDatabaseError {
+    StateDatabaseError::InvalidSnapshot(msg.to_string())
+}
+
+fn io_error(err: io::Error) -> StateDatabaseError {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_snapshot("unexpected end of snapshot"),
+        _ => StateDatabaseError::InvalidSnapshot(format!("{}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut bytes = vec![];
+        {
+            let mut writer = SnapshotWriter::new(&mut bytes, "abcd").unwrap();
+            writer.write_entry("ab0000", b"value1").unwrap();
+            writer.write_entry("ab0001", b"").unwrap();
+            assert_eq!(2, writer.finish().unwrap());
+        }
+
+        let mut input = &bytes[..];
+        let reader = SnapshotReader::new(&mut input).unwrap();
+        assert_eq!("abcd", reader.merkle_root());
+        let entries = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            vec![
+                ("ab0000".to_string(), b"value1".to_vec()),
+                ("ab0001".to_string(), vec![]),
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn snapshot_corruption() {
+        let mut bytes = vec![];
+        {
+            let mut writer = SnapshotWriter::new(&mut bytes, "abcd").unwrap();
+            writer.write_entry("ab0000", b"value1").unwrap();
+            writer.finish().unwrap();
+        }
+
+        // Modifying the value is detected by the checksum
+        let mut corrupted = bytes.clone();
+        let index = corrupted.len() - CHECKSUM_SIZE - 10;
+        corrupted[index] ^= 0xff;
+        let mut input = &corrupted[..];
+        let result = SnapshotReader::new(&mut input)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>();
+        match result {
+            Err(StateDatabaseError::InvalidSnapshot(_)) => (),
+            res => panic!("Expected InvalidSnapshot, got {:?}", res),
+        }
+
+        // A truncated snapshot is detected
+        let mut input = &bytes[..bytes.len() - 1];
+        let result = SnapshotReader::new(&mut input)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>();
+        match result {
+            Err(StateDatabaseError::InvalidSnapshot(_)) => (),
+            res => panic!("Expected InvalidSnapshot, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn snapshot_oversized_fields() {
+        let mut bytes = vec![];
+        {
+            let mut writer = SnapshotWriter::new(&mut bytes, "abcd").unwrap();
+            writer.write_entry("ab0000", b"value1").unwrap();
+            writer.finish().unwrap();
+        }
+        let root_len_index = MAGIC.len() + 1;
+        let address_len_index = root_len_index + 4 + "abcd".len() + 1;
+        let value_len_index = address_len_index + 4 + "ab0000".len();
+
+        // A merkle root longer than the limit is rejected
+        let mut oversized = bytes.clone();
+        oversized[root_len_index..root_len_index + 4]
+            .copy_from_slice(&(MAX_ADDRESS_SIZE as u32 + 1).to_be_bytes());
+        let mut input = &oversized[..];
+        assert_exceeds_limit(SnapshotReader::new(&mut input).map(|_| ()));
+
+        // An address longer than the limit is rejected
+        let mut oversized = bytes.clone();
+        oversized[address_len_index..address_len_index + 4]
+            .copy_from_slice(&(MAX_ADDRESS_SIZE as u32 + 1).to_be_bytes());
+        let mut input = &oversized[..];
+        assert_exceeds_limit(
+            SnapshotReader::new(&mut input)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>(),
+        );
+
+        // A value longer than the limit is rejected
+        let mut oversized = bytes.clone();
+        oversized[value_len_index..value_len_index + 4]
+            .copy_from_slice(&(MAX_VALUE_SIZE as u32 + 1).to_be_bytes());
+        let mut input = &oversized[..];
+        assert_exceeds_limit(
+            SnapshotReader::new(&mut input)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>(),
+        );
+    }
+
+    fn assert_exceeds_limit<T: std::fmt::Debug>(result: Result<T, StateDatabaseError>) {
+        match result {
+            Err(StateDatabaseError::InvalidSnapshot(msg)) => {
+                assert!(msg.ends_with("exceeds the limit"), "{}", msg)
+            }
+            res => panic!(
+                "Expected InvalidSnapshot for an oversized field, got {:?}",
+                res
+            ),
+        }
+    }
+}
@@ -27,16 +27,22 @@ pub mod error;
 pub mod hashmap;
 pub mod merkle;
 mod merkle_error;
+mod merkle_history;
+mod merkle_pruning;
+mod merkle_snapshot;
 
 pub use crate::state::error::{StatePruneError, StateReadError, StateWriteError};
 use std::collections::HashMap;
 
+/// An iterator over entries in state, returned by `Read::iter_prefix`.
+pub type ValueIter<K, V> = Box<dyn Iterator<Item = Result<(K, V), StateReadError>>>;
+
 /// A change to be applied to state, in terms of keys and values.
 ///
 /// A `StateChange` represents the basic level of changes that can be applied to
 /// values in state.  This covers the setting of a key/value pair, or the
 /// deletion of a key.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum StateChange {
     Set { key: String, value: Vec<u8> },
     Delete { key: String },
@@ -173,6 +179,27 @@ pub trait Read: Send + Send {
         keys: &[Self::Key],
     ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError>;
 
+    /// At a given `StateId`, iterate over the keys and values whose keys start with the given
+    /// prefix, in key order.
+    ///
+    /// Every key and value in the state is returned if no prefix is given.
+    ///
+    /// # Errors
+    ///
+    /// `StateReadError` is returned if the `StateId` does not exist, or if any issues occur while
+    /// trying to create the iterator.  Errors which occur while iterating are returned by the
+    /// iterator.  The default implementation returns an error, for states which cannot be
+    /// iterated.
+    fn iter_prefix(
+        &self,
+        _state_id: &Self::StateId,
+        _prefix: Option<&Self::Key>,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        Err(StateReadError::StorageError(
+            "Iterating over keys is not supported by this state".into(),
+        ))
+    }
+
     fn clone_box(&self)
         -> Box<Read<StateId = Self::StateId, Key = Self::Key, Value = Self::Value>>;
 }
@@ -20,6 +20,7 @@ use std::error::Error;
 use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
 use crate::protocol::transaction::{HashMethod, TransactionBuilder, TransactionPair};
 use crate::signing::hash::HashSigner;
+use crate::workload::error::WorkloadError;
 
 const COMMAND_FAMILY_NAME: &str = "command";
 const COMMAND_VERSION: &str = "0.1";
@@ -97,7 +98,7 @@ impl std::fmt::Display for Command {
     }
 }
 
-pub fn make_command_transaction(commands: &[Command]) -> TransactionPair {
+pub fn make_command_transaction(commands: &[Command]) -> Result<TransactionPair, WorkloadError> {
     let signer = HashSigner::new();
     TransactionBuilder::new()
         .with_batcher_public_key(vec![0u8, 0u8, 0u8, 0u8])
@@ -106,28 +107,24 @@ pub fn make_command_transaction(commands: &[Command]) -> TransactionPair {
         .with_inputs(
             commands
                 .iter()
-                .map(|cmd| match cmd {
-                    Command::Set { address, .. } | Command::Delete { address } => {
-                        Some(address.as_bytes().to_vec())
-                    }
+                .filter_map(|cmd| match cmd {
+                    Command::Set { address, .. }
+                    | Command::Delete { address }
+                    | Command::Get { address } => Some(decode_address(address)),
                     _ => None,
                 })
-                .filter(Option::is_some)
-                .map(Option::unwrap)
-                .collect(),
+                .collect::<Result<_, _>>()?,
         )
         .with_outputs(
             commands
                 .iter()
-                .map(|cmd| match cmd {
+                .filter_map(|cmd| match cmd {
                     Command::Set { address, .. } | Command::Delete { address } => {
-                        Some(address.as_bytes().to_vec())
+                        Some(decode_address(address))
                     }
                     _ => None,
                 })
-                .filter(Option::is_some)
-                .map(Option::unwrap)
-                .collect(),
+                .collect::<Result<_, _>>()?,
         )
         .with_payload_hash_method(HashMethod::SHA512)
         .with_payload(
@@ -139,7 +136,13 @@ pub fn make_command_transaction(commands: &[Command]) -> TransactionPair {
                 .into_bytes(),
         )
         .build_pair(&signer)
-        .unwrap()
+        .map_err(WorkloadError::from)
+}
+
+fn decode_address(address: &str) -> Result<Vec<u8>, WorkloadError> {
+    hex::decode(address).map_err(|err| {
+        WorkloadError::InvalidAddress(format!("Command address {} is not hex: {}", address, err))
+    })
 }
 
 fn parse_commands(payload: &[u8]) -> Result<Vec<Command>, ParseCommandError> {
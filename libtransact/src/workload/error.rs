@@ -11,6 +11,9 @@ pub enum WorkloadError {
 
     // Returned when an error occurs while using TransactionBuilder.
     TransactionBuildError(TransactionBuildError),
+
+    // Returned when a workload address is not valid hex.
+    InvalidAddress(String),
 }
 
 impl std::error::Error for WorkloadError {
@@ -18,6 +21,7 @@ impl std::error::Error for WorkloadError {
         match *self {
             WorkloadError::BatchBuildError(ref err) => err.description(),
             WorkloadError::TransactionBuildError(ref err) => err.description(),
+            WorkloadError::InvalidAddress(ref msg) => msg,
         }
     }
 
@@ -25,6 +29,7 @@ impl std::error::Error for WorkloadError {
         match *self {
             WorkloadError::BatchBuildError(ref err) => Some(err),
             WorkloadError::TransactionBuildError(ref err) => Some(err),
+            WorkloadError::InvalidAddress(_) => None,
         }
     }
 }
@@ -38,6 +43,7 @@ impl std::fmt::Display for WorkloadError {
             WorkloadError::TransactionBuildError(ref err) => {
                 write!(f, "TransactionBuildError: {}", err.description())
             }
+            WorkloadError::InvalidAddress(ref msg) => write!(f, "InvalidAddress: {}", msg),
         }
     }
 }